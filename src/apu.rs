@@ -0,0 +1,361 @@
+use std::collections::VecDeque;
+
+/*
+    The sound chip, reduced to what the boot chime needs: channel 1 (a square wave
+    with a volume envelope and a length timer) and the master controls, mixed down
+    to mono samples at SAMPLE_RATE.
+
+        NR10 0xFF10  sweep (kept, not applied)
+        NR11 0xFF11  duty (bits 6-7), length (bits 0-5)
+        NR12 0xFF12  initial volume (bits 4-7), envelope direction (bit 3) and pace (bits 0-2)
+        NR13 0xFF13  period, low 8 bits
+        NR14 0xFF14  trigger (bit 7), length enabled (bit 6), period, high 3 bits
+        NR50 0xFF24  master volume, left (bits 4-6) and right (bits 0-2)
+        NR51 0xFF25  panning, channel 1 is bits 4 (left) and 0 (right)
+        NR52 0xFF26  power (bit 7)
+
+    The registers still read back from memory as they were written. Channels 2-4,
+    the sweep and the wave RAM aren't emulated, so games play with channel 1 only.
+    Samples pile up until the frontend takes them, the oldest being dropped past a
+    second's worth when nothing does.
+*/
+
+pub const SAMPLE_RATE: u32 = 48000;
+const CPU_CLOCK: u64 = 4_194_304;
+
+// the frame sequencer ticks at 512 Hz, the length timer on even steps, the envelope on step 7
+const SEQUENCER_PERIOD: u32 = CPU_CLOCK as u32 / 512;
+
+const MAX_SAMPLES: usize = SAMPLE_RATE as usize;
+
+// loud enough, and leaves room for the other channels someday
+const OUTPUT_VOLUME: f32 = 0.25;
+
+pub const NR10: u16 = 0xFF10;
+pub const NR11: u16 = 0xFF11;
+pub const NR12: u16 = 0xFF12;
+pub const NR13: u16 = 0xFF13;
+pub const NR14: u16 = 0xFF14;
+pub const NR50: u16 = 0xFF24;
+pub const NR51: u16 = 0xFF25;
+pub const NR52: u16 = 0xFF26;
+
+// which of the 8 steps of a period are high, for each duty (12.5%, 25%, 50%, 75%)
+const DUTY_WAVES: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+#[derive(Default)]
+struct SquareChannel {
+    enabled: bool,
+    dac_enabled: bool,
+
+    duty: u8,
+    duty_step: u8,
+    // 11 bits, the wave steps every (2048 - period) * 4 cycles
+    period: u16,
+    period_timer: u32,
+
+    length_enabled: bool,
+    length_counter: u8,
+
+    initial_volume: u8,
+    envelope_increases: bool,
+    envelope_pace: u8,
+    volume: u8,
+    envelope_timer: u8,
+}
+
+pub struct APU {
+    powered: bool,
+    channel1: SquareChannel,
+    master_volume: u8,
+    panning: u8,
+
+    sequencer_step: u8,
+    cycles_to_sequencer: u32,
+    // CPU cycles times SAMPLE_RATE since the last sample, one is due at CPU_CLOCK
+    sample_clock: u64,
+    samples: VecDeque<f32>,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            powered: false,
+            channel1: SquareChannel::default(),
+            master_volume: 0,
+            panning: 0,
+            sequencer_step: 0,
+            cycles_to_sequencer: SEQUENCER_PERIOD,
+            sample_clock: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    // called by MemoryBus after it stored the byte, for 0xFF10-0xFF26
+    pub fn write_register(&mut self, address: u16, byte: u8) {
+        if address == NR52 {
+            let powered = byte & 0x80 != 0;
+            // turning it off clears every register
+            if self.powered && !powered {
+                self.channel1 = SquareChannel::default();
+                self.master_volume = 0;
+                self.panning = 0;
+            }
+            self.powered = powered;
+            return;
+        }
+
+        // while off, only NR52 can be written
+        if !self.powered {
+            return;
+        }
+
+        let channel = &mut self.channel1;
+
+        match address {
+            NR11 => {
+                channel.duty = byte >> 6;
+                channel.length_counter = 64 - (byte & 0x3F);
+            },
+            NR12 => {
+                channel.initial_volume = byte >> 4;
+                channel.envelope_increases = byte & 0x08 != 0;
+                channel.envelope_pace = byte & 0x07;
+                channel.dac_enabled = byte & 0xF8 != 0;
+                if !channel.dac_enabled {
+                    channel.enabled = false;
+                }
+            },
+            NR13 => channel.period = (channel.period & 0x700) | byte as u16,
+            NR14 => {
+                channel.period = (channel.period & 0xFF) | ((byte as u16 & 0x07) << 8);
+                channel.length_enabled = byte & 0x40 != 0;
+                if byte & 0x80 != 0 {
+                    channel.trigger();
+                }
+            },
+            NR50 => self.master_volume = byte,
+            NR51 => self.panning = byte,
+            _ => {},
+        }
+    }
+
+    // whether channel 1 is playing, what NR52's bit 0 would say
+    pub fn channel1_on(&self) -> bool {
+        self.powered && self.channel1.enabled
+    }
+
+    // moves the sound along by `cycles` T-cycles, producing the samples they cover
+    pub fn step(&mut self, cycles: u32) {
+        let mut remaining = cycles;
+
+        while remaining > 0 {
+            let cycles_to_sample = (CPU_CLOCK - self.sample_clock).div_ceil(SAMPLE_RATE as u64) as u32;
+            let chunk = remaining.min(self.cycles_to_sequencer).min(cycles_to_sample.max(1));
+
+            if self.powered {
+                self.channel1.clock(chunk);
+            }
+            remaining -= chunk;
+
+            self.cycles_to_sequencer -= chunk;
+            if self.cycles_to_sequencer == 0 {
+                self.cycles_to_sequencer = SEQUENCER_PERIOD;
+                self.clock_sequencer();
+            }
+
+            self.sample_clock += chunk as u64 * SAMPLE_RATE as u64;
+            if self.sample_clock >= CPU_CLOCK {
+                self.sample_clock -= CPU_CLOCK;
+                self.push_sample();
+            }
+        }
+    }
+
+    // only the window takes them, and only with the `audio` feature
+    #[cfg_attr(not(feature = "audio"), allow(unused))]
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+
+    fn clock_sequencer(&mut self) {
+        if self.powered {
+            if self.sequencer_step.is_multiple_of(2) {
+                self.channel1.clock_length();
+            }
+            if self.sequencer_step == 7 {
+                self.channel1.clock_envelope();
+            }
+        }
+
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+
+    fn push_sample(&mut self) {
+        let mut sample = 0.0;
+
+        if self.channel1_on() {
+            let left = if self.panning & 0x10 != 0 { ((self.master_volume >> 4) & 0x07) + 1 } else { 0 };
+            let right = if self.panning & 0x01 != 0 { (self.master_volume & 0x07) + 1 } else { 0 };
+            // both sides at full volume is 1
+            sample = self.channel1.output() * (left + right) as f32 / 16.0 * OUTPUT_VOLUME;
+        }
+
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+}
+
+impl SquareChannel {
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        if self.length_counter == 0 {
+            self.length_counter = 64;
+        }
+        self.period_timer = self.period_cycles();
+        self.volume = self.initial_volume;
+        self.envelope_timer = self.envelope_pace;
+    }
+
+    fn period_cycles(&self) -> u32 {
+        (2048 - self.period as u32) * 4
+    }
+
+    fn clock(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+
+        while cycles >= self.period_timer {
+            cycles -= self.period_timer;
+            self.period_timer = self.period_cycles();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.period_timer -= cycles;
+    }
+
+    fn clock_length(&mut self) {
+        if self.length_enabled && self.length_counter > 0 {
+            self.length_counter -= 1;
+            if self.length_counter == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        // a pace of 0 leaves the volume alone
+        if self.envelope_pace == 0 {
+            return;
+        }
+
+        self.envelope_timer = self.envelope_timer.saturating_sub(1);
+        if self.envelope_timer == 0 {
+            self.envelope_timer = self.envelope_pace;
+
+            if self.envelope_increases && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.envelope_increases && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    // -1 to 1
+    fn output(&self) -> f32 {
+        let high = DUTY_WAVES[self.duty as usize] & (1 << self.duty_step) != 0;
+        let level = self.volume as f32 / 15.0;
+
+        if high { level } else { -level }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CYCLES_PER_FRAME;
+
+    fn playing_apu() -> APU {
+        let mut apu = APU::new();
+        apu.write_register(NR52, 0x80);
+        apu.write_register(NR50, 0x77);
+        apu.write_register(NR51, 0xF3);
+        apu.write_register(NR11, 0x80);
+        apu.write_register(NR12, 0xF3);
+        apu.write_register(NR13, 0x83);
+        apu.write_register(NR14, 0x87);
+        apu
+    }
+
+    fn loudest(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |loudest, sample| sample.abs().max(loudest))
+    }
+
+    #[test]
+    fn makes_a_sample_rate_worth_of_samples() {
+        let mut apu = APU::new();
+        // under a second, past that the oldest ones are dropped
+        for _ in 0..30 {
+            apu.step(CYCLES_PER_FRAME);
+        }
+
+        let expected = CYCLES_PER_FRAME as u64 * 30 * SAMPLE_RATE as u64 / CPU_CLOCK;
+        assert_eq!(apu.take_samples().len() as u64, expected);
+        // silent while off
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn trigger_plays_a_square_wave() {
+        let mut apu = playing_apu();
+        assert!(apu.channel1_on());
+
+        apu.step(CYCLES_PER_FRAME);
+        let samples = apu.take_samples();
+
+        // 50% duty, both sides at full volume
+        assert_eq!(loudest(&samples), OUTPUT_VOLUME);
+        assert!(samples.iter().any(|sample| *sample > 0.0));
+        assert!(samples.iter().any(|sample| *sample < 0.0));
+    }
+
+    #[test]
+    fn envelope_fades_the_note_out() {
+        let mut apu = playing_apu();
+
+        // 15 volume steps of 3/64 s, a second is plenty
+        for _ in 0..60 {
+            apu.step(CYCLES_PER_FRAME);
+        }
+        apu.take_samples();
+        apu.step(CYCLES_PER_FRAME);
+
+        assert_eq!(loudest(&apu.take_samples()), 0.0);
+    }
+
+    #[test]
+    fn length_stops_the_channel() {
+        let mut apu = playing_apu();
+        // 64 - 60 = 4 ticks of 1/256 s
+        apu.write_register(NR11, 0x80 | 60);
+        apu.write_register(NR14, 0xC7);
+
+        apu.step(SEQUENCER_PERIOD * 8);
+        assert!(!apu.channel1_on());
+    }
+
+    #[test]
+    fn power_off_silences_and_ignores_writes() {
+        let mut apu = playing_apu();
+        apu.write_register(NR52, 0x00);
+        assert!(!apu.channel1_on());
+
+        apu.write_register(NR12, 0xF3);
+        apu.write_register(NR14, 0x87);
+        assert!(!apu.channel1_on());
+
+        apu.step(CYCLES_PER_FRAME);
+        assert_eq!(loudest(&apu.take_samples()), 0.0);
+    }
+}
@@ -0,0 +1,1122 @@
+use std::{collections::HashMap, error::Error, fmt, fs};
+
+use crate::{disassembler, instructions::{Instruction, JumpTypes, OPCODE_LENGTHS}, registers::{Reg, Reg16}, rom::ROM, utils::log};
+
+/*
+    Assembles RGBDS-style source into a ROM, mostly for writing test programs:
+
+        SECTION "Entry", ROM0[$0100]
+            jp Main
+
+        SECTION "Main", ROM0[$0150]
+        Main:
+            ld a, $12
+        .loop:
+            dec a
+            jr nz, .loop
+            ld [wCounter], a
+            db $DD, "text", 0
+            dw Main
+
+        SECTION "Variables", WRAM0[$C000]
+        wCounter: ds 1
+
+    Sections have to be at a fixed address (ROMX ones take a BANK[n], 1 by default),
+    RAM sections only reserve space for labels. Also understood: `DEF name EQU value`,
+    `ds count[, fill]`, local labels, and expressions made of numbers ($hex, %binary,
+    decimal), symbols, @ (the current address), + - ( ) and HIGH()/LOW().
+
+    Instructions are read into the `Instruction` the CPU decodes, and assembled into
+    the opcode that decodes back into it. Whatever the disassembler writes assembles
+    back into the same bytes (the .asm from `disasm --asm` included).
+
+    The CLI:
+        crusty-boy asm <source.asm> <out.gb> [--fix-checksums]
+*/
+
+const BANK_SIZE: usize = 0x4000;
+// MBC5's 512 banks, the most any cartridge has
+const MAX_BANK: i64 = 0x1FF;
+// bank 0 + bank 1, the smallest ROM there is
+const MIN_ROM_SIZE: usize = 0x8000;
+
+// where each section type can go, end excluded
+const MEMORY_AREAS: [(&str, u32, u32); 8] = [
+    ("ROM0", 0x0000, 0x4000),
+    ("ROMX", 0x4000, 0x8000),
+    ("VRAM", 0x8000, 0xA000),
+    ("SRAM", 0xA000, 0xC000),
+    ("WRAM0", 0xC000, 0xD000),
+    ("WRAMX", 0xD000, 0xE000),
+    ("OAM", 0xFE00, 0xFEA0),
+    ("HRAM", 0xFF80, 0xFFFF),
+];
+
+const KEYWORDS: [&str; 21] = [
+    "A", "B", "C", "D", "E", "H", "L", "AF", "BC", "DE", "HL", "SP",
+    "NZ", "Z", "NC", "[HL]", "[HL+]", "[HL-]", "[BC]", "[DE]", "[C]",
+];
+
+// the operations on A where RGBDS lets the `A,` out
+const ACCUMULATOR_OPERATIONS: [&str; 8] = ["ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP"];
+
+const MNEMONICS: [&str; 44] = [
+    "NOP", "HALT", "STOP", "DI", "EI", "DAA", "CPL", "CCF", "SCF", "RLCA", "RRCA", "RLA", "RRA",
+    "JP", "JR", "CALL", "RET", "RETI", "RST", "PUSH", "POP",
+    "ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP", "INC", "DEC",
+    "BIT", "RES", "SET", "RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL", "LD", "LDH",
+];
+
+#[derive(Debug)]
+pub struct AssemblerError {
+    pub line: usize,
+    pub message: String,
+}
+
+// an instruction's operand, as written in the source
+enum Argument {
+    // registers, conditions, [HL+] and the like
+    Keyword(String),
+    Value(String),
+    Memory(String),
+    // the expression keeps its sign: "+$03", "-4"
+    SpOffset(String),
+}
+
+enum Data {
+    Text(Vec<u8>),
+    Expression(String),
+}
+
+enum Item {
+    Instruction { opcode: Vec<u8>, placeholder: Option<&'static str>, expression: Option<String> },
+    Bytes(Vec<Data>),
+    Words(Vec<String>),
+    Fill { count: usize, value: Option<String> },
+}
+
+// an item that puts bytes in the ROM, kept from the first pass for the second
+struct Statement {
+    line: usize,
+    address: u32,
+    offset: usize,
+    size: usize,
+    // the global label before it, for local labels
+    scope: String,
+    item: Item,
+}
+
+#[derive(Clone, Copy)]
+struct Location {
+    address: u32,
+    end: u32,
+    // where `address` is in the ROM, None in RAM
+    offset: Option<usize>,
+}
+
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    statements: Vec<Statement>,
+    location: Option<Location>,
+    scope: String,
+    // the one being read in the first pass
+    line: usize,
+
+    rom: Vec<u8>,
+    written: Vec<bool>,
+    // past the last byte written
+    end: usize,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler {
+            symbols: HashMap::new(),
+            statements: Vec::new(),
+            location: None,
+            scope: String::new(),
+            line: 0,
+            rom: vec![0; MIN_ROM_SIZE],
+            written: vec![false; MIN_ROM_SIZE],
+            end: 0,
+        }
+    }
+
+    /**
+        First pass: defines the symbols and lays out every statement, whose size
+        never depends on a label. Second pass: evaluates the operands and writes.
+     */
+    fn run(&mut self, source: &str) -> Result<(), AssemblerError> {
+        for (index, line) in source.lines().enumerate() {
+            self.line = index + 1;
+            self.read_line(line).map_err(|message| AssemblerError { line: index + 1, message })?;
+        }
+
+        for statement in std::mem::take(&mut self.statements) {
+            let bytes = self.encode(&statement).map_err(|message| AssemblerError { line: statement.line, message })?;
+            self.write(statement.offset, &bytes).map_err(|message| AssemblerError { line: statement.line, message })?;
+        }
+
+        Ok(())
+    }
+
+    fn read_line(&mut self, line: &str) -> Result<(), String> {
+        let mut text = strip_comment(line).trim();
+
+        if let Some((name, rest)) = split_label(text) {
+            self.define_label(name)?;
+            text = rest;
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let (keyword, arguments) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let keyword = keyword.to_ascii_uppercase();
+
+        // DEF name EQU value, or the older name EQU value
+        let definition = match keyword.as_str() {
+            "DEF" => Some(arguments),
+            _ => arguments.split_whitespace().next().is_some_and(|word| word.eq_ignore_ascii_case("EQU")).then_some(text),
+        };
+        if let Some(definition) = definition {
+            return self.define_constant(definition);
+        }
+
+        let arguments = split_arguments(arguments);
+
+        match keyword.as_str() {
+            "SECTION" => self.start_section(&arguments),
+            "DB" => {
+                let data = arguments.iter().map(|argument| parse_data(argument)).collect::<Result<Vec<_>, _>>()?;
+                let size = data.iter().map(|data| match data {
+                    Data::Text(bytes) => bytes.len(),
+                    Data::Expression(_) => 1,
+                }).sum();
+                self.add_statement(size, Item::Bytes(data))
+            },
+            "DW" => self.add_statement(arguments.len() * 2, Item::Words(arguments)),
+            "DS" => {
+                let Some(count) = arguments.first() else {
+                    return Err(String::from("ds needs a size"));
+                };
+                let count = usize::try_from(self.constant(count)?).map_err(|_| String::from("ds can't have a negative size"))?;
+
+                match self.location {
+                    // RAM only has room made for labels
+                    Some(Location { offset: None, .. }) => self.advance(count),
+                    _ => self.add_statement(count, Item::Fill { count, value: arguments.get(1).cloned() }),
+                }
+            },
+            _ => {
+                let instruction = self.match_instruction(&keyword, &arguments)?;
+                let size = match &instruction {
+                    Item::Instruction { opcode, .. } if opcode[0] == 0xCB => 2,
+                    Item::Instruction { opcode, .. } => OPCODE_LENGTHS[opcode[0] as usize] as usize,
+                    _ => 0,
+                };
+                self.add_statement(size, instruction)
+            },
+        }
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        let location = self.location.ok_or(format!("{name} isn't in a SECTION"))?;
+
+        let name = match name.strip_prefix('.') {
+            Some(_) if self.scope.is_empty() => return Err(format!("local label {name} has no global label before it")),
+            Some(_) => format!("{}{name}", self.scope),
+            None => {
+                if !name.contains('.') {
+                    self.scope = name.to_string();
+                }
+                name.to_string()
+            },
+        };
+
+        self.define(name, location.address as i64)
+    }
+
+    // "name EQU value"
+    fn define_constant(&mut self, definition: &str) -> Result<(), String> {
+        let mut words = definition.trim().splitn(3, char::is_whitespace);
+        let (Some(name), Some(equ), Some(value)) = (words.next(), words.next(), words.next()) else {
+            return Err(String::from("expected DEF name EQU value"));
+        };
+        if !equ.eq_ignore_ascii_case("EQU") {
+            return Err(format!("only EQU constants are supported, not {equ}"));
+        }
+
+        let value = self.constant(value)?;
+        self.define(name.to_string(), value)
+    }
+
+    fn define(&mut self, name: String, value: i64) -> Result<(), String> {
+        if self.symbols.contains_key(&name) {
+            return Err(format!("{name} is already defined"));
+        }
+
+        self.symbols.insert(name, value);
+        Ok(())
+    }
+
+    // SECTION "name", ROMX[$4000], BANK[2]
+    fn start_section(&mut self, arguments: &[String]) -> Result<(), String> {
+        let [name, kind, bank @ ..] = arguments else {
+            return Err(String::from("SECTION needs a name and a type"));
+        };
+        if name.len() < 2 || !name.starts_with('"') || !name.ends_with('"') {
+            return Err(String::from("the section's name has to be quoted"));
+        }
+
+        let (kind, address) = split_bracket(kind);
+        let address = address.ok_or(format!("the section needs a fixed address, like {kind}[$0150]"))?;
+        let address = self.constant(address)?;
+
+        let bank = match bank {
+            [] => None,
+            [bank] => match split_bracket(bank) {
+                (word, Some(bank)) if word.eq_ignore_ascii_case("BANK") => Some(self.constant(bank)?),
+                _ => return Err(format!("expected BANK[n], not {bank}")),
+            },
+            _ => return Err(String::from("too many arguments to SECTION")),
+        };
+
+        let &(kind, start, end) = MEMORY_AREAS.iter()
+            .find(|(area, _, _)| area.eq_ignore_ascii_case(kind))
+            .ok_or(format!("unknown section type {kind}"))?;
+        if !(start as i64..end as i64).contains(&address) {
+            return Err(format!("{kind} sections go in ${start:04X}-${:04X}", end - 1));
+        }
+        let address = address as u32;
+
+        let offset = match (kind, bank) {
+            ("ROM0", _) => Some(address as usize),
+            ("ROMX", Some(..=0)) => return Err(String::from("ROMX sections can't be in bank 0")),
+            ("ROMX", Some(bank)) if bank > MAX_BANK => return Err(format!("there's no bank ${bank:X}, the last one is ${MAX_BANK:X}")),
+            ("ROMX", bank) => Some(bank.unwrap_or(1) as usize * BANK_SIZE + (address as usize - BANK_SIZE)),
+            _ => None,
+        };
+
+        self.location = Some(Location { address, end, offset });
+        Ok(())
+    }
+
+    fn add_statement(&mut self, size: usize, item: Item) -> Result<(), String> {
+        let Some(Location { address, offset, .. }) = self.location else {
+            return Err(String::from("code and data have to be in a SECTION"));
+        };
+        let offset = offset.ok_or(String::from("RAM sections can only reserve space (ds)"))?;
+
+        self.statements.push(Statement { line: self.line, address, offset, size, scope: self.scope.clone(), item });
+        self.advance(size)
+    }
+
+    fn advance(&mut self, size: usize) -> Result<(), String> {
+        let location = self.location.as_mut().ok_or(String::from("code and data have to be in a SECTION"))?;
+
+        if location.address as usize + size > location.end as usize {
+            return Err(format!("the section goes past ${:04X}", location.end - 1));
+        }
+
+        location.address += size as u32;
+        location.offset = location.offset.map(|offset| offset + size);
+        Ok(())
+    }
+
+    /**
+        The `Instruction` written in the source, as the opcode that decodes into it,
+        with the placeholder and expression of its operand if it has one.
+     */
+    fn match_instruction(&self, mnemonic: &str, arguments: &[String]) -> Result<Item, String> {
+        let mut mnemonic = mnemonic;
+        let mut arguments: Vec<Argument> = arguments.iter().map(|argument| parse_argument(argument)).collect();
+
+        // the shorthands RGBDS takes too
+        if ACCUMULATOR_OPERATIONS.contains(&mnemonic) && arguments.len() == 1 {
+            arguments.insert(0, Argument::Keyword(String::from("A")));
+        }
+        if mnemonic == "JP" && matches!(&arguments[..], [Argument::Keyword(keyword)] if keyword == "[HL]") {
+            arguments[0] = Argument::Keyword(String::from("HL"));
+        }
+        if mnemonic == "LD" && arguments.iter().any(|argument| matches!(argument, Argument::Keyword(keyword) if keyword == "[C]")) {
+            mnemonic = "LDH";
+        }
+
+        if !MNEMONICS.contains(&mnemonic) {
+            return Err(format!("unknown instruction {mnemonic}"));
+        }
+
+        let (instruction, expression) = self.instruction(mnemonic, &arguments)?;
+        let (opcode, prefixed) = instruction.opcode().ok_or(format!("{mnemonic} doesn't take these operands"))?;
+        let placeholder = disassembler::opcode_template(opcode, prefixed).and_then(|template| disassembler::placeholder(&template));
+
+        Ok(Item::Instruction {
+            opcode: if prefixed { vec![0xCB, opcode] } else { vec![opcode] },
+            placeholder,
+            expression,
+        })
+    }
+
+    // the instruction and the expression of its operand, BIT's and RST's numbers are constants
+    fn instruction(&self, mnemonic: &str, arguments: &[Argument]) -> Result<(Instruction, Option<String>), String> {
+        let mismatch = || format!("{mnemonic} doesn't take these operands");
+        let mut expression = None;
+
+        let instruction = match (mnemonic, arguments) {
+            ("NOP", []) => Instruction::NOP,
+            ("HALT", []) => Instruction::HALT,
+            ("STOP", []) => Instruction::STOP,
+            ("DI", []) => Instruction::DI,
+            ("EI", []) => Instruction::EI,
+            ("DAA", []) => Instruction::DAA,
+            ("CPL", []) => Instruction::CPL,
+            ("CCF", []) => Instruction::CCF,
+            ("SCF", []) => Instruction::SCF,
+            ("RLCA", []) => Instruction::RLCA,
+            ("RRCA", []) => Instruction::RRCA,
+            ("RLA", []) => Instruction::RLA,
+            ("RRA", []) => Instruction::RRA,
+            ("RETI", []) => Instruction::RETI,
+            ("RET", []) => Instruction::RET(JumpTypes::Always),
+            ("RET", [condition]) => Instruction::RET(jump_type(condition).ok_or_else(mismatch)?),
+
+            ("JP", [Argument::Keyword(keyword)]) if keyword == "HL" => Instruction::JPHL,
+            ("JP" | "JR" | "CALL", [.., Argument::Value(target)]) => {
+                let jump_type = match arguments {
+                    [_] => JumpTypes::Always,
+                    [condition, _] => jump_type(condition).ok_or_else(mismatch)?,
+                    _ => return Err(mismatch()),
+                };
+                expression = Some(target.clone());
+
+                match mnemonic {
+                    "JP" => Instruction::JP(jump_type),
+                    "JR" => Instruction::JR(jump_type),
+                    _ => Instruction::CALL(jump_type),
+                }
+            },
+            ("RST", [Argument::Value(vector)]) => {
+                let vector = self.constant(vector)?;
+                if !(0..=0x38).contains(&vector) || vector % 8 != 0 {
+                    return Err(format!("${vector:X} isn't an RST vector"));
+                }
+                Instruction::RST(vector as u8)
+            },
+
+            ("PUSH", [pair]) => Instruction::PUSH(register_pair(pair).ok_or_else(mismatch)?),
+            ("POP", [pair]) => Instruction::POP(register_pair(pair).ok_or_else(mismatch)?),
+
+            ("ADD", [Argument::Keyword(keyword), pair]) if keyword == "HL" => Instruction::ADDHL(register_pair(pair).ok_or_else(mismatch)?),
+            ("ADD", [Argument::Keyword(keyword), Argument::Value(offset)]) if keyword == "SP" => {
+                expression = Some(offset.clone());
+                Instruction::ADDSP
+            },
+            (_, [Argument::Keyword(keyword), source]) if ACCUMULATOR_OPERATIONS.contains(&mnemonic) && keyword == "A" => {
+                let (source, source_expression) = operand(source, Reg::D8).ok_or_else(mismatch)?;
+                expression = source_expression;
+
+                match mnemonic {
+                    "ADD" => Instruction::ADD(source),
+                    "ADC" => Instruction::ADC(source),
+                    "SUB" => Instruction::SUB(source),
+                    "SBC" => Instruction::SBC(source),
+                    "AND" => Instruction::AND(source),
+                    "XOR" => Instruction::XOR(source),
+                    "OR" => Instruction::OR(source),
+                    _ => Instruction::CP(source),
+                }
+            },
+
+            ("INC", [target]) => Instruction::INC(register(target).ok_or_else(mismatch)?),
+            ("DEC", [target]) => Instruction::DEC(register(target).ok_or_else(mismatch)?),
+
+            ("BIT" | "RES" | "SET", [Argument::Value(bit), target]) => {
+                let bit = self.constant(bit)?;
+                if !(0..=7).contains(&bit) {
+                    return Err(format!("there's no bit {bit}"));
+                }
+                let target = register(target).ok_or_else(mismatch)?;
+
+                match mnemonic {
+                    "BIT" => Instruction::BIT(target, bit as u8),
+                    "RES" => Instruction::RESET(target, bit as u8),
+                    _ => Instruction::SET(target, bit as u8),
+                }
+            },
+            ("RLC" | "RRC" | "RL" | "RR" | "SLA" | "SRA" | "SWAP" | "SRL", [target]) => {
+                let target = register(target).ok_or_else(mismatch)?;
+
+                match mnemonic {
+                    "RLC" => Instruction::RLC(target),
+                    "RRC" => Instruction::RRC(target),
+                    "RL" => Instruction::RL(target),
+                    "RR" => Instruction::RR(target),
+                    "SLA" => Instruction::SLA(target),
+                    "SRA" => Instruction::SRA(target),
+                    "SWAP" => Instruction::SWAP(target),
+                    _ => Instruction::SRL(target),
+                }
+            },
+
+            ("LD", [Argument::Keyword(keyword), Argument::SpOffset(offset)]) if keyword == "HL" => {
+                expression = Some(offset.clone());
+                Instruction::LDHLSP
+            },
+            ("LD", [destination, source]) => {
+                let (destination, destination_expression) = operand(destination, Reg::D8).ok_or_else(mismatch)?;
+                // the pairs are loaded with a word
+                let immediate = if matches!(destination, Reg::BC | Reg::DE | Reg::HL | Reg::SP) { Reg::D16 } else { Reg::D8 };
+                let (source, source_expression) = operand(source, immediate).ok_or_else(mismatch)?;
+                expression = destination_expression.or(source_expression);
+
+                Instruction::LD(destination, source)
+            },
+            ("LDH", [destination, source]) => {
+                let (destination, destination_expression) = high_operand(destination).ok_or_else(mismatch)?;
+                let (source, source_expression) = high_operand(source).ok_or_else(mismatch)?;
+                expression = destination_expression.or(source_expression);
+
+                Instruction::LDH(destination, source)
+            },
+
+            _ => return Err(mismatch()),
+        };
+
+        Ok((instruction, expression))
+    }
+
+    fn encode(&self, statement: &Statement) -> Result<Vec<u8>, String> {
+        let evaluate = |expression: &str| self.evaluate(expression, &statement.scope, statement.address);
+
+        let mut bytes = match &statement.item {
+            Item::Instruction { opcode, placeholder, expression } => {
+                let mut bytes = opcode.clone();
+
+                if let (Some(placeholder), Some(expression)) = (placeholder, expression) {
+                    let next_address = (statement.address + statement.size as u32) as i64;
+                    bytes.extend(operand_bytes(placeholder, evaluate(expression)?, next_address)?);
+                }
+                bytes
+            },
+            Item::Bytes(data) => {
+                let mut bytes = Vec::new();
+                for data in data {
+                    match data {
+                        Data::Text(text) => bytes.extend(text),
+                        Data::Expression(expression) => bytes.push(to_byte(evaluate(expression)?)?),
+                    }
+                }
+                bytes
+            },
+            Item::Words(words) => {
+                let mut bytes = Vec::new();
+                for word in words {
+                    bytes.extend(to_word(evaluate(word)?)?.to_le_bytes());
+                }
+                bytes
+            },
+            Item::Fill { count, value } => {
+                let value = match value {
+                    Some(value) => to_byte(evaluate(value)?)?,
+                    None => 0,
+                };
+                vec![value; *count]
+            },
+        };
+
+        // STOP's second byte
+        bytes.resize(statement.size, 0);
+        Ok(bytes)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), String> {
+        let end = offset + bytes.len();
+        if end > self.rom.len() {
+            let size = end.div_ceil(BANK_SIZE) * BANK_SIZE;
+            self.rom.resize(size, 0);
+            self.written.resize(size, false);
+        }
+
+        if self.written[offset..end].iter().any(|written| *written) {
+            return Err(String::from("overlaps with another section"));
+        }
+
+        self.rom[offset..end].copy_from_slice(bytes);
+        self.written[offset..end].fill(true);
+        self.end = self.end.max(end);
+        Ok(())
+    }
+
+    // a value needed during the first pass, which can only use what's defined above it
+    fn constant(&self, expression: &str) -> Result<i64, String> {
+        let address = self.location.map(|location| location.address).unwrap_or(0);
+        self.evaluate(expression, &self.scope, address)
+    }
+
+    fn evaluate(&self, expression: &str, scope: &str, address: u32) -> Result<i64, String> {
+        let mut parser = ExpressionParser { text: expression, position: 0, symbols: &self.symbols, scope, address };
+        let value = parser.sum()?;
+
+        parser.skip_spaces();
+        match parser.text[parser.position..].chars().next() {
+            Some(character) => Err(format!("unexpected '{character}' in {expression}")),
+            None => Ok(value),
+        }
+    }
+}
+
+// sums of terms: numbers, symbols, @, (...), HIGH(...) and LOW(...)
+struct ExpressionParser<'a> {
+    text: &'a str,
+    position: usize,
+    symbols: &'a HashMap<String, i64>,
+    scope: &'a str,
+    address: u32,
+}
+
+impl<'a> ExpressionParser<'a> {
+    fn sum(&mut self) -> Result<i64, String> {
+        let mut value = self.term()?;
+
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some('+') => {
+                    self.position += 1;
+                    value = value.checked_add(self.term()?).ok_or_else(|| self.overflow())?;
+                },
+                Some('-') => {
+                    self.position += 1;
+                    value = value.checked_sub(self.term()?).ok_or_else(|| self.overflow())?;
+                },
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<i64, String> {
+        self.skip_spaces();
+
+        match self.peek() {
+            Some('-') => {
+                self.position += 1;
+                self.term()?.checked_neg().ok_or_else(|| self.overflow())
+            },
+            Some('+') => {
+                self.position += 1;
+                self.term()
+            },
+            Some('(') => {
+                self.position += 1;
+                self.parenthesized()
+            },
+            Some('@') => {
+                self.position += 1;
+                Ok(self.address as i64)
+            },
+            Some('$') => {
+                self.position += 1;
+                self.number(16)
+            },
+            Some('%') => {
+                self.position += 1;
+                self.number(2)
+            },
+            Some(character) if character.is_ascii_digit() => self.number(10),
+            Some(character) if is_symbol_character(character) => {
+                let name = self.take_while(is_symbol_character);
+
+                self.skip_spaces();
+                match (name.to_ascii_uppercase().as_str(), self.peek()) {
+                    ("HIGH", Some('(')) => {
+                        self.position += 1;
+                        Ok((self.parenthesized()? >> 8) & 0xFF)
+                    },
+                    ("LOW", Some('(')) => {
+                        self.position += 1;
+                        Ok(self.parenthesized()? & 0xFF)
+                    },
+                    _ => {
+                        let full_name = if name.starts_with('.') { format!("{}{name}", self.scope) } else { name.to_string() };
+                        self.symbols.get(&full_name).copied().ok_or(format!("unknown symbol {name}"))
+                    },
+                }
+            },
+            Some(character) => Err(format!("unexpected '{character}' in {}", self.text)),
+            None => Err(format!("{} is missing a value", self.text)),
+        }
+    }
+
+    // what follows a '(' that's already been read
+    fn parenthesized(&mut self) -> Result<i64, String> {
+        let value = self.sum()?;
+
+        self.skip_spaces();
+        if self.peek() != Some(')') {
+            return Err(format!("missing ')' in {}", self.text));
+        }
+        self.position += 1;
+        Ok(value)
+    }
+
+    fn number(&mut self, radix: u32) -> Result<i64, String> {
+        let digits = self.take_while(|character| character.is_ascii_alphanumeric() || character == '_');
+        i64::from_str_radix(&digits.replace('_', ""), radix).map_err(|_| format!("{digits} isn't a number"))
+    }
+
+    fn overflow(&self) -> String {
+        format!("{} overflows", self.text)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let text = self.text;
+        let start = self.position;
+        let length = text[start..].find(|character| !predicate(character)).unwrap_or(text.len() - start);
+        self.position += length;
+        &text[start..self.position]
+    }
+
+    fn skip_spaces(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+}
+
+fn is_symbol_character(character: char) -> bool {
+    character.is_ascii_alphanumeric() || character == '_' || character == '.' || character == '#'
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..index],
+            _ => {},
+        }
+    }
+
+    line
+}
+
+// "Main: ld a,b" gives ("Main", "ld a,b"), exported labels (Main::) too
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (name, rest) = text.split_once(':')?;
+
+    if name.is_empty() || !name.chars().all(is_symbol_character) {
+        return None;
+    }
+
+    Some((name, rest.strip_prefix(':').unwrap_or(rest).trim()))
+}
+
+// splits on the commas that aren't in a string, brackets or parentheses
+fn split_arguments(text: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+
+    for character in text.chars() {
+        match character {
+            '"' => in_string = !in_string,
+            '[' | '(' if !in_string => depth += 1,
+            ']' | ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                arguments.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            _ => {},
+        }
+        current.push(character);
+    }
+
+    if !current.trim().is_empty() || !arguments.is_empty() {
+        arguments.push(current.trim().to_string());
+    }
+
+    arguments
+}
+
+// "ROMX[$4000]" gives ("ROMX", Some("$4000"))
+fn split_bracket(text: &str) -> (&str, Option<&str>) {
+    match text.split_once('[') {
+        Some((word, rest)) => (word.trim(), rest.strip_suffix(']')),
+        None => (text.trim(), None),
+    }
+}
+
+fn parse_argument(text: &str) -> Argument {
+    let compact: String = text.chars().filter(|character| !character.is_whitespace()).collect();
+    let keyword = match compact.to_ascii_uppercase().as_str() {
+        "[HLI]" => String::from("[HL+]"),
+        "[HLD]" => String::from("[HL-]"),
+        "[$FF00+C]" => String::from("[C]"),
+        keyword => keyword.to_string(),
+    };
+
+    if KEYWORDS.contains(&keyword.as_str()) {
+        return Argument::Keyword(keyword);
+    }
+    if keyword.starts_with("SP+") || keyword.starts_with("SP-") {
+        return Argument::SpOffset(compact[2..].to_string());
+    }
+
+    match compact.strip_prefix('[').and_then(|inner| inner.strip_suffix(']')) {
+        Some(inner) => Argument::Memory(inner.to_string()),
+        None => Argument::Value(text.trim().to_string()),
+    }
+}
+
+// registers, the pairs and the addresses in a pair ([HL], [HL+]...)
+fn register(argument: &Argument) -> Option<Reg> {
+    let Argument::Keyword(keyword) = argument else {
+        return None;
+    };
+
+    Some(match keyword.as_str() {
+        "A" => Reg::A,
+        "B" => Reg::B,
+        "C" => Reg::C,
+        "D" => Reg::D,
+        "E" => Reg::E,
+        "H" => Reg::H,
+        "L" => Reg::L,
+        "AF" => Reg::AF,
+        "BC" => Reg::BC,
+        "DE" => Reg::DE,
+        "HL" => Reg::HL,
+        "SP" => Reg::SP,
+        "[HL]" => Reg::Addr(Reg16::HL),
+        "[HL+]" => Reg::Addr(Reg16::HLI),
+        "[HL-]" => Reg::Addr(Reg16::HLD),
+        "[BC]" => Reg::Addr(Reg16::BC),
+        "[DE]" => Reg::Addr(Reg16::DE),
+        _ => return None,
+    })
+}
+
+fn register_pair(argument: &Argument) -> Option<Reg16> {
+    match register(argument)? {
+        Reg::Addr(_) => None,
+        register => Reg16::try_from(register).ok(),
+    }
+}
+
+fn jump_type(argument: &Argument) -> Option<JumpTypes> {
+    let Argument::Keyword(keyword) = argument else {
+        return None;
+    };
+
+    Some(match keyword.as_str() {
+        "NZ" => JumpTypes::NotZero,
+        "Z" => JumpTypes::Zero,
+        "NC" => JumpTypes::NotCarry,
+        "C" => JumpTypes::Carry,
+        _ => return None,
+    })
+}
+
+// a register, or `immediate` (D8/D16) or [a16] with its expression
+fn operand(argument: &Argument, immediate: Reg) -> Option<(Reg, Option<String>)> {
+    match argument {
+        Argument::Keyword(_) => Some((register(argument)?, None)),
+        Argument::Value(expression) => Some((immediate, Some(expression.clone()))),
+        Argument::Memory(expression) => Some((Reg::A16, Some(expression.clone()))),
+        Argument::SpOffset(_) => None,
+    }
+}
+
+// LDH's: A, [C] or [a8], which is D8 in `Instruction::LDH`
+fn high_operand(argument: &Argument) -> Option<(Reg, Option<String>)> {
+    match argument {
+        Argument::Keyword(keyword) if keyword == "A" => Some((Reg::A, None)),
+        Argument::Keyword(keyword) if keyword == "[C]" => Some((Reg::C, None)),
+        Argument::Memory(expression) => Some((Reg::D8, Some(expression.clone()))),
+        _ => None,
+    }
+}
+
+fn parse_data(argument: &str) -> Result<Data, String> {
+    let Some(text) = argument.strip_prefix('"') else {
+        return Ok(Data::Expression(argument.to_string()));
+    };
+    let text = text.strip_suffix('"').ok_or(format!("unterminated string {argument}"))?;
+
+    let mut bytes = Vec::new();
+    let mut characters = text.chars();
+    while let Some(character) = characters.next() {
+        let character = match character {
+            '\\' => match characters.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(escaped) => escaped,
+                None => return Err(format!("unterminated string {argument}")),
+            },
+            character => character,
+        };
+
+        let mut buffer = [0; 4];
+        bytes.extend(character.encode_utf8(&mut buffer).bytes());
+    }
+
+    Ok(Data::Text(bytes))
+}
+
+fn operand_bytes(placeholder: &str, value: i64, next_address: i64) -> Result<Vec<u8>, String> {
+    Ok(match placeholder {
+        "n8" => vec![to_byte(value)?],
+        "n16" | "a16" | "j16" => to_word(value)?.to_le_bytes().to_vec(),
+        "a8" => match value {
+            0xFF00..=0xFFFF | 0x00..=0xFF => vec![value as u8],
+            _ => return Err(format!("${value:04X} isn't in $FF00-$FFFF")),
+        },
+        "e8" => {
+            let offset = value - next_address;
+            if !(-128..=127).contains(&offset) {
+                return Err(format!("the jump to ${value:04X} is too far ({offset} bytes)"));
+            }
+            vec![offset as u8]
+        },
+        _ => {
+            // s8
+            if !(-128..=127).contains(&value) {
+                return Err(format!("{value} doesn't fit in a signed byte"));
+            }
+            vec![value as u8]
+        },
+    })
+}
+
+fn to_byte(value: i64) -> Result<u8, String> {
+    match value {
+        -0x80..=0xFF => Ok(value as u8),
+        _ => Err(format!("{value} doesn't fit in a byte")),
+    }
+}
+
+fn to_word(value: i64) -> Result<u16, String> {
+    match value {
+        -0x8000..=0xFFFF => Ok(value as u16),
+        _ => Err(format!("{value} doesn't fit in a word")),
+    }
+}
+
+/**
+    Assembles `source` into a ROM image, padded with zeros to a whole number of
+    banks (two at least), ready for `MemoryBus::load_rom`.
+ */
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    let mut assembler = Assembler::new();
+    assembler.run(source)?;
+    Ok(assembler.rom)
+}
+
+/**
+    Assembles a snippet without any SECTION, as if it were at `address`, and
+    returns just its bytes: `assemble_code("ld a,$12\nret", 0x0150)`.
+ */
+#[allow(unused)]
+pub fn assemble_code(source: &str, address: u16) -> Result<Vec<u8>, AssemblerError> {
+    let mut assembler = Assembler::new();
+    // the ROM is only scratch space here, laid out like memory
+    assembler.location = Some(Location { address: address as u32, end: 0x10000, offset: Some(address as usize) });
+    assembler.run(source)?;
+
+    let end = assembler.end.max(address as usize);
+    Ok(assembler.rom[address as usize..end].to_vec())
+}
+
+
+pub fn main(args: &[String]) -> i32 {
+    let fix_checksums = args.iter().any(|arg| arg == "--fix-checksums");
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    let [source_path, rom_path] = paths[..] else {
+        log("[ASM] usage: crusty-boy asm <source.asm> <out.gb> [--fix-checksums]");
+        return 1;
+    };
+
+    let source = match fs::read_to_string(source_path) {
+        Ok(source) => source,
+        Err(error) => {
+            log(&format!("[ASM] Couldn't read {source_path}: {error}"));
+            return 1;
+        },
+    };
+
+    let mut rom = match assemble(&source) {
+        Ok(rom) => rom,
+        Err(error) => {
+            log(&format!("[ASM] {source_path}, {error}"));
+            return 1;
+        },
+    };
+
+    if fix_checksums {
+        ROM::fix_checksums(&mut rom);
+    }
+
+    match fs::write(rom_path, &rom) {
+        Ok(()) => {
+            log(&format!("[ASM] {} bytes written to {rom_path}", rom.len()));
+            0
+        },
+        Err(error) => {
+            log(&format!("[ASM] Couldn't write {rom_path}: {error}"));
+            1
+        },
+    }
+}
+
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssemblerError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+
+    // the opcode and as much of 0x12, 0x34 as its operand takes
+    fn instruction_bytes(opcode: u8, prefixed: bool) -> Vec<u8> {
+        if prefixed {
+            return vec![0xCB, opcode];
+        }
+        // STOP's second byte isn't an operand, it's written as 0
+        if opcode == 0x10 {
+            return vec![0x10, 0x00];
+        }
+        [opcode, 0x12, 0x34][..OPCODE_LENGTHS[opcode as usize] as usize].to_vec()
+    }
+
+    #[test]
+    fn assembles_every_opcode_template() {
+        for prefixed in [false, true] {
+            for opcode in 0..=0xFF {
+                let Some(template) = disassembler::opcode_template(opcode, prefixed) else {
+                    continue;
+                };
+                if !prefixed && opcode == 0xCB {
+                    continue;
+                }
+
+                let bytes = instruction_bytes(opcode, prefixed);
+                let instruction = disassembler::DisassembledInstruction::decode(&bytes, 0x0150);
+                assert_eq!(instruction.template(), template);
+
+                let text = instruction.to_string();
+                let assembled = assemble_code(&text, 0x0150).unwrap_or_else(|error| panic!("\"{text}\": {error}"));
+                assert_eq!(assembled, bytes, "\"{text}\"");
+            }
+        }
+    }
+
+    #[test]
+    fn disassembly_assembles_back() {
+        let code = [
+            0x31, 0xFE, 0xFF,   // ld sp, $FFFE
+            0x21, 0x00, 0xC0,   // ld hl, $C000
+            0x3E, 0x10,         // ld a, $10
+            0x22,               // ld [hl+], a
+            0x3D,               // dec a
+            0x20, 0xFC,         // jr nz, -4
+            0xCB, 0x37,         // swap a
+            0xE0, 0x80,         // ldh [$FF80], a
+            0xCD, 0x50, 0x01,   // call $0150
+            0xF8, 0xFE,         // ld hl, sp-2
+            0xDD,               // illegal, a db
+            0xC9,               // ret
+        ];
+
+        let source: Vec<String> = disassembler::disassemble(&code, 0x0150).iter().map(ToString::to_string).collect();
+        assert_eq!(assemble_code(&source.join("\n"), 0x0150).unwrap(), code);
+    }
+
+    #[test]
+    fn assembled_rom_runs() {
+        let rom = assemble(r#"
+            SECTION "Entry", ROM0[$0100]
+                jp Main
+
+            SECTION "Main", ROM0[$0150]
+            Main:
+                ld a, $12
+                ld b, a
+                add a, b
+                ld hl, wResult
+                ld [hl+], a
+            .end:
+                jr .end
+
+            SECTION "Variables", WRAM0[$C000]
+            wResult: ds 1
+        "#).unwrap();
+
+        let mut cpu = CPU::new();
+        cpu.mem_bus.load_rom(&rom);
+        cpu.rom_size = rom.len() / 1024;
+        cpu.pc = 0x0100;
+
+        // up to .end, where it loops
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.pc, 0x0158);
+
+        assert_eq!(cpu.regs.a, 0x24);
+        assert_eq!(cpu.mem_bus.read_byte(0xC000), 0x24);
+        assert_eq!(cpu.regs.get_vreg_value(Reg16::HL).0, 0xC001);
+    }
+
+    #[test]
+    fn operands_pick_the_instruction() {
+        let code = assemble_code("inc hl\ninc [hl]\nld [hl], a\nld hl, $C000\nld [hl], $12\nldh a, [c]\nld a, [$FF00+c]\nld [$C000], a", 0x0150).unwrap();
+        assert_eq!(code, [0x23, 0x34, 0x77, 0x21, 0x00, 0xC0, 0x36, 0x12, 0xF2, 0xF2, 0xEA, 0x00, 0xC0]);
+
+        for (source, message) in [
+            ("ld [hl], [hl]", "LD doesn't take these operands"),
+            ("push [hl]", "PUSH doesn't take these operands"),
+            ("jp nz, hl", "JP doesn't take these operands"),
+            ("ldh c, a", "LDH doesn't take these operands"),
+            ("bit 8, a", "there's no bit 8"),
+            ("rst $39", "$39 isn't an RST vector"),
+            ("mov a, b", "unknown instruction MOV"),
+        ] {
+            assert_eq!(assemble_code(source, 0x0150).unwrap_err().message, message, "{source}");
+        }
+    }
+
+    #[test]
+    fn rejects_banks_past_the_last_one() {
+        let rom = assemble("SECTION \"Last\", ROMX[$4000], BANK[$1FF]\n    nop").unwrap();
+        assert_eq!(rom.len(), 0x200 * BANK_SIZE);
+
+        let error = assemble("SECTION \"Huge\", ROMX[$4000], BANK[$FFFFFF]\n    nop").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert!(error.message.contains("no bank $FFFFFF"), "{error}");
+    }
+
+    #[test]
+    fn overflowing_expressions_are_errors() {
+        for expression in ["$7FFFFFFFFFFFFFFF + 1", "-$7FFFFFFFFFFFFFFF - 2", "-(-$7FFFFFFFFFFFFFFF - 1)"] {
+            let error = assemble(&format!("DEF X EQU {expression}")).unwrap_err();
+            assert!(error.message.ends_with("overflows"), "{expression}: {error}");
+        }
+
+        assert_eq!(assemble_code("DEF X EQU $7FFFFFFFFFFFFFFF - 1 - $7FFFFFFFFFFFFF00\ndb X", 0x0150).unwrap(), [0xFE]);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, SampleFormat, SampleRate, Stream};
+
+use crate::{apu::SAMPLE_RATE, utils::log};
+
+/*
+    Plays the APU's samples on the default output device, with the `audio` feature
+    (cargo run --features audio). The window hands over each frame's samples, the
+    device's callback takes them as it needs them and plays silence when it runs out.
+*/
+
+// past this, the emulator is ahead of the device (turbo...), the oldest samples go
+const MAX_QUEUED: usize = SAMPLE_RATE as usize / 10;
+
+pub struct AudioOutput {
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    // playing as long as it's alive
+    _stream: Stream,
+}
+
+impl AudioOutput {
+    // None (after logging why) when there's no device that takes our samples
+    pub fn open() -> Option<Self> {
+        match Self::try_open() {
+            Ok(output) => Some(output),
+            Err(message) => {
+                log(&format!("[AUDIO] No sound: {message}"));
+                None
+            },
+        }
+    }
+
+    fn try_open() -> Result<Self, String> {
+        let device = cpal::default_host().default_output_device().ok_or("no output device")?;
+
+        let config = device.supported_output_configs()
+            .map_err(|error| error.to_string())?
+            .find(|range| range.sample_format() == SampleFormat::F32
+                && (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&SAMPLE_RATE))
+            .ok_or(format!("the output device can't play {SAMPLE_RATE} Hz"))?
+            .with_sample_rate(SampleRate(SAMPLE_RATE))
+            .config();
+
+        let channels = config.channels as usize;
+        let queue: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::new()));
+        let device_queue = Arc::clone(&queue);
+
+        let stream = device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _| {
+                let mut queue = device_queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                // mono, the same sample on every channel
+                for frame in data.chunks_mut(channels) {
+                    frame.fill(queue.pop_front().unwrap_or(0.0));
+                }
+            },
+            |error| log(&format!("[AUDIO] {error}")),
+            None,
+        ).map_err(|error| error.to_string())?;
+
+        stream.play().map_err(|error| error.to_string())?;
+        log(&format!("[AUDIO] Playing at {SAMPLE_RATE} Hz on {}", device.name().unwrap_or_default()));
+
+        Ok(AudioOutput { queue, _stream: stream })
+    }
+
+    pub fn queue(&self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        queue.extend(samples);
+
+        let excess = queue.len().saturating_sub(MAX_QUEUED);
+        queue.drain(..excess);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::{apu::{NR11, NR12, NR13, NR14, NR50, NR51, NR52}, cpu::{CPU, CYCLES_PER_FRAME}, logger::{self, Category}, rom::ROM, memory::{MemoryBus, BGP, LCDC, SCY}, registers::Reg16, utils::log};
+
+/*
+    Replacement for the DMG boot ROM, used when none is provided.
+    Does what the real one does (minus the logo check): copies the cartridge's
+    logo into VRAM, scrolls it down the screen, rings the chime and hands off
+    to the cartridge at 0x0100 with the post-boot register state.
+
+    The chime goes through channel 1's registers at the right time, like the boot
+    ROM does, and the APU (apu.rs) plays it. It's heard with the `audio` feature.
+*/
+
+const LOGO_START: u16 = 0x0104;
+const LOGO_SIZE: u16 = 48;
+
+const LOGO_TILES_START: u16 = 0x8010;   // tile 1, tile 0 stays blank
+const REGISTERED_TILE_START: u16 = 0x8190;  // tile 25
+const REGISTERED_TILE_ID: u8 = 0x19;
+
+const LOGO_TOP_ROW_MAP: u16 = 0x9904;
+const LOGO_BOTTOM_ROW_MAP: u16 = 0x9924;
+const REGISTERED_MAP: u16 = 0x9910;
+
+// the "®" next to the logo, one byte per row
+const REGISTERED_TILE: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+// timings of the original boot ROM: SCY goes from 0x64 to 0 (one pixel every 2 frames),
+// then it waits 0x20 more steps before jumping to the cartridge
+const SCROLL_START: u8 = 0x64;
+const FRAMES_PER_STEP: u32 = 2;
+const IDLE_STEPS: u32 = 0x20;
+
+// the two notes of the chime, played near the end of the scroll
+const FIRST_NOTE_STEP: u32 = 0x62;
+const FIRST_NOTE: u8 = 0x83;
+const SECOND_NOTE_STEP: u32 = 0x64;
+const SECOND_NOTE: u8 = 0xC1;
+
+// I/O registers as the DMG boot ROM leaves them
+const POST_BOOT_IO: [(u16, u8); 32] = [
+    (0xFF00, 0xCF), (0xFF01, 0x00), (0xFF02, 0x7E), (0xFF04, 0xAB),
+    (0xFF05, 0x00), (0xFF06, 0x00), (0xFF07, 0xF8), (0xFF0F, 0xE1),
+    (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF),
+    (0xFF14, 0xBF), (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF19, 0xBF),
+    (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1E, 0xBF),
+    (0xFF20, 0xFF), (0xFF23, 0xBF), (0xFF24, 0x77), (0xFF25, 0xF3),
+    (0xFF26, 0xF1), (0xFF40, 0x91), (0xFF41, 0x85), (0xFF42, 0x00),
+    (0xFF43, 0x00), (0xFF47, 0xFC), (0xFF50, 0x01), (0xFFFF, 0x00),
+];
+
+
+pub struct BootAnimation {
+    frame: u32,
+    step: u32,
+    pub finished: bool,
+}
+
+impl BootAnimation {
+    pub fn new(cpu: &mut CPU) -> Self {
+        let mem_bus = &mut cpu.mem_bus;
+
+        load_logo(mem_bus);
+
+        // audio on, channel 1 ready for the chime
+        mem_bus.write_byte(NR52, 0x80);
+        mem_bus.write_byte(NR11, 0x80);
+        mem_bus.write_byte(NR12, 0xF3);
+        mem_bus.write_byte(NR51, 0xF3);
+        mem_bus.write_byte(NR50, 0x77);
+
+        mem_bus.write_byte(BGP, 0xFC);
+        mem_bus.write_byte(SCY, SCROLL_START);
+        mem_bus.write_byte(LCDC, 0x91);
+
+        BootAnimation {
+            frame: 0,
+            step: 0,
+            finished: false,
+        }
+    }
+
+    /**
+        Advances the animation by one frame. Returns true once it's over,
+        at which point the CPU is ready to run the cartridge.
+     */
+    pub fn step_frame(&mut self, cpu: &mut CPU) -> bool {
+        if self.finished {
+            return true;
+        }
+
+        self.frame += 1;
+        // no CPU running to move the sound along
+        cpu.mem_bus.apu.step(CYCLES_PER_FRAME);
+
+        if !self.frame.is_multiple_of(FRAMES_PER_STEP) {
+            return false;
+        }
+
+        self.step += 1;
+
+        match self.step {
+            FIRST_NOTE_STEP => play_note(&mut cpu.mem_bus, FIRST_NOTE),
+            SECOND_NOTE_STEP => play_note(&mut cpu.mem_bus, SECOND_NOTE),
+            _ => {}
+        }
+
+        let scroll = cpu.mem_bus.read_byte(SCY);
+        if scroll > 0 {
+            cpu.mem_bus.write_byte(SCY, scroll - 1);
+        }
+
+        if self.step >= SCROLL_START as u32 + IDLE_STEPS {
+            apply_post_boot_state(cpu);
+            self.finished = true;
+        }
+
+        self.finished
+    }
+}
+
+
+/**
+    Puts the CPU and I/O registers in the state the DMG boot ROM leaves them in,
+    right before it jumps to 0x0100.
+ */
+pub fn apply_post_boot_state(cpu: &mut CPU) {
+    cpu.regs.set_vreg(Reg16::AF, 0x01B0);
+    cpu.regs.set_vreg(Reg16::BC, 0x0013);
+    cpu.regs.set_vreg(Reg16::DE, 0x00D8);
+    cpu.regs.set_vreg(Reg16::HL, 0x014D);
+    cpu.regs.sp = 0xFFFE;
+    cpu.pc = 0x0100;
+
+    // powering the APU off first ends the chime, and keeps the NR14 write below from starting a note
+    cpu.mem_bus.write_byte(NR52, 0x00);
+
+    // this also unmaps the boot ROM (0xFF50), in case one was loaded but we're skipping it
+    for (address, value) in POST_BOOT_IO {
+        cpu.mem_bus.write_byte(address, value);
+    }
+}
+
+/**
+    A fresh machine with the cartridge inserted, as it is once the boot ROM is done.
+    Always the same, so it's the starting point of movies and headless runs.
+ */
+pub fn power_on(rom: &ROM) -> CPU {
+    let mut cpu = CPU::new();
+
+    cpu.mem_bus.load_rom(&rom.data);
+    cpu.rom_size = rom.size;
+    apply_post_boot_state(&mut cpu);
+
+    cpu
+}
+
+/**
+    Decodes the cartridge's logo the way the boot ROM does: each nibble is one
+    row of 4 pixels, every bit is doubled horizontally and every row vertically,
+    giving 24 tiles of 8x8 (only the low bitplane is used, so color 1).
+ */
+fn load_logo(mem_bus: &mut MemoryBus) {
+    let mut vram_address = LOGO_TILES_START;
+
+    for i in 0..LOGO_SIZE {
+        let byte = mem_bus.read_byte(LOGO_START + i);
+
+        for nibble in [byte >> 4, byte & 0x0F] {
+            let row = double_bits(nibble);
+
+            for _ in 0..2 {
+                mem_bus.write_byte(vram_address, row);
+                vram_address += 2;
+            }
+        }
+    }
+
+    for (i, row) in REGISTERED_TILE.iter().enumerate() {
+        mem_bus.write_byte(REGISTERED_TILE_START + i as u16 * 2, *row);
+    }
+
+    for i in 0..12 {
+        mem_bus.write_byte(LOGO_TOP_ROW_MAP + i, 1 + i as u8);
+        mem_bus.write_byte(LOGO_BOTTOM_ROW_MAP + i, 13 + i as u8);
+    }
+    mem_bus.write_byte(REGISTERED_MAP, REGISTERED_TILE_ID);
+
+    log("[BOOT] Logo loaded into VRAM");
+}
+
+// 0b1011 -> 0b11001111
+fn double_bits(nibble: u8) -> u8 {
+    let mut doubled: u8 = 0;
+
+    for bit_i in 0..4 {
+        if nibble & (1 << bit_i) != 0 {
+            doubled |= 0b11 << (bit_i * 2);
+        }
+    }
+
+    doubled
+}
+
+fn play_note(mem_bus: &mut MemoryBus, frequency_low: u8) {
+    logger::debug(Category::Apu, &format!("[APU] Channel 1 note, NR13 = 0x{frequency_low:02X}"));
+    mem_bus.write_byte(NR13, frequency_low);
+    mem_bus.write_byte(NR14, 0x87);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chime_plays_then_stops_at_the_hand_off() {
+        let mut cpu = CPU::new();
+        let mut animation = BootAnimation::new(&mut cpu);
+
+        // up to just before the first note
+        for _ in 0..FIRST_NOTE_STEP * FRAMES_PER_STEP - 1 {
+            animation.step_frame(&mut cpu);
+        }
+        assert!(cpu.mem_bus.apu.take_samples().iter().all(|sample| *sample == 0.0));
+
+        for _ in 0..FRAMES_PER_STEP {
+            animation.step_frame(&mut cpu);
+        }
+        assert!(cpu.mem_bus.apu.channel1_on());
+        assert!(cpu.mem_bus.apu.take_samples().iter().any(|sample| *sample != 0.0));
+
+        while !animation.step_frame(&mut cpu) {}
+        assert!(!cpu.mem_bus.apu.channel1_on());
+    }
+}
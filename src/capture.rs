@@ -0,0 +1,182 @@
+use std::{fs::{self, File}, io::{self, BufWriter, Write}, path::{Path, PathBuf}};
+
+use time::OffsetDateTime;
+
+use crate::gpu::{Screen, TilePixelValue, LCD_HEIGHT, LCD_WIDTH};
+
+/*
+    Writing frames out of the emulator: PNG screenshots, animated GIFs,
+    and raw video + audio dumps for an external encoder.
+*/
+
+pub const FRAME_RATE: f64 = 59.73;
+
+// the 4 shades, as GIF palette indexes
+const GIF_PALETTE: [u8; 12] = [
+    0xFF, 0xFF, 0xFF,
+    0xAA, 0xAA, 0xAA,
+    0x55, 0x55, 0x55,
+    0x00, 0x00, 0x00,
+];
+
+// raw audio: signed 16-bit little-endian stereo
+pub const AUDIO_SAMPLE_RATE: u32 = 48000;
+const AUDIO_CHANNELS: u32 = 2;
+
+// the screen as RGB bytes, every pixel repeated `scale` times both ways
+pub fn screen_to_rgb(screen: &Screen, scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let mut rgb: Vec<u8> = Vec::with_capacity(LCD_WIDTH * LCD_HEIGHT * scale * scale * 3);
+
+    for y in 0..LCD_HEIGHT * scale {
+        for x in 0..LCD_WIDTH * scale {
+            let color = screen[(y / scale) * LCD_WIDTH + x / scale].to_rgb();
+            rgb.extend_from_slice(&color.to_be_bytes()[1..]);
+        }
+    }
+
+    rgb
+}
+
+pub fn write_png(path: &Path, screen: &Screen, scale: usize) -> io::Result<()> {
+    let scale = scale.max(1);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        (LCD_WIDTH * scale) as u32,
+        (LCD_HEIGHT * scale) as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&screen_to_rgb(screen, scale)).map_err(io::Error::other)?;
+
+    Ok(())
+}
+
+// <dir>/<prefix>_20240131_235959_123.<extension>, in UTC
+pub fn timestamped_path(dir: &Path, prefix: &str, extension: &str) -> PathBuf {
+    let now = OffsetDateTime::now_utc();
+
+    dir.join(format!(
+        "{prefix}_{:04}{:02}{:02}_{:02}{:02}{:02}_{:03}.{extension}",
+        now.year(), now.month() as u8, now.day(),
+        now.hour(), now.minute(), now.second(), now.millisecond()
+    ))
+}
+
+
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    frames: u64,
+    // in hundredths of a second, the GIF's time unit
+    elapsed: u64,
+}
+
+impl GifRecorder {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = gif::Encoder::new(file, LCD_WIDTH as u16, LCD_HEIGHT as u16, &GIF_PALETTE)
+            .map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+
+        Ok(GifRecorder { encoder, frames: 0, elapsed: 0 })
+    }
+
+    pub fn add_frame(&mut self, screen: &Screen) -> io::Result<()> {
+        self.frames += 1;
+
+        // GIF delays can't do 59.73 fps, so they alternate between 1 and 2 to keep in sync
+        let frame_end = (self.frames as f64 * 100.0 / FRAME_RATE).round() as u64;
+        let delay = frame_end - self.elapsed;
+        self.elapsed = frame_end;
+
+        let mut frame = gif::Frame {
+            width: LCD_WIDTH as u16,
+            height: LCD_HEIGHT as u16,
+            buffer: screen.iter().map(|pixel| gif_palette_index(*pixel)).collect(),
+            ..gif::Frame::default()
+        };
+        frame.delay = delay as u16;
+
+        self.encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+
+    // writes the GIF's trailer, returns how many frames were recorded
+    pub fn finish(self) -> io::Result<u64> {
+        self.encoder.into_inner()?.flush()?;
+        Ok(self.frames)
+    }
+}
+
+/**
+    Raw dump: `<name>.rgb` holds the frames (RGB24, 160x144, one after the other)
+    and `<name>.pcm` the audio (s16le, stereo, AUDIO_SAMPLE_RATE), both can be fed to ffmpeg.
+ */
+pub struct RawRecorder {
+    video: BufWriter<File>,
+    audio: BufWriter<File>,
+    frames: u64,
+    audio_samples: u64,
+}
+
+impl RawRecorder {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Ok(RawRecorder {
+            video: BufWriter::new(File::create(path.with_extension("rgb"))?),
+            audio: BufWriter::new(File::create(path.with_extension("pcm"))?),
+            frames: 0,
+            audio_samples: 0,
+        })
+    }
+
+    pub fn add_frame(&mut self, screen: &Screen) -> io::Result<()> {
+        self.video.write_all(&screen_to_rgb(screen, 1))?;
+        self.frames += 1;
+
+        // TODO: the APU's samples aren't recorded yet, so the audio track is silence of the right length
+        let frame_end = (self.frames as f64 * AUDIO_SAMPLE_RATE as f64 / FRAME_RATE).round() as u64;
+        let samples = frame_end - self.audio_samples;
+        self.audio_samples = frame_end;
+        self.audio.write_all(&vec![0; (samples * AUDIO_CHANNELS as u64 * 2) as usize])?;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.video.flush()?;
+        self.audio.flush()?;
+        Ok(self.frames)
+    }
+
+    // how to turn the dump into a video
+    pub fn encoder_hint(path: &Path) -> String {
+        format!(
+            "ffmpeg -f rawvideo -pixel_format rgb24 -video_size {LCD_WIDTH}x{LCD_HEIGHT} -framerate {FRAME_RATE} -i {} \
+            -f s16le -ar {AUDIO_SAMPLE_RATE} -ac {AUDIO_CHANNELS} -i {} out.mp4",
+            path.with_extension("rgb").display(), path.with_extension("pcm").display()
+        )
+    }
+}
+
+fn gif_palette_index(pixel: TilePixelValue) -> u8 {
+    match pixel {
+        TilePixelValue::White => 0,
+        TilePixelValue::LightGray => 1,
+        TilePixelValue::DarkGray => 2,
+        TilePixelValue::Black => 3,
+    }
+}
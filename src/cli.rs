@@ -0,0 +1,66 @@
+use std::{fmt, io};
+
+use crate::{assembler, disassembler, gdb_stub, headless, movie::MovieError, rom::RomError, terminal, trace_diff};
+
+/*
+    The subcommands, `crusty-boy <name> ...` instead of opening the window.
+
+    Each has its own module with a `main` taking the arguments after its name
+    and returning the process' exit code: 0 when everything went as asked, 1
+    otherwise (bad arguments included, after logging why).
+*/
+
+// the arguments after the subcommand's name, gives back the exit code
+type Main = fn(&[String]) -> i32;
+
+pub const SUBCOMMANDS: [(&str, Main); 6] = [
+    ("headless", headless::main),
+    ("terminal", terminal::main),
+    ("disasm", disassembler::main),
+    ("asm", assembler::main),
+    ("trace-diff", trace_diff::main),
+    ("gdb", gdb_stub::main),
+];
+
+// what the subcommands can fail with before or outside of emulating
+#[derive(Debug)]
+pub enum CliError {
+    Io(io::Error),
+    Rom(RomError),
+    Movie(MovieError),
+    InvalidArgument(String),
+    // headless' --inputs file
+    Script { line: usize, message: String },
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Io(error) => write!(f, "{error}"),
+            CliError::Rom(error) => write!(f, "{error}"),
+            CliError::Movie(error) => write!(f, "couldn't play the movie: {error}"),
+            CliError::InvalidArgument(message) => write!(f, "{message}"),
+            CliError::Script { line, message } => write!(f, "input script, line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl From<io::Error> for CliError {
+    fn from(error: io::Error) -> Self {
+        CliError::Io(error)
+    }
+}
+
+impl From<RomError> for CliError {
+    fn from(error: RomError) -> Self {
+        CliError::Rom(error)
+    }
+}
+
+impl From<MovieError> for CliError {
+    fn from(error: MovieError) -> Self {
+        CliError::Movie(error)
+    }
+}
@@ -0,0 +1,131 @@
+use std::{fs, io};
+
+use crate::{filters::Filter, logger::LogSettings, presentation::ScaleMode, utils::log};
+
+/*
+    User settings, read from a plain `key = value` file. Lines starting with '#' are comments.
+    Missing file or keys just mean default values.
+*/
+
+const CONFIG_PATH: &str = "data/config.txt";
+
+pub struct Config {
+    // capture a rewind snapshot every N frames
+    pub rewind_interval: u32,
+    // how many snapshots are kept
+    pub rewind_length: usize,
+    // frames run per displayed frame while turbo is held, 0 for as fast as possible
+    pub turbo_speed: u32,
+    // how many times slower slow motion is
+    pub slow_motion_factor: u32,
+    // window size, in multiples of 160x144
+    pub scale: usize,
+    pub scale_mode: ScaleMode,
+    // there's no way to ask the monitor, so fullscreen is this size
+    pub fullscreen_width: usize,
+    pub fullscreen_height: usize,
+    pub filter: Filter,
+    // blends each frame with the previous one, like the DMG's slow LCD
+    pub ghosting: bool,
+    // where screenshots and recordings go
+    pub capture_dir: String,
+    // levels, file and trace window, see logger.rs
+    pub log: LogSettings,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {
+            rewind_interval: 4,
+            rewind_length: 600,
+            turbo_speed: 0,
+            slow_motion_factor: 2,
+            scale: 3,
+            scale_mode: ScaleMode::Integer,
+            fullscreen_width: 1920,
+            fullscreen_height: 1080,
+            filter: Filter::None,
+            ghosting: false,
+            capture_dir: String::from("data/captures"),
+            log: LogSettings::new(),
+        }
+    }
+
+    pub fn load() -> Self {
+        let mut config = Config::new();
+
+        let Ok(text) = fs::read_to_string(CONFIG_PATH) else {
+            return config;
+        };
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                log(&format!("[CONFIG] Line {}: expected `key = value`", line_index + 1));
+                continue;
+            };
+
+            if let Err(message) = config.set(key.trim(), value.trim()) {
+                log(&format!("[CONFIG] Line {}: {message}", line_index + 1));
+            }
+        }
+
+        config
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "rewind_interval" => self.rewind_interval = parse_value(key, value)?,
+            "rewind_length" => self.rewind_length = parse_value(key, value)?,
+            "turbo_speed" => self.turbo_speed = parse_value(key, value)?,
+            "slow_motion_factor" => self.slow_motion_factor = parse_value(key, value)?,
+            "scale" => self.scale = parse_value(key, value)?,
+            "scale_mode" => self.scale_mode = parse_value(key, value)?,
+            "fullscreen_width" => self.fullscreen_width = parse_value(key, value)?,
+            "fullscreen_height" => self.fullscreen_height = parse_value(key, value)?,
+            "filter" => self.filter = parse_value(key, value)?,
+            "ghosting" => self.ghosting = parse_value(key, value)?,
+            "capture_dir" => self.capture_dir = String::from(value),
+            "log" | "log_path" | "trace_pc" | "trace_after" => self.log.set(key, value)?,
+
+            _ => return Err(format!("unknown setting \"{key}\"")),
+        }
+
+        Ok(())
+    }
+
+    /**
+        Writes a single setting to the config file, replacing its line if it's
+        already there. Everything else in the file (comments included) is kept.
+     */
+    pub fn store(key: &str, value: &str) -> io::Result<()> {
+        let text = fs::read_to_string(CONFIG_PATH).unwrap_or_default();
+        let setting = format!("{key} = {value}");
+
+        let mut replaced = false;
+        let mut lines: Vec<String> = text.lines()
+            .map(|line| match line.split_once('=') {
+                Some((line_key, _)) if !line.trim_start().starts_with('#') && line_key.trim() == key => {
+                    replaced = true;
+                    setting.clone()
+                },
+                _ => String::from(line),
+            })
+            .collect();
+
+        if !replaced {
+            lines.push(setting);
+        }
+
+        fs::write(CONFIG_PATH, lines.join("\n") + "\n")
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value \"{value}\" for \"{key}\""))
+}
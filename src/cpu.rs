@@ -1,12 +1,30 @@
-use std::process::exit;
-use crate::{instructions::{Instruction, JumpTypes}, memory::MemoryBus, registers::*, utils::*};
+use crate::{doctor::{self, DoctorLog}, error::EmuError, history::{InstructionHistory, HISTORY_LENGTH}, instructions::{self, Instruction, JumpTypes}, logger::{self, Category, Level}, memory::MemoryBus, registers::*, symbols::{Symbols, MAPPED_ROM_BANK}, utils::*};
+
+// 4194304 Hz / 59.73 Hz
+pub const CYCLES_PER_FRAME: u32 = 70224;
 
 pub struct CPU {
     pub regs: Registers,
     pub pc: u16,
     pub mem_bus: MemoryBus,
     pub is_halted: bool,
+    // after an illegal opcode or an emulation error, nothing runs until a reset
+    pub is_locked: bool,
     pub rom_size: usize,
+
+    // T-cycles since power-on
+    pub cycles: u64,
+    // whether the last conditional jump/call/ret was taken, it costs more
+    branch_taken: bool,
+
+    // from the ROM's .sym file, for the debugger and the logs
+    pub symbols: Symbols,
+
+    // Gameboy Doctor trace of every instruction, see doctor.rs
+    pub doctor_log: Option<Box<DoctorLog>>,
+
+    // the last instructions run, for crash reports and the debugger
+    pub history: InstructionHistory,
 }
 
 
@@ -19,16 +37,76 @@ impl CPU {
             pc: 0, // TODO: might not be correct as an initial value
             mem_bus: MemoryBus::new(),
             is_halted: false,
+            is_locked: false,
             rom_size: 0,
+            cycles: 0,
+            branch_taken: false,
+            symbols: Symbols::new(),
+            doctor_log: None,
+            history: InstructionHistory::new(),
         }
     }
 
-    pub fn step(&mut self) {
+    /**
+        Runs instructions until a whole frame's worth of cycles went by.
+        The extra cycles of the last instruction are carried over to the next frame.
+        Stops early on an error, the CPU is locked by then and the next call finishes the frame.
+     */
+    pub fn run_frame(&mut self) -> Result<(), EmuError> {
+        let frame_end = (self.cycles / CYCLES_PER_FRAME as u64 + 1) * CYCLES_PER_FRAME as u64;
+
+        while self.cycles < frame_end {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    /**
+        Same as `run_frame`, but asks `should_break` before every instruction.
+        Returns true if it stopped before the end of the frame, in which case the
+        instruction at PC hasn't run yet. Calling it again finishes the frame.
+     */
+    pub fn run_frame_until(&mut self, mut should_break: impl FnMut(&CPU) -> bool) -> Result<bool, EmuError> {
+        let frame_end = (self.cycles / CYCLES_PER_FRAME as u64 + 1) * CYCLES_PER_FRAME as u64;
+
+        while self.cycles < frame_end {
+            if should_break(self) {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+
+        Ok(false)
+    }
+
+    /**
+        Returns how many T-cycles the instruction took. On an error the CPU locks up
+        with PC on the instruction that caused it, and the error is only returned once.
+     */
+    pub fn step(&mut self) -> Result<u32, EmuError> {
         // TODO: I don't think this is correct, nor necessary. HOW WILL WE RE-ENABLE INTERRUPTS????
-        if self.is_halted {
-            return;
+        if self.is_halted || self.is_locked {
+            // the clock keeps ticking while halted, and the sound with it
+            self.cycles += 4;
+            self.mem_bus.apu.step(4);
+            return Ok(4);
+        }
+
+        logger::on_instruction(self.pc);
+
+        // the state the instruction starts from
+        if logger::enabled(Category::Cpu, Level::Trace) {
+            logger::trace(Category::Cpu, &doctor::state_line(self));
+        }
+        if let Some(mut doctor_log) = self.doctor_log.take() {
+            match doctor_log.write_state(self) {
+                Ok(()) => self.doctor_log = Some(doctor_log),
+                Err(error) => log(&format!("[DOCTOR] Trace stopped: {error}")),
+            }
         }
 
+        let start_pc = self.pc;
         let mut instruction_byte = self.mem_bus.read_byte(self.pc);
         let prefixed = instruction_byte == 0xCB;
 
@@ -36,68 +114,103 @@ impl CPU {
             self.pc += 1;
             instruction_byte = self.mem_bus.read_byte(self.pc);
         }
+        self.history.record(start_pc, instruction_byte, prefixed, &self.regs);
 
-        if DEBUG_ENABLED {
-            let log: String = format!(
-                "[0x{:04X}]{}{:?}:0x{instruction_byte:02X}", 
-                self.pc, if prefixed { " (0xCB) " } else { " " },
+        if logger::enabled(Category::Cpu, Level::Trace) {
+            logger::trace(Category::Cpu, &format!(
+                "[0x{:04X}{}]{}{:?}:0x{instruction_byte:02X}",
+                self.pc, self.symbols.describe(self.pc, MAPPED_ROM_BANK).map(|name| format!(" {name}")).unwrap_or_default(),
+                if prefixed { " (0xCB) " } else { " " },
                 if let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed) {instruction} else {Instruction::IDK}
-            );
-            println!("{}", log);
-            debug_logs(&log);
+            ));
         }
 
-        let next_pc = if let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed) {
-            self.execute(instruction)
-        } else {
-            let byte_name = format!("0x{}{:X}", if prefixed { "cb" } else { "" }, instruction_byte);
-            let error_message = format!("'{}' not recognized as an instruction at 0x{:04X}", byte_name, self.pc);
-            panic_log(&error_message);
+        let result = match Instruction::from_byte(instruction_byte, prefixed) {
+            Some(instruction) => self.execute(instruction.clone()).map_err(|error| match error {
+                // registers.rs doesn't know where it was called from
+                EmuError::InvalidOperand(message) => EmuError::InvalidOperand(format!("{message}, in {instruction:?} at 0x{start_pc:04X}")),
+                error => error,
+            }),
+            // every other opcode decodes, even the ones execute doesn't emulate yet
+            None => Err(EmuError::IllegalOpcode { opcode: instruction_byte, address: start_pc }),
         };
 
-        if DEBUG_ENABLED {
-            let log: String = format!(
-                "A:{:08b}|B:{:08b}|C:{:08b}|D:{:08b}|E:{:08b}|F:{:08b}|H:{:08b}|L:{:08b}\n\
-                SP:{:08b}|BC:{:016b}|DE:{:016b}|HL:{:016b}|AF:{:016b}\n",
-                self.regs.a, self.regs.b, self.regs.c, self.regs.d, self.regs.e, self.regs.flags.into_u8(), self.regs.h, self.regs.l,
-                self.regs.sp, self.regs.get_vreg_value(Reg16::BC).0, self.regs.get_vreg_value(Reg16::DE).0, 
-                self.regs.get_vreg_value(Reg16::HL).0, self.regs.get_vreg_value(Reg16::AF).0
-            );
-            
-            println!("{log}");
-            debug_logs(&log);
-        }
+        self.pc = match result {
+            Ok(next_pc) => next_pc,
+            Err(error) => {
+                self.pc = start_pc;
+                self.is_locked = true;
+                return Err(error);
+            },
+        };
 
-        self.pc = next_pc;
+        let cycles = if prefixed {
+            instructions::prefixed_opcode_cycles(instruction_byte)
+        } else if self.branch_taken {
+            instructions::OPCODE_CYCLES[instruction_byte as usize] + instructions::branch_taken_extra_cycles(instruction_byte)
+        } else {
+            instructions::OPCODE_CYCLES[instruction_byte as usize]
+        } as u32;
+        self.branch_taken = false;
+        self.cycles += cycles as u64;
+        self.mem_bus.apu.step(cycles);
 
         if self.pc as usize >= (self.rom_size * 1024) {
-            log("SOP");
+            logger::warn(Category::Cpu, &format!("[CPU] PC left the ROM (0x{:04X}), halting", self.pc));
             self.is_halted = true;
         }
 
         // delay(10);
+        Ok(cycles)
     }
 
-    pub fn execute(&mut self, instruction: Instruction) -> u16 {
-        if self.is_halted {
-            return self.pc; // ig that's correct? cuz we not advancing!
+    // for the frontends, what went wrong and how we got there
+    pub fn report_error(&self, error: &EmuError) {
+        logger::error(Category::Cpu, &format!("[CPU] {error}"));
+        self.log_history();
+    }
+
+    // the whole instruction history, to the log
+    pub fn log_history(&self) {
+        logger::error(Category::Cpu, &format!("[CPU] Last {} instructions (oldest first):", self.history.len()));
+        for line in self.history.lines(HISTORY_LENGTH, &self.symbols) {
+            logger::error(Category::Cpu, &format!("  {line}"));
         }
+    }
 
-        match instruction.clone() {
-            // JUMPS!!!11!!!1!1!
+    pub fn execute(&mut self, instruction: Instruction) -> Result<u16, EmuError> {
+        if self.is_halted || self.is_locked {
+            return Ok(self.pc); // ig that's correct? cuz we not advancing!
+        }
 
-            Instruction::JP(jump_type) | Instruction::CALL(jump_type) | 
-            Instruction::RET(jump_type) | Instruction::JR(jump_type ) => {
-                let jump_condition = self.get_jump_condition(jump_type).0;
+        match instruction.clone() {
+            // decoded, but not emulated yet
+            Instruction::ADDSP | Instruction::DAA | Instruction::JPHL | Instruction::RETI | Instruction::RST(_)
+            | Instruction::LDHLSP | Instruction::LDH(..) | Instruction::DI | Instruction::EI | Instruction::STOP
+            | Instruction::LD(Reg::SP | Reg::A16, _) | Instruction::LD(_, Reg::A16)
+            | Instruction::ADC(Reg::D8) | Instruction::SUB(Reg::D8) | Instruction::SBC(Reg::D8) | Instruction::AND(Reg::D8)
+            | Instruction::OR(Reg::D8) | Instruction::XOR(Reg::D8) | Instruction::CP(Reg::D8) => {
+                let (opcode, prefixed) = instruction.opcode().unwrap_or_default();
+                return Err(EmuError::UnknownOpcode { opcode, prefixed, address: self.pc });
+            },
 
-                return match instruction.clone() {
-                    Instruction::JP(_) => self.jump(jump_condition),
-                    Instruction::JR(_) => self.relative_jump(jump_condition),
-                    Instruction::CALL(_) => self.call(jump_condition),
-                    Instruction::RET(_) => self.ret(jump_condition),
+            // JUMPS!!!11!!!1!1!
 
-                    _ => unreachable!("WHAT are you?"),
-                }
+            Instruction::JP(jump_type) => {
+                let jump_condition = self.take_branch(jump_type);
+                return Ok(self.jump(jump_condition));
+            },
+            Instruction::JR(jump_type) => {
+                let jump_condition = self.take_branch(jump_type);
+                return Ok(self.relative_jump(jump_condition));
+            },
+            Instruction::CALL(jump_type) => {
+                let jump_condition = self.take_branch(jump_type);
+                return Ok(self.call(jump_condition));
+            },
+            Instruction::RET(jump_type) => {
+                let jump_condition = self.take_branch(jump_type);
+                return Ok(self.ret(jump_condition));
             },
 
             Instruction::LD(dst, src) => {
@@ -107,22 +220,18 @@ impl CPU {
             Instruction::PUSH(target16) => {
                 let value: u16 = self.regs.get_vreg_value(target16).0;
                 self.push(value);
-                return self.pc.wrapping_add(1);
+                return Ok(self.pc.wrapping_add(1));
             },
 
             Instruction::POP(target16) => {
                 let value: u16 = self.pop();
-                self.regs.set_vreg(target16.into(), value);
-                return self.pc.wrapping_add(1);
+                self.regs.set_vreg(target16, value);
+                return Ok(self.pc.wrapping_add(1));
             },
 
             // Add 'target' to regA
             Instruction::ADD(target) => {
-                let value_to_add: u8 = if target == Reg::D8 {
-                    self.read_next_byte()
-                } else {
-                    self.regs.get_reg_value(target).0
-                };
+                let value_to_add: u8 = self.read_operand(target)?;
 
 
                 // when adding 16bit reg to 8bit reg, we get
@@ -132,11 +241,7 @@ impl CPU {
 
             Instruction::ADC(target) => {
                 // TODO: Should the new value REALLY be 8bit? We're storing it in Reg::A which is 8bit, but idk...
-                let new_value: u8 = if target == Reg::HL {
-                    self.gb_add16(self.regs.a as u16, self.regs.get_vreg_value(Reg16::HL).0) as u8
-                } else {
-                    self.gb_add(self.regs.a, self.regs.get_reg_value(target).0)
-                };
+                let new_value: u8 = self.gb_add(self.regs.a, self.read_operand(target)?);
 
                 let carry = self.regs.flags.carry as u8;
                 let (final_value, final_overflow) = new_value.overflowing_add(carry);
@@ -147,7 +252,7 @@ impl CPU {
 
             // Add 'target' to regHL
             Instruction::ADDHL(target) => {
-                let add_num = self.regs.get_vreg_value(target.into()).0;
+                let add_num = self.regs.get_vreg_value(target).0;
                 let hl = self.regs.get_vreg_value(Reg16::HL).0;
                 let sum = self.gb_add16(hl, add_num);
 
@@ -156,12 +261,12 @@ impl CPU {
 
             // Subtract 'target' from regA
             Instruction::SUB(target) => {
-                let subtraction = self.gb_sub(self.regs.a, self.regs.get_reg_value(target).0);
+                let subtraction = self.gb_sub(self.regs.a, self.read_operand(target)?);
                 self.regs.a = subtraction;
             },
 
             Instruction::SBC(target) => {
-                let new_value = self.gb_sub(self.regs.a, self.regs.get_reg_value(target).0);
+                let new_value = self.gb_sub(self.regs.a, self.read_operand(target)?);
                 let carry = self.regs.flags.carry as u8;
                 let (final_value, final_overflow) = new_value.overflowing_sub(carry);
 
@@ -170,7 +275,7 @@ impl CPU {
             },
 
             Instruction::AND(target) => {
-                let reg = self.regs.get_reg_value(target).0;
+                let reg = self.read_operand(target)?;
                 self.regs.a &= reg;
 
                 self.regs.flags.zero = self.regs.a == 0;
@@ -181,7 +286,7 @@ impl CPU {
             },
 
             Instruction::OR(target) => {
-                let reg = self.regs.get_reg_value(target).0;
+                let reg = self.read_operand(target)?;
                 self.regs.a |= reg;
 
                 self.regs.flags.zero = self.regs.a == 0;
@@ -191,7 +296,7 @@ impl CPU {
             },
 
             Instruction::CP(target) => {
-                let reg_value = self.regs.get_reg_value(target).0;
+                let reg_value = self.read_operand(target)?;
                 let diff = self.gb_sub(self.regs.a, reg_value);
 
                 self.regs.flags.zero = diff == 0;
@@ -201,7 +306,7 @@ impl CPU {
             },
 
             Instruction::XOR(target) => {
-                let reg = self.regs.get_reg_value(target).0;
+                let reg = self.read_operand(target)?;
                 self.regs.a ^= reg;
 
                 self.regs.flags.zero = self.regs.a == 0;
@@ -212,11 +317,11 @@ impl CPU {
 
             Instruction::INC(target) => {
                 // if target is 16bit
-                self.inc(target);
+                self.inc(target)?;
             },
 
             Instruction::DEC(target) => {
-                self.dev(target);
+                self.dev(target)?;
             },
 
             Instruction::CCF => {
@@ -288,11 +393,9 @@ impl CPU {
             },
 
             Instruction::BIT(target, bit_pos) => {
-                if bit_pos > 7 {
-                    panic_log("Invalid bit position! (b > 7)");
-                }
+                check_bit_position(bit_pos)?;
 
-                let reg_value = self.regs.get_reg_value(target).0;
+                let reg_value = self.read_operand(target)?;
                 let result = (reg_value & (1 << bit_pos)) == 0;
 
                 self.regs.flags.zero = result;
@@ -301,34 +404,30 @@ impl CPU {
             },
 
             Instruction::RESET(target, bit_pos) => {
-                if bit_pos > 7 {
-                    panic_log("Invalid bit position! (b > 7)");
-                }
-
-                // TODO: implement for 16bit regs
+                check_bit_position(bit_pos)?;
 
-                let reg = self.regs.get_reg(target).0;
-                *reg &= !(1 << bit_pos);
+                let value = self.read_operand(target)?;
+                self.write_operand(target, value & !(1 << bit_pos))?;
             },
 
             Instruction::SET(target, bit_pos) => {
-                self.set(target, bit_pos);
+                self.set(target, bit_pos)?;
             },
 
             Instruction::SRL(target) => {
-                let reg = self.regs.get_reg(target).0;
-                *reg = *reg >> 1;
+                let value = self.read_operand(target)?;
+                self.write_operand(target, value >> 1)?;
             },
 
             Instruction::RR(target) => {
                 let old_carry = self.regs.flags.carry as u8;
-                let reg = self.regs.get_reg(target).0;
-                let new_carry = (*reg & 0x1) != 0;
-                let new_reg = (old_carry << 7) | (*reg >> 1);
+                let value = self.read_operand(target)?;
+                let new_carry = (value & 0x1) != 0;
+                let new_reg = (old_carry << 7) | (value >> 1);
                 
-                *reg = new_reg;
+                self.write_operand(target, new_reg)?;
 
-                self.regs.flags.zero = *reg == 0;
+                self.regs.flags.zero = new_reg == 0;
                 self.regs.flags.subtract = false;
                 self.regs.flags.half_carry = false;
                 self.regs.flags.carry = new_carry;
@@ -336,66 +435,66 @@ impl CPU {
 
             Instruction::RL(target) => {
                 let old_carry = self.regs.flags.carry as u8;
-                let reg = self.regs.get_reg(target).0;
-                let new_carry = (*reg & 0b10000000) != 0;
-                let new_reg = (*reg << 1) | (old_carry & 0x1);
+                let value = self.read_operand(target)?;
+                let new_carry = (value & 0b10000000) != 0;
+                let new_reg = (value << 1) | (old_carry & 0x1);
 
-                *reg = new_reg;
+                self.write_operand(target, new_reg)?;
 
-                self.regs.flags.zero = *reg == 0;
+                self.regs.flags.zero = new_reg == 0;
                 self.regs.flags.subtract = false;
                 self.regs.flags.half_carry = false;
                 self.regs.flags.carry = new_carry;
             },
 
             Instruction::RRC(target) => {
-                let reg = self.regs.get_reg(target).0;
-                let new_carry = (*reg & 0x1) != 0;
-                let new_reg = ((new_carry as u8) << 7) | (*reg >> 1);
+                let value = self.read_operand(target)?;
+                let new_carry = (value & 0x1) != 0;
+                let new_reg = ((new_carry as u8) << 7) | (value >> 1);
 
-                *reg = new_reg;
+                self.write_operand(target, new_reg)?;
 
-                self.regs.flags.zero = *reg == 0;
+                self.regs.flags.zero = new_reg == 0;
                 self.regs.flags.subtract = false;
                 self.regs.flags.half_carry = false;
                 self.regs.flags.carry = new_carry;
             },
 
             Instruction::RLC(target) => {
-                let reg = self.regs.get_reg(target).0;
-                let new_carry = (*reg & 0b10000000) != 0;
-                let new_reg = (*reg << 1) | ((new_carry as u8) & 0x1);
+                let value = self.read_operand(target)?;
+                let new_carry = (value & 0b10000000) != 0;
+                let new_reg = (value << 1) | ((new_carry as u8) & 0x1);
 
-                *reg = new_reg;
+                self.write_operand(target, new_reg)?;
 
-                self.regs.flags.zero = *reg == 0;
+                self.regs.flags.zero = new_reg == 0;
                 self.regs.flags.subtract = false;
                 self.regs.flags.half_carry = false;
                 self.regs.flags.carry = new_carry;
             },
 
             Instruction::SRA(target) => {
-                let reg = self.regs.get_reg(target).0;
-                let old_msb = *reg & (1 << 7); // sign bit
-                let new_value = *reg >> 1;
-                let new_carry = (*reg & 0x1) != 0;
+                let value = self.read_operand(target)?;
+                let old_msb = value & (1 << 7); // sign bit
+                let new_value = old_msb | (value >> 1);
+                let new_carry = (value & 0x1) != 0;
 
-                *reg = old_msb | new_value;
+                self.write_operand(target, new_value)?;
 
-                self.regs.flags.zero = *reg == 0;
+                self.regs.flags.zero = new_value == 0;
                 self.regs.flags.subtract = false;
                 self.regs.flags.half_carry = false;
                 self.regs.flags.carry = new_carry;
             },
 
             Instruction::SLA(target) => {
-                let reg = self.regs.get_reg(target).0;
-                let new_value = *reg << 1;
-                let new_carry = (*reg & (1 << 7)) != 0;
+                let value = self.read_operand(target)?;
+                let new_value = value << 1;
+                let new_carry = (value & (1 << 7)) != 0;
 
-                *reg = new_value;
+                self.write_operand(target, new_value)?;
 
-                self.regs.flags.zero = *reg == 0;
+                self.regs.flags.zero = new_value == 0;
                 self.regs.flags.subtract = false;
                 self.regs.flags.half_carry = false;
                 self.regs.flags.carry = new_carry;
@@ -403,54 +502,46 @@ impl CPU {
 
             Instruction::SWAP(target) => {
                 // swap nibbles
-                let reg = self.regs.get_reg(target).0;
-                let upper = *reg & 0b11110000;
-                let lower = *reg & 0b00001111;
+                let value = self.read_operand(target)?;
+                let upper = value & 0b11110000;
+                let lower = value & 0b00001111;
+                let new_value = (upper >> 4) | (lower << 4);
 
-                *reg = (upper >> 4) | (lower << 4);
+                self.write_operand(target, new_value)?;
 
-                self.regs.flags.zero = *reg == 0;
+                self.regs.flags.zero = new_value == 0;
                 self.regs.flags.subtract = false;
                 self.regs.flags.half_carry = false;
                 self.regs.flags.carry = false;
             },
 
             Instruction::HALT => {
-                log("Halting");
+                logger::debug(Category::Cpu, "[CPU] Halting");
                 self.is_halted = true;
-                return self.pc;
+                return Ok(self.pc);
             },
 
             Instruction::NOP => {
                 // we return early just cuz. even tho when we do nothing, we return pc+1 at the end
-                return self.pc.wrapping_add(1);
+                return Ok(self.pc.wrapping_add(1));
             },
 
 
             // delulu instructions
 
             Instruction::IDK => {
-                log("(bro doesn't know)");
+                logger::debug(Category::Cpu, "(bro doesn't know)");
             },
-
-            Instruction::EXIT => {
-                exit(0);
-            }
-
-            #[allow(unreachable_patterns)]
-            _ => {
-                unreachable!("Unknown Instruction");
-            }
         }
 
-        self.pc.wrapping_add(1)
+        Ok(self.pc.wrapping_add(1))
     }
 
 
 
     // instructions
 
-    pub fn ld(&mut self, src: Reg, dst: Reg) -> u16 {
+    pub fn ld(&mut self, src: Reg, dst: Reg) -> Result<u16, EmuError> {
         let source_value: u16 = match src {
         Reg::D8 => self.read_next_byte().into(),
         Reg::D16 => self.read_next_word(),
@@ -470,23 +561,23 @@ impl CPU {
                 );
             }
 
-            if DEBUG_ENABLED {
-                log(&format!("Loading 0x{byte:04X} from {:04X} into {dst:?}", vreg.0));
+            if logger::enabled(Category::Cpu, Level::Trace) {
+                logger::trace(Category::Cpu, &format!("Loading 0x{byte:04X} from {:04X} into {dst:?}", vreg.0));
             }
 
             byte.into()
         },
         _ => { 
             if self.regs.reg8_can_be_reg16(src) {
-                self.regs.get_vreg_value(src.into()).0
+                self.regs.get_vreg_value(src.try_into()?).0
             } else {
-                self.regs.get_reg_value(src).0.into()
+                self.regs.get_reg_value(src)?.0.into()
             }
         }
         };
 
-        if DEBUG_ENABLED {
-            log(&format!("Source Value: 0x{source_value:04X}"));
+        if logger::enabled(Category::Cpu, Level::Trace) {
+            logger::trace(Category::Cpu, &format!("Source Value: 0x{source_value:04X}"));
         }
 
         if matches!(dst, Reg::HLI | Reg::HLD) {
@@ -501,7 +592,7 @@ impl CPU {
             });
         } else {
             if matches!(dst, Reg::BC | Reg::DE | Reg::HL /*| Reg::SP*/) {
-                self.regs.set_vreg(dst.into(), source_value);
+                self.regs.set_vreg(dst.try_into()?, source_value);
             } else {
                 match dst {
                     Reg::Addr(addr_reg) => {
@@ -515,15 +606,14 @@ impl CPU {
                             self.regs.get_vreg_value(addr_reg).0
                         };
 
-                        if DEBUG_ENABLED {
-                            let address_value = self.mem_bus.read_byte(address);
-                            log(&format!("Writing 0x{:04X} to 0x{address:04X}", address_value));
+                        if logger::enabled(Category::Cpu, Level::Trace) {
+                            logger::trace(Category::Cpu, &format!("Writing 0x{source_value:04X} to 0x{address:04X}"));
                         }
 
                         self.mem_bus.write_byte(address, source_value as u8);
                     },
                     _ => {
-                        let reg = self.regs.get_reg(dst);
+                        let reg = self.regs.get_reg(dst)?;
                         let the_reg: &mut u8 = reg.0;
 
                         *the_reg = source_value as u8;
@@ -532,11 +622,11 @@ impl CPU {
             }
         }
 
-        return match src {
+        return Ok(match src {
             Reg::D8 => self.pc.wrapping_add(2),
             Reg::D16 => self.pc.wrapping_add(3),
             _ => self.pc.wrapping_add(1)
-        };
+        });
     }
 
     pub fn jump(&self, should_jump: bool) -> u16 {
@@ -547,8 +637,8 @@ impl CPU {
             // TODO: undestand little-endian
 
             let addr: u16 = (greater_byte << 8) | smaller_byte;
-            if DEBUG_ENABLED {
-                log(&format!("Jumped to: 0x{:04x}", addr));
+            if logger::enabled(Category::Cpu, Level::Trace) {
+                logger::trace(Category::Cpu, &format!("Jumped to: 0x{:04x}", addr));
             }
             addr
         } else {
@@ -563,7 +653,9 @@ impl CPU {
 
             let new_pc = self.pc.wrapping_add(relative as i16 as u16);
 
-            log(&format!("Jump to: 0x{new_pc:04X}"));
+            if logger::enabled(Category::Cpu, Level::Trace) {
+                logger::trace(Category::Cpu, &format!("Jump to: 0x{new_pc:04X}"));
+            }
 
             new_pc
         } else {
@@ -574,8 +666,8 @@ impl CPU {
     pub fn call(&mut self, should_jump: bool) -> u16 {
         let next_pc = self.pc.wrapping_add(3);
 
-        if DEBUG_ENABLED {
-            log(&format!("Calling function at 0x{:04X}", next_pc));
+        if logger::enabled(Category::Cpu, Level::Trace) {
+            logger::trace(Category::Cpu, &format!("Calling function at 0x{:04X}", next_pc));
         }
 
         if should_jump {
@@ -589,51 +681,60 @@ impl CPU {
     pub fn ret(&mut self, should_jump: bool) -> u16 {
         if should_jump {
             let return_addr = self.pop();
-            if DEBUG_ENABLED { log(&format!("Returning to address 0x{:04X}", return_addr)); }
+            if logger::enabled(Category::Cpu, Level::Trace) { logger::trace(Category::Cpu, &format!("Returning to address 0x{:04X}", return_addr)); }
             return_addr
         } else {
             self.pc.wrapping_add(1)
         }
     }
 
-    pub fn inc(&mut self, target: Reg) -> () {
+    pub fn inc(&mut self, target: Reg) -> Result<(), EmuError> {
         if self.regs.reg8_can_be_reg16(target) {
-            let incremented_value = self.gb_add16(self.regs.get_vreg_value(target.into()).0, 1);
-            self.regs.set_vreg(target.into(), incremented_value);
+            let incremented_value = self.gb_add16(self.regs.get_vreg_value(target.try_into()?).0, 1);
+            self.regs.set_vreg(target.try_into()?, incremented_value);
         } else {
-            let (reg_value, target) = self.regs.get_reg_value(target);
-            let new_reg_value = self.gb_add(reg_value, 1);
-            *self.regs.get_reg(target).0 = new_reg_value;
+            let new_value = self.gb_add(self.read_operand(target)?, 1);
+            self.write_operand(target, new_value)?;
         }
+
+        Ok(())
     }
 
-    pub fn dev(&mut self, target: Reg) -> () {
+    pub fn dev(&mut self, target: Reg) -> Result<(), EmuError> {
         if self.regs.reg8_can_be_reg16(target) {
-            let decremented_value = self.gb_sub16(self.regs.get_vreg_value(target.into()).0, 1);
-            self.regs.set_vreg(target.into(), decremented_value);
+            let decremented_value = self.gb_sub16(self.regs.get_vreg_value(target.try_into()?).0, 1);
+            self.regs.set_vreg(target.try_into()?, decremented_value);
         } else {
-            let (reg_value, target) = self.regs.get_reg_value(target);
-            let new_reg_value = self.gb_sub(reg_value, 1);
-            *self.regs.get_reg(target).0 = new_reg_value;
+            let new_value = self.gb_sub(self.read_operand(target)?, 1);
+            self.write_operand(target, new_value)?;
         }
+
+        Ok(())
     }
 
-    pub fn set(&mut self, target: Reg, bit_position: u8) -> () {
-        
-        if bit_position > 7 {
-            panic_log("Invalid bit position! (b > 7)");
-        }
+    pub fn set(&mut self, target: Reg, bit_position: u8) -> Result<(), EmuError> {
+        check_bit_position(bit_position)?;
 
-        if target != Reg::HL && matches!(target, Reg::A | Reg::B | Reg::C | Reg::D | Reg::E | Reg::H | Reg::L | Reg::HL) {
-            let reg_dat = self.regs.get_reg(target);
-            *reg_dat.0 |= 1 << bit_position;
-        } else if target == Reg::HL {
-            let mut hl_byte: u16 = self.regs.get_vreg_value(Reg16::HL).0;
-            hl_byte |= 1 << bit_position;
-            self.regs.set_vreg(Reg16::HL, hl_byte);
-        } else {
-            panic_log("Tried setting registers' bit other than A, B, C, D, E, H, L, HL!");
+        let value = self.read_operand(target)?;
+        self.write_operand(target, value | (1 << bit_position))
+    }
+
+    // an 8-bit operand: a register, the byte at [HL] (or [BC], [DE]) or the immediate byte
+    fn read_operand(&self, target: Reg) -> Result<u8, EmuError> {
+        Ok(match target {
+            Reg::D8 => self.read_next_byte(),
+            Reg::Addr(reg16) => self.mem_bus.read_byte(self.regs.get_vreg_value(reg16).0),
+            _ => self.regs.get_reg_value(target)?.0,
+        })
+    }
+
+    fn write_operand(&mut self, target: Reg, value: u8) -> Result<(), EmuError> {
+        match target {
+            Reg::Addr(reg16) => self.mem_bus.write_byte(self.regs.get_vreg_value(reg16).0, value),
+            _ => *self.regs.get_reg(target)?.0 = value,
         }
+
+        Ok(())
     }
 
 
@@ -693,7 +794,7 @@ impl CPU {
     pub fn pop(&mut self) -> u16 {
         // TODO: maybe shouldn't check?
         if self.regs.sp >= 0xFFFE {
-            if DEBUG_ENABLED { log("Stack is 0xFFFE! Cannot pop anymore!"); }
+            logger::debug(Category::Cpu, "Stack is 0xFFFE! Cannot pop anymore!");
             return 0;
         }
 
@@ -706,6 +807,13 @@ impl CPU {
         (msb << 8) | lsb
     }
 
+    // whether a jump/call/ret is taken, remembered for the cycle count
+    fn take_branch(&mut self, jump_type: JumpTypes) -> bool {
+        let jump_condition = self.get_jump_condition(jump_type).0;
+        self.branch_taken = jump_condition;
+        jump_condition
+    }
+
     fn get_jump_condition(&self, jump_type: JumpTypes) -> (bool, JumpTypes) {
         (match jump_type {
             JumpTypes::NotZero => !self.regs.flags.zero,
@@ -717,13 +825,111 @@ impl CPU {
     }
 
     pub fn read_next_byte(&self) -> u8 {
-        self.mem_bus.memory[(self.pc + 1) as usize]
+        self.mem_bus.memory[self.pc.wrapping_add(1) as usize]
     }
 
     pub fn read_next_word(&self) -> u16 {
-        let lsb = self.mem_bus.memory[(self.pc + 1) as usize] as u16;
-        let msb = self.mem_bus.memory[(self.pc + 2) as usize] as u16;
+        let lsb = self.mem_bus.memory[self.pc.wrapping_add(1) as usize] as u16;
+        let msb = self.mem_bus.memory[self.pc.wrapping_add(2) as usize] as u16;
 
         (msb << 8) | lsb
     }
-}
\ No newline at end of file
+}
+
+fn check_bit_position(bit_position: u8) -> Result<(), EmuError> {
+    if bit_position > 7 {
+        return Err(EmuError::InvalidOperand(format!("Invalid bit position {bit_position}! (b > 7)")));
+    }
+    Ok(())
+}
+
+// a panic unwinding past the CPU is a crash, this is where it came from
+impl Drop for CPU {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.log_history();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn cpu_running(source: &str) -> CPU {
+        let rom = assembler::assemble(source).unwrap();
+        let mut cpu = CPU::new();
+        cpu.mem_bus.load_rom(&rom);
+        cpu.rom_size = rom.len() / 1024;
+        cpu.pc = 0x0150;
+        cpu
+    }
+
+    #[test]
+    fn illegal_opcodes_lock_the_cpu() {
+        for opcode in [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD] {
+            let mut cpu = cpu_running(&format!(r#"
+                SECTION "Main", ROM0[$0150]
+                    ld a, $12
+                    db ${opcode:02X}
+                    ld a, $34
+            "#));
+
+            cpu.step().unwrap();
+            let error = cpu.step().unwrap_err();
+            assert!(matches!(error, EmuError::IllegalOpcode { opcode: illegal, address: 0x0152 } if illegal == opcode));
+            assert!(cpu.is_locked);
+            assert_eq!(cpu.pc, 0x0152);
+
+            // the error comes once, then nothing runs but the clock
+            let cycles = cpu.cycles;
+            for _ in 0..10 {
+                assert_eq!(cpu.step().unwrap(), 4);
+            }
+            assert_eq!((cpu.pc, cpu.regs.a), (0x0152, 0x12));
+            assert_eq!(cpu.cycles, cycles + 40);
+        }
+    }
+
+    #[test]
+    fn a_locked_frame_still_ends() {
+        let mut cpu = cpu_running(r#"
+            SECTION "Main", ROM0[$0150]
+                db $DB
+        "#);
+
+        assert!(cpu.run_frame().is_err());
+        // the rest of the frame goes by without running anything
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.pc, 0x0150);
+        assert!(cpu.cycles >= CYCLES_PER_FRAME as u64);
+    }
+
+    #[test]
+    fn hl_operands_are_the_byte_at_hl() {
+        let mut cpu = cpu_running(r#"
+            SECTION "Main", ROM0[$0150]
+                ld hl, $C000
+                ld [hl], $12
+                inc [hl]
+                set 7, [hl]
+                swap [hl]
+                ld a, [hl]
+                add a, [hl]
+                di
+        "#);
+
+        for _ in 0..7 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.mem_bus.read_byte(0xC000), 0x39);
+        assert_eq!(cpu.regs.a, 0x72);
+        assert_eq!(cpu.regs.get_vreg_value(Reg16::HL).0, 0xC000);
+
+        // decoded, but not emulated yet
+        let error = cpu.step().unwrap_err();
+        assert!(matches!(error, EmuError::UnknownOpcode { opcode: 0xF3, prefixed: false, address: 0x015C }), "{error}");
+    }
+}
@@ -0,0 +1,559 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{cpu::CPU, disassembler::{self, DisassembledInstruction}, history::HISTORY_LENGTH, instructions::OPCODE_LENGTHS, registers::Reg16, symbols::{Symbols, MAPPED_ROM_BANK}, utils::{log, parse_number}, watchpoints::{Watchpoint, Watchpoints}};
+
+/*
+    Interactive debugger, driven from the terminal while the emulation is paused.
+
+    The frontend asks `should_break` before every instruction (only when the debugger
+    has something to stop on, so it costs nothing otherwise), and opens the prompt
+    with `prompt` when it says so.
+
+    Conditions are comparisons joined with &&, on registers (A..L, AF, BC, DE, HL, SP, PC),
+    flags (ZF, NF, HF, CF), memory ([$C000], [HL]) and numbers ($FF, 0xFF, 255):
+        b $0150 if A == $3C && !ZF
+
+    With a .sym file loaded, addresses can be given as labels too:
+        b Main.loop if [wPlayerX] > $80
+*/
+
+const DISASSEMBLY_LINES: usize = 8;
+const STACK_LINES: usize = 8;
+// of the instruction history, shown when we stop
+const HISTORY_LINES: usize = 8;
+
+const REGISTER_NAMES: [&str; 18] = [
+    "A", "F", "B", "C", "D", "E", "H", "L", "AF", "BC", "DE", "HL", "SP", "PC", "ZF", "NF", "HF", "CF",
+];
+
+const HELP: &str = "\
+Commands:
+  c, continue               resume
+  s, step                   run one instruction
+  n, next                   step over calls
+  o, out                    run until the current function returns
+  u, until <addr>           run to an address
+  b, break <addr> [if <condition>]
+  d, delete <index>         remove a breakpoint
+  bl, breakpoints           list the breakpoints
+  w, watch <addr>[-<end>] [r|w|c|=<value>] [log]
+                            stop (or log) on reads, writes, changes or writes of a value
+  dw, unwatch <index>       remove a watchpoint
+  wl, watchpoints           list the watchpoints
+  r, regs                   registers, disassembly and stack
+  hist, history [count]     the last instructions run (all of them by default)
+  x <addr> [count]          dump memory
+  q, quit                   close the emulator
+  An empty line repeats the last command.";
+
+pub enum DebuggerAction {
+    Resume,
+    Quit,
+}
+
+pub struct Breakpoint {
+    pub address: u16,
+    pub condition: Option<Condition>,
+    source: String,
+}
+
+// runs until something happens, set by the commands that resume
+enum StepMode {
+    Run,
+    // stop at the instruction after the call, with the stack back where it was
+    Over { address: u16, sp: u16 },
+    // stop right after a return that brings SP above `sp`
+    Out { sp: u16 },
+    Until(u16),
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    step_mode: StepMode,
+    // set when we stop, so the breakpoint we're sitting on doesn't fire again on resume
+    resume_pc: Option<u16>,
+    // for `out`, whether the last instruction was a return
+    returning: bool,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            step_mode: StepMode::Run,
+            resume_pc: None,
+            returning: false,
+            last_command: String::new(),
+        }
+    }
+
+    // whether `should_break` needs calling at all
+    pub fn is_active(&self, cpu: &CPU) -> bool {
+        !self.breakpoints.is_empty() || !matches!(self.step_mode, StepMode::Run) || cpu.mem_bus.watchpoints.is_some()
+    }
+
+    pub fn should_break(&mut self, cpu: &CPU) -> bool {
+        // accesses made by the last instruction
+        if let Some(watchpoints) = &cpu.mem_bus.watchpoints {
+            let mut watch_break = false;
+
+            for hit in watchpoints.take_hits() {
+                log(&format!("[WATCH] PC 0x{:04X}: {hit}", cpu.pc));
+                watch_break |= !hit.log_only;
+            }
+
+            if watch_break {
+                self.resume_pc = None;
+                return true;
+            }
+        }
+
+        let resuming = self.resume_pc.take() == Some(cpu.pc);
+        let returned = std::mem::replace(&mut self.returning, is_return(cpu.mem_bus.peek_byte(cpu.pc)));
+
+        let stop = match self.step_mode {
+            StepMode::Run => false,
+            StepMode::Over { address, sp } => cpu.pc == address && cpu.regs.sp >= sp,
+            StepMode::Out { sp } => returned && cpu.regs.sp > sp,
+            StepMode::Until(address) => cpu.pc == address,
+        };
+
+        if stop {
+            return true;
+        }
+
+        !resuming && self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.address == cpu.pc && breakpoint.condition.as_ref().is_none_or(|condition| condition.evaluate(cpu))
+        })
+    }
+
+    /**
+        Shows where we are and reads commands from stdin until one resumes the emulation.
+     */
+    pub fn prompt(&mut self, cpu: &mut CPU) -> DebuggerAction {
+        self.step_mode = StepMode::Run;
+        print_history(cpu, HISTORY_LINES);
+        print_state(cpu);
+
+        let stdin = io::stdin();
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                // stdin is closed, nobody's there to type commands
+                return DebuggerAction::Resume;
+            }
+
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            if let Some(action) = self.run_command(cpu, &line) {
+                self.resume_pc = Some(cpu.pc);
+                return action;
+            }
+        }
+    }
+
+    // returns an action when the command leaves the prompt
+    fn run_command(&mut self, cpu: &mut CPU, line: &str) -> Option<DebuggerAction> {
+        let (command, arguments) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arguments = arguments.trim();
+
+        match command {
+            "c" | "continue" => return Some(DebuggerAction::Resume),
+            "q" | "quit" => return Some(DebuggerAction::Quit),
+
+            "s" | "step" => {
+                step(cpu);
+                print_state(cpu);
+            },
+            "n" | "next" => {
+                let opcode = cpu.mem_bus.peek_byte(cpu.pc);
+                if is_call(opcode) {
+                    let address = cpu.pc.wrapping_add(OPCODE_LENGTHS[opcode as usize] as u16);
+                    self.step_mode = StepMode::Over { address, sp: cpu.regs.sp };
+                    return Some(DebuggerAction::Resume);
+                }
+
+                step(cpu);
+                print_state(cpu);
+            },
+            "o" | "out" => {
+                self.step_mode = StepMode::Out { sp: cpu.regs.sp };
+                return Some(DebuggerAction::Resume);
+            },
+            "u" | "until" => match parse_address(arguments, &cpu.symbols) {
+                Some(address) => {
+                    self.step_mode = StepMode::Until(address);
+                    return Some(DebuggerAction::Resume);
+                },
+                None => println!("Usage: until <address>"),
+            },
+
+            "b" | "break" => match parse_breakpoint(arguments, &cpu.symbols) {
+                Ok(breakpoint) => {
+                    println!("Breakpoint {} at {}", self.breakpoints.len(), describe_address(&cpu.symbols, breakpoint.address));
+                    self.breakpoints.push(breakpoint);
+                },
+                Err(message) => println!("{message}"),
+            },
+            "d" | "delete" => match arguments.parse::<usize>() {
+                Ok(index) if index < self.breakpoints.len() => {
+                    self.breakpoints.remove(index);
+                },
+                _ => println!("Usage: delete <index> (see `breakpoints`)"),
+            },
+            "bl" | "breakpoints" => {
+                if self.breakpoints.is_empty() {
+                    println!("No breakpoints");
+                }
+                for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+                    println!("  {index}: {}", breakpoint.source);
+                }
+            },
+
+            "w" | "watch" => match Watchpoint::parse(arguments) {
+                Ok(watchpoint) => {
+                    let watchpoints = cpu.mem_bus.watchpoints.get_or_insert_with(|| Box::new(Watchpoints::new()));
+                    println!("Watchpoint {}: {watchpoint}", watchpoints.list.len());
+                    watchpoints.list.push(watchpoint);
+                },
+                Err(message) => println!("{message}"),
+            },
+            "dw" | "unwatch" => {
+                let count = cpu.mem_bus.watchpoints.as_ref().map_or(0, |watchpoints| watchpoints.list.len());
+
+                match arguments.parse::<usize>() {
+                    Ok(index) if index < count => {
+                        let watchpoints = cpu.mem_bus.watchpoints.as_mut().unwrap();
+                        watchpoints.list.remove(index);
+
+                        // back to no checks at all
+                        if watchpoints.list.is_empty() {
+                            cpu.mem_bus.watchpoints = None;
+                        }
+                    },
+                    _ => println!("Usage: unwatch <index> (see `watchpoints`)"),
+                }
+            },
+            "wl" | "watchpoints" => match &cpu.mem_bus.watchpoints {
+                Some(watchpoints) => {
+                    for (index, watchpoint) in watchpoints.list.iter().enumerate() {
+                        println!("  {index}: {watchpoint}");
+                    }
+                },
+                None => println!("No watchpoints"),
+            },
+
+            "r" | "regs" => print_state(cpu),
+            "hist" | "history" => print_history(cpu, parse_number(arguments).map_or(HISTORY_LENGTH, |count| count as usize)),
+            "x" => {
+                let mut arguments = arguments.split_whitespace();
+                match arguments.next().and_then(|address| parse_address(address, &cpu.symbols)) {
+                    Some(address) => {
+                        let count = arguments.next().and_then(parse_number).unwrap_or(16);
+                        print_memory(cpu, address, count);
+                    },
+                    None => println!("Usage: x <address> [count]"),
+                }
+            },
+
+            "h" | "help" => println!("{HELP}"),
+            _ => println!("Unknown command \"{command}\", try `help`"),
+        }
+
+        None
+    }
+}
+
+
+pub struct Condition {
+    comparisons: Vec<Comparison>,
+}
+
+struct Comparison {
+    left: Operand,
+    operator: Operator,
+    right: Operand,
+}
+
+#[derive(Clone, Copy)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+enum Operand {
+    Register(String),
+    Memory(Box<Operand>),
+    Value(u32),
+}
+
+impl Condition {
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self, String> {
+        let comparisons = text.split("&&").map(|term| Comparison::parse(term.trim(), symbols)).collect::<Result<_, _>>()?;
+        Ok(Condition { comparisons })
+    }
+
+    pub fn evaluate(&self, cpu: &CPU) -> bool {
+        self.comparisons.iter().all(|comparison| comparison.evaluate(cpu))
+    }
+}
+
+impl Comparison {
+    // operators are tried longest first, so "<=" isn't read as "<"
+    const OPERATORS: [(&'static str, Operator); 6] = [
+        ("==", Operator::Equal), ("!=", Operator::NotEqual),
+        ("<=", Operator::LessOrEqual), (">=", Operator::GreaterOrEqual),
+        ("<", Operator::Less), (">", Operator::Greater),
+    ];
+
+    fn parse(term: &str, symbols: &Symbols) -> Result<Self, String> {
+        for (symbol, operator) in Comparison::OPERATORS {
+            if let Some((left, right)) = term.split_once(symbol) {
+                return Ok(Comparison {
+                    left: Operand::parse(left.trim(), symbols)?,
+                    operator,
+                    right: Operand::parse(right.trim(), symbols)?,
+                });
+            }
+        }
+
+        // a lone operand is a truth test: "ZF", "!CF"
+        match term.strip_prefix('!') {
+            Some(operand) => Ok(Comparison { left: Operand::parse(operand.trim(), symbols)?, operator: Operator::Equal, right: Operand::Value(0) }),
+            None => Ok(Comparison { left: Operand::parse(term, symbols)?, operator: Operator::NotEqual, right: Operand::Value(0) }),
+        }
+    }
+
+    fn evaluate(&self, cpu: &CPU) -> bool {
+        let (left, right) = (self.left.evaluate(cpu), self.right.evaluate(cpu));
+
+        match self.operator {
+            Operator::Equal => left == right,
+            Operator::NotEqual => left != right,
+            Operator::Less => left < right,
+            Operator::LessOrEqual => left <= right,
+            Operator::Greater => left > right,
+            Operator::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl Operand {
+    fn parse(text: &str, symbols: &Symbols) -> Result<Self, String> {
+        if let Some(inner) = text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+            return Ok(Operand::Memory(Box::new(Operand::parse(inner.trim(), symbols)?)));
+        }
+
+        if let Some(value) = parse_number(text) {
+            return Ok(Operand::Value(value as u32));
+        }
+
+        let name = text.to_ascii_uppercase();
+        if REGISTER_NAMES.contains(&name.as_str()) {
+            return Ok(Operand::Register(name));
+        }
+
+        match symbols.address_of(text) {
+            Some(address) => Ok(Operand::Value(address as u32)),
+            None => Err(format!("unknown register, value or label \"{text}\"")),
+        }
+    }
+
+    fn evaluate(&self, cpu: &CPU) -> u32 {
+        match self {
+            Operand::Register(name) => register_value(cpu, name).unwrap_or(0),
+            Operand::Memory(address) => cpu.mem_bus.peek_byte(address.evaluate(cpu) as u16) as u32,
+            Operand::Value(value) => *value,
+        }
+    }
+}
+
+fn register_value(cpu: &CPU, name: &str) -> Option<u32> {
+    let regs = &cpu.regs;
+
+    Some(match name {
+        "A" => regs.a as u32,
+        "F" => regs.flags.into_u8() as u32,
+        "B" => regs.b as u32,
+        "C" => regs.c as u32,
+        "D" => regs.d as u32,
+        "E" => regs.e as u32,
+        "H" => regs.h as u32,
+        "L" => regs.l as u32,
+        "AF" => regs.get_vreg_value(Reg16::AF).0 as u32,
+        "BC" => regs.get_vreg_value(Reg16::BC).0 as u32,
+        "DE" => regs.get_vreg_value(Reg16::DE).0 as u32,
+        "HL" => regs.get_vreg_value(Reg16::HL).0 as u32,
+        "SP" => regs.sp as u32,
+        "PC" => cpu.pc as u32,
+        "ZF" => regs.flags.zero as u32,
+        "NF" => regs.flags.subtract as u32,
+        "HF" => regs.flags.half_carry as u32,
+        "CF" => regs.flags.carry as u32,
+        _ => return None,
+    })
+}
+
+fn parse_breakpoint(arguments: &str, symbols: &Symbols) -> Result<Breakpoint, String> {
+    let (address, condition) = match arguments.split_once(" if ") {
+        Some((address, condition)) => (address.trim(), Some(Condition::parse(condition, symbols)?)),
+        None => (arguments, None),
+    };
+
+    let address = parse_address(address, symbols).ok_or(String::from("Usage: break <address|label> [if <condition>]"))?;
+
+    Ok(Breakpoint { address, condition, source: arguments.to_string() })
+}
+
+// a number, or a label from the .sym file
+fn parse_address(text: &str, symbols: &Symbols) -> Option<u16> {
+    match parse_number(text) {
+        Some(address) => Some(address).filter(|address| *address <= 0xFFFF).map(|address| address as u16),
+        None => symbols.address_of(text),
+    }
+}
+
+// "$0153 (Main+$3)"
+fn describe_address(symbols: &Symbols, address: u16) -> String {
+    match symbols.describe(address, MAPPED_ROM_BANK) {
+        Some(name) => format!("${address:04X} ({name})"),
+        None => format!("${address:04X}"),
+    }
+}
+
+// one instruction, from the prompt
+fn step(cpu: &mut CPU) {
+    if let Err(error) = cpu.step() {
+        println!("{error}");
+    }
+}
+
+// CALL, CALL cc and RST
+fn is_call(opcode: u8) -> bool {
+    matches!(opcode, 0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC) || opcode & 0xC7 == 0xC7
+}
+
+// RET, RET cc and RETI
+fn is_return(opcode: u8) -> bool {
+    matches!(opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8)
+}
+
+
+// the last `count` instructions, oldest first, their registers before they ran
+fn print_history(cpu: &CPU, count: usize) {
+    if cpu.history.len() == 0 {
+        return;
+    }
+
+    let lines = cpu.history.lines(count, &cpu.symbols);
+    println!("Last {} instructions:", lines.len());
+    for line in lines {
+        println!("  {line}");
+    }
+}
+
+fn print_state(cpu: &CPU) {
+    let regs = &cpu.regs;
+    let flag = |set: bool, name: char| if set { name } else { '-' };
+
+    println!(
+        "AF: {:04X}  BC: {:04X}  DE: {:04X}  HL: {:04X}  SP: {:04X}  PC: {:04X}  [{}{}{}{}]{}  cycles: {}",
+        regs.get_vreg_value(Reg16::AF).0, regs.get_vreg_value(Reg16::BC).0,
+        regs.get_vreg_value(Reg16::DE).0, regs.get_vreg_value(Reg16::HL).0,
+        regs.sp, cpu.pc,
+        flag(regs.flags.zero, 'Z'), flag(regs.flags.subtract, 'N'),
+        flag(regs.flags.half_carry, 'H'), flag(regs.flags.carry, 'C'),
+        if cpu.is_locked { "  LOCKED" } else if cpu.is_halted { "  HALTED" } else { "" },
+        cpu.cycles,
+    );
+
+    let mut address = cpu.pc;
+    for line in 0..DISASSEMBLY_LINES {
+        // at the top, where in the function we stopped
+        let label = match line {
+            0 => cpu.symbols.describe(address, MAPPED_ROM_BANK),
+            _ => cpu.symbols.name_at(address, MAPPED_ROM_BANK).map(String::from),
+        };
+        if let Some(label) = label {
+            println!("  {label}:");
+        }
+
+        let (text, length) = describe_instruction(cpu, address);
+        println!("{} {text}", if line == 0 { '>' } else { ' ' });
+        address = address.wrapping_add(length);
+    }
+
+    print!("Stack:");
+    for i in 0..STACK_LINES as u16 {
+        let address = regs.sp.wrapping_add(i * 2);
+        if address >= 0xFFFE {
+            break;
+        }
+        let word = u16::from_le_bytes([cpu.mem_bus.peek_byte(address), cpu.mem_bus.peek_byte(address + 1)]);
+
+        // with symbols, one entry per line: return addresses read a lot better with their function
+        match cpu.symbols.describe(word, MAPPED_ROM_BANK) {
+            Some(name) => print!("\n  {address:04X}: {word:04X} ({name})"),
+            None => print!("  {address:04X}: {word:04X}"),
+        }
+    }
+    println!();
+}
+
+// "0150: 18 FE     JR $0150              ; 12", and the instruction's length
+pub fn describe_instruction(cpu: &CPU, address: u16) -> (String, u16) {
+    let bytes: Vec<u8> = (0..3).map(|i| cpu.mem_bus.peek_byte(address.wrapping_add(i))).collect();
+    let instruction = DisassembledInstruction::decode(&bytes, address);
+
+    (disassembler::format_line(&instruction, &cpu.symbols, MAPPED_ROM_BANK), instruction.length())
+}
+
+fn print_memory(cpu: &CPU, address: u16, count: u64) {
+    for row in memory_rows(cpu, address, count) {
+        println!("{row}");
+    }
+}
+
+// 16 bytes a row, stops after 0xFFFF instead of wrapping around
+fn memory_rows(cpu: &CPU, address: u16, count: u64) -> Vec<String> {
+    let end = (address as u64).saturating_add(count).min(0x10000);
+
+    (address as u64..end).step_by(16).map(|row_start| {
+        let bytes: Vec<String> = (row_start..end.min(row_start + 16))
+            .map(|i| format!("{:02X}", cpu.mem_bus.peek_byte(i as u16)))
+            .collect();
+
+        format!("{row_start:04X}: {}", bytes.join(" "))
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_dump_stops_at_the_last_byte() {
+        let mut cpu = CPU::new();
+        cpu.mem_bus.write_byte(0xFFFF, 0x1F);
+
+        let rows = memory_rows(&cpu, 0xFFF0, 16);
+        assert_eq!(rows, ["FFF0: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 1F"]);
+
+        // past the end, the rest is cut off
+        let rows = memory_rows(&cpu, 0xFFF8, u64::MAX);
+        assert_eq!(rows, ["FFF8: 00 00 00 00 00 00 00 1F"]);
+    }
+}
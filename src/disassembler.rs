@@ -0,0 +1,456 @@
+use std::{fmt, io::{self, Write}, path::{Path, PathBuf}};
+
+use crate::{instructions::{self, OPCODE_CYCLES, OPCODE_LENGTHS}, rom::{RomError, ROM}, symbols::{Symbols, MAPPED_ROM_BANK}, tracer, utils::{self, log}};
+
+/*
+    Turns bytes back into RGBDS-syntax assembly: `LD A,[HL+]`, `JR NZ,$0150`, `LDH [$FF40],A`.
+
+    Every opcode has a template, where the operand (there's at most one) is a placeholder:
+        n8, n16     immediate values
+        a8          an address in $FF00-$FFFF, for LDH
+        a16         an address in memory
+        e8          a relative jump, shown as its target
+        j16         an absolute jump or call target
+        s8          a signed offset from SP
+    Illegal opcodes come out as `db $D3`.
+
+    The CLI:
+        crusty-boy disasm <rom> [--bank N] [--start ADDR] [--end ADDR]
+        crusty-boy disasm <rom> --asm <out.asm>
+*/
+
+const UNPREFIXED_TEMPLATES: [&str; 256] = [
+    "NOP",          "LD BC,n16",    "LD [BC],A",    "INC BC",       "INC B",        "DEC B",        "LD B,n8",      "RLCA",         // 0x00
+    "LD [a16],SP",  "ADD HL,BC",    "LD A,[BC]",    "DEC BC",       "INC C",        "DEC C",        "LD C,n8",      "RRCA",         // 0x08
+    "STOP",         "LD DE,n16",    "LD [DE],A",    "INC DE",       "INC D",        "DEC D",        "LD D,n8",      "RLA",          // 0x10
+    "JR e8",        "ADD HL,DE",    "LD A,[DE]",    "DEC DE",       "INC E",        "DEC E",        "LD E,n8",      "RRA",          // 0x18
+    "JR NZ,e8",     "LD HL,n16",    "LD [HL+],A",   "INC HL",       "INC H",        "DEC H",        "LD H,n8",      "DAA",          // 0x20
+    "JR Z,e8",      "ADD HL,HL",    "LD A,[HL+]",   "DEC HL",       "INC L",        "DEC L",        "LD L,n8",      "CPL",          // 0x28
+    "JR NC,e8",     "LD SP,n16",    "LD [HL-],A",   "INC SP",       "INC [HL]",     "DEC [HL]",     "LD [HL],n8",   "SCF",          // 0x30
+    "JR C,e8",      "ADD HL,SP",    "LD A,[HL-]",   "DEC SP",       "INC A",        "DEC A",        "LD A,n8",      "CCF",          // 0x38
+    "LD B,B",       "LD B,C",       "LD B,D",       "LD B,E",       "LD B,H",       "LD B,L",       "LD B,[HL]",    "LD B,A",       // 0x40
+    "LD C,B",       "LD C,C",       "LD C,D",       "LD C,E",       "LD C,H",       "LD C,L",       "LD C,[HL]",    "LD C,A",       // 0x48
+    "LD D,B",       "LD D,C",       "LD D,D",       "LD D,E",       "LD D,H",       "LD D,L",       "LD D,[HL]",    "LD D,A",       // 0x50
+    "LD E,B",       "LD E,C",       "LD E,D",       "LD E,E",       "LD E,H",       "LD E,L",       "LD E,[HL]",    "LD E,A",       // 0x58
+    "LD H,B",       "LD H,C",       "LD H,D",       "LD H,E",       "LD H,H",       "LD H,L",       "LD H,[HL]",    "LD H,A",       // 0x60
+    "LD L,B",       "LD L,C",       "LD L,D",       "LD L,E",       "LD L,H",       "LD L,L",       "LD L,[HL]",    "LD L,A",       // 0x68
+    "LD [HL],B",    "LD [HL],C",    "LD [HL],D",    "LD [HL],E",    "LD [HL],H",    "LD [HL],L",    "HALT",         "LD [HL],A",    // 0x70
+    "LD A,B",       "LD A,C",       "LD A,D",       "LD A,E",       "LD A,H",       "LD A,L",       "LD A,[HL]",    "LD A,A",       // 0x78
+    "ADD A,B",      "ADD A,C",      "ADD A,D",      "ADD A,E",      "ADD A,H",      "ADD A,L",      "ADD A,[HL]",   "ADD A,A",      // 0x80
+    "ADC A,B",      "ADC A,C",      "ADC A,D",      "ADC A,E",      "ADC A,H",      "ADC A,L",      "ADC A,[HL]",   "ADC A,A",      // 0x88
+    "SUB A,B",      "SUB A,C",      "SUB A,D",      "SUB A,E",      "SUB A,H",      "SUB A,L",      "SUB A,[HL]",   "SUB A,A",      // 0x90
+    "SBC A,B",      "SBC A,C",      "SBC A,D",      "SBC A,E",      "SBC A,H",      "SBC A,L",      "SBC A,[HL]",   "SBC A,A",      // 0x98
+    "AND A,B",      "AND A,C",      "AND A,D",      "AND A,E",      "AND A,H",      "AND A,L",      "AND A,[HL]",   "AND A,A",      // 0xA0
+    "XOR A,B",      "XOR A,C",      "XOR A,D",      "XOR A,E",      "XOR A,H",      "XOR A,L",      "XOR A,[HL]",   "XOR A,A",      // 0xA8
+    "OR A,B",       "OR A,C",       "OR A,D",       "OR A,E",       "OR A,H",       "OR A,L",       "OR A,[HL]",    "OR A,A",       // 0xB0
+    "CP A,B",       "CP A,C",       "CP A,D",       "CP A,E",       "CP A,H",       "CP A,L",       "CP A,[HL]",    "CP A,A",       // 0xB8
+    "RET NZ",       "POP BC",       "JP NZ,j16",    "JP j16",       "CALL NZ,j16",  "PUSH BC",      "ADD A,n8",     "RST $00",      // 0xC0
+    "RET Z",        "RET",          "JP Z,j16",     "",             "CALL Z,j16",   "CALL j16",     "ADC A,n8",     "RST $08",      // 0xC8
+    "RET NC",       "POP DE",       "JP NC,j16",    "",             "CALL NC,j16",  "PUSH DE",      "SUB A,n8",     "RST $10",      // 0xD0
+    "RET C",        "RETI",         "JP C,j16",     "",             "CALL C,j16",   "",             "SBC A,n8",     "RST $18",      // 0xD8
+    "LDH [a8],A",   "POP HL",       "LDH [C],A",    "",             "",             "PUSH HL",      "AND A,n8",     "RST $20",      // 0xE0
+    "ADD SP,s8",    "JP HL",        "LD [a16],A",   "",             "",             "",             "XOR A,n8",     "RST $28",      // 0xE8
+    "LDH A,[a8]",   "POP AF",       "LDH A,[C]",    "DI",           "",             "PUSH AF",      "OR A,n8",      "RST $30",      // 0xF0
+    "LD HL,SP+s8",  "LD SP,HL",     "LD A,[a16]",   "EI",           "",             "",             "CP A,n8",      "RST $38",      // 0xF8
+];
+
+// 0xCB opcodes are regular: operation in bits 3-7, register in bits 0-2
+const PREFIXED_OPERATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const PREFIXED_REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "[HL]", "A"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+    Immediate8(u8),
+    Immediate16(u16),
+    HighAddress(u8),
+    Address(u16),
+    // jumps (relative ones included) and calls, as the address they go to
+    Target(u16),
+    SpOffset(i8),
+}
+
+#[derive(Clone, Debug)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    // T-cycles, and when a conditional jump/call/ret is taken
+    pub cycles: u8,
+    pub taken_cycles: u8,
+    template: String,
+    pub operand: Option<Operand>,
+}
+
+impl DisassembledInstruction {
+    /**
+        Decodes the instruction at the start of `bytes`, which sits at `address`.
+        Unknown opcodes, and instructions cut short by the end of `bytes`, are `db`s.
+     */
+    pub fn decode(bytes: &[u8], address: u16) -> Self {
+        let Some(&opcode) = bytes.first() else {
+            return Self::data(&[], address);
+        };
+
+        if opcode == 0xCB {
+            return match bytes.get(1) {
+                Some(&byte) => DisassembledInstruction {
+                    address,
+                    bytes: vec![opcode, byte],
+                    cycles: instructions::prefixed_opcode_cycles(byte),
+                    taken_cycles: instructions::prefixed_opcode_cycles(byte),
+                    template: prefixed_template(byte),
+                    operand: None,
+                },
+                None => Self::data(&bytes[..1], address),
+            };
+        }
+
+        let template = UNPREFIXED_TEMPLATES[opcode as usize];
+        let length = OPCODE_LENGTHS[opcode as usize] as usize;
+
+        if template.is_empty() || bytes.len() < length {
+            return Self::data(&bytes[..1], address);
+        }
+
+        let next_address = address.wrapping_add(length as u16);
+        let byte = bytes.get(1).copied().unwrap_or(0);
+        let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or(0)]);
+
+        let operand = match placeholder(template) {
+            Some("n8") => Some(Operand::Immediate8(byte)),
+            Some("n16") => Some(Operand::Immediate16(word)),
+            Some("a8") => Some(Operand::HighAddress(byte)),
+            Some("a16") => Some(Operand::Address(word)),
+            Some("e8") => Some(Operand::Target(next_address.wrapping_add(byte as i8 as u16))),
+            Some("j16") => Some(Operand::Target(word)),
+            Some("s8") => Some(Operand::SpOffset(byte as i8)),
+            _ => None,
+        };
+
+        let cycles = OPCODE_CYCLES[opcode as usize];
+
+        DisassembledInstruction {
+            address,
+            bytes: bytes[..length].to_vec(),
+            cycles,
+            taken_cycles: cycles + instructions::branch_taken_extra_cycles(opcode),
+            template: String::from(template),
+            operand,
+        }
+    }
+
+    // a lone byte that isn't (or can't be) an instruction
+    fn data(bytes: &[u8], address: u16) -> Self {
+        DisassembledInstruction {
+            address,
+            bytes: bytes.to_vec(),
+            cycles: 0,
+            taken_cycles: 0,
+            template: String::from("db"),
+            operand: None,
+        }
+    }
+
+    pub fn length(&self) -> u16 {
+        self.bytes.len().max(1) as u16
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.template == "db"
+    }
+
+    // with `operand` written in place of the operand's value, for labels
+    pub fn text_with_operand(&self, operand: &str) -> String {
+        match placeholder(&self.template) {
+            Some(placeholder) => self.template.replacen(placeholder, operand, 1),
+            None => self.to_string(),
+        }
+    }
+
+    // the template without its operand's value, e.g. "JR NZ,e8"
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+}
+
+/**
+    The RGBDS-style template of an opcode, None for illegal ones. 0xCB alone
+    isn't an instruction; its opcodes are asked for with `prefixed`.
+ */
+pub fn opcode_template(opcode: u8, prefixed: bool) -> Option<String> {
+    if prefixed {
+        return Some(prefixed_template(opcode));
+    }
+
+    Some(UNPREFIXED_TEMPLATES[opcode as usize])
+        .filter(|template| !template.is_empty())
+        .map(String::from)
+}
+
+fn prefixed_template(byte: u8) -> String {
+    let register = PREFIXED_REGISTERS[(byte & 0x07) as usize];
+    let bit = (byte >> 3) & 0x07;
+
+    match byte >> 6 {
+        0 => format!("{} {register}", PREFIXED_OPERATIONS[bit as usize]),
+        1 => format!("BIT {bit},{register}"),
+        2 => format!("RES {bit},{register}"),
+        _ => format!("SET {bit},{register}"),
+    }
+}
+
+// the operand's placeholder in a template, if it has one
+pub fn placeholder(template: &str) -> Option<&'static str> {
+    ["n16", "n8", "a16", "a8", "e8", "j16", "s8"].into_iter()
+        .find(|placeholder| template.split([' ', ',', '[', ']', '+']).any(|word| word == *placeholder))
+}
+
+/**
+    Linear disassembly of `bytes`, the first one being at `address`. Doesn't tell code
+    from data: whatever follows a jump is disassembled as if it ran.
+ */
+pub fn disassemble(bytes: &[u8], address: u16) -> Vec<DisassembledInstruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let instruction = DisassembledInstruction::decode(&bytes[offset..], address.wrapping_add(offset as u16));
+        offset += instruction.length() as usize;
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+
+pub struct DisasmOptions {
+    pub rom_path: String,
+    // traces the whole ROM into an .asm file instead, see tracer.rs
+    pub asm_path: Option<PathBuf>,
+    pub bank: usize,
+    pub start: Option<u16>,
+    pub end: Option<u16>,
+}
+
+impl DisasmOptions {
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = DisasmOptions { rom_path: String::new(), asm_path: None, bank: 0, start: None, end: None };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut number = || args.next()
+                .and_then(|value| utils::parse_number(value))
+                .ok_or(format!("{arg} needs a number"));
+
+            match arg.as_str() {
+                "--bank" => options.bank = number()? as usize,
+                "--start" => options.start = Some(number()? as u16),
+                "--end" => options.end = Some(number()? as u16),
+                "--asm" => options.asm_path = Some(PathBuf::from(args.next().ok_or(format!("{arg} needs a path"))?)),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+                _ => options.rom_path = arg.clone(),
+            }
+        }
+
+        if options.rom_path.is_empty() {
+            return Err(String::from("no ROM given"));
+        }
+
+        Ok(options)
+    }
+}
+
+pub fn main(args: &[String]) -> i32 {
+    let options = match DisasmOptions::from_args(args) {
+        Ok(options) => options,
+        Err(message) => {
+            log(&format!("[DISASM] {message}"));
+            return 1;
+        },
+    };
+
+    let result = match &options.asm_path {
+        Some(path) => write_asm(&options.rom_path, path),
+        None => print_bank(&options),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            log(&format!("[DISASM] {error}"));
+            1
+        },
+    }
+}
+
+fn write_asm(rom_path: &str, path: &Path) -> Result<(), RomError> {
+    let rom = ROM::read_rom(rom_path)?;
+    let symbols = Symbols::for_rom(rom_path);
+    let trace = tracer::write_asm_file(&rom.data, &symbols, path, &format!("\"{}\"", rom.name))?;
+
+    log(&format!(
+        "[DISASM] {} bytes of code and {} labels found, written to {}",
+        trace.code_bytes(), trace.label_count(), path.display()
+    ));
+    Ok(())
+}
+
+/**
+    Prints a ROM bank (or part of it) at the addresses it's mapped to: bank 0 at
+    $0000-$3FFF, the others at $4000-$7FFF.
+ */
+fn print_bank(options: &DisasmOptions) -> Result<(), RomError> {
+    let rom = ROM::read_rom(&options.rom_path)?;
+    let bank = options.bank;
+
+    let bank_count = rom.data.len().div_ceil(0x4000);
+    if bank >= bank_count {
+        println!("The ROM only has {bank_count} banks");
+        return Ok(());
+    }
+
+    let bank_address: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let bank_data = &rom.data[bank * 0x4000..rom.data.len().min((bank + 1) * 0x4000)];
+    let bank_end = bank_address + (bank_data.len() as u16 - 1);
+
+    let start = options.start.unwrap_or(bank_address).clamp(bank_address, bank_end);
+    let end = options.end.unwrap_or(bank_end).clamp(start, bank_end);
+
+    let bytes = &bank_data[(start - bank_address) as usize..=(end - bank_address) as usize];
+
+    let symbols = Symbols::for_rom(&options.rom_path);
+    // bank 0 reaches whatever's mapped, the others can only mean themselves
+    let mapped_bank = if bank == 0 { MAPPED_ROM_BANK } else { bank as u16 };
+
+    let mut stdout = io::stdout().lock();
+    for instruction in disassemble(bytes, start) {
+        let label = symbols.name_at(instruction.address, bank as u16).map(|name| format!("{name}:\n")).unwrap_or_default();
+
+        // whoever reads the output went away (`| head`)
+        if writeln!(stdout, "{label}{bank:02X}:{}", format_line(&instruction, &symbols, mapped_bank)).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// "0150: 18 FE     JR Main               ; 12", for listings, `bank` being the one at $4000-$7FFF
+pub fn format_line(instruction: &DisassembledInstruction, symbols: &Symbols, bank: u16) -> String {
+    let bytes: Vec<String> = instruction.bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+
+    let cycles = match (instruction.cycles, instruction.taken_cycles) {
+        (0, _) => String::new(),
+        (cycles, taken) if cycles == taken => format!("; {cycles}"),
+        (cycles, taken) => format!("; {taken}/{cycles}"),
+    };
+
+    let text = match symbols.operand_name(instruction, bank) {
+        Some(name) => instruction.text_with_operand(name),
+        None => instruction.to_string(),
+    };
+
+    format!("{:04X}: {:<9} {:<22}{cycles}", instruction.address, bytes.join(" "), text).trim_end().to_string()
+}
+
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Immediate8(value) => write!(f, "${value:02X}"),
+            Operand::Immediate16(value) => write!(f, "${value:04X}"),
+            Operand::HighAddress(low) => write!(f, "$FF{low:02X}"),
+            Operand::Address(address) | Operand::Target(address) => write!(f, "${address:04X}"),
+            Operand::SpOffset(offset) if *offset < 0 => write!(f, "-${:02X}", offset.unsigned_abs()),
+            Operand::SpOffset(offset) => write!(f, "${offset:02X}"),
+        }
+    }
+}
+
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_data() {
+            return write!(f, "db ${:02X}", self.bytes.first().copied().unwrap_or(0));
+        }
+
+        match (self.operand, placeholder(&self.template)) {
+            // SP+-$03 reads badly, RGBDS takes SP-$03 too
+            (Some(operand), Some(placeholder)) => write!(f, "{}", self.template.replacen(placeholder, &operand.to_string(), 1).replace("+-", "-")),
+            _ => write!(f, "{}", self.template),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8], address: u16) -> DisassembledInstruction {
+        DisassembledInstruction::decode(bytes, address)
+    }
+
+    #[test]
+    fn decodes_the_usual_forms() {
+        let instruction = decode(&[0x2A], 0x0150);
+        assert_eq!(instruction.to_string(), "LD A,[HL+]");
+        assert_eq!((instruction.length(), instruction.cycles), (1, 8));
+
+        let instruction = decode(&[0xE0, 0x40], 0x0150);
+        assert_eq!(instruction.to_string(), "LDH [$FF40],A");
+        assert_eq!(instruction.operand, Some(Operand::HighAddress(0x40)));
+        assert_eq!((instruction.length(), instruction.cycles), (2, 12));
+
+        let instruction = decode(&[0xC3, 0x50, 0x01], 0x0100);
+        assert_eq!(instruction.to_string(), "JP $0150");
+        assert_eq!((instruction.length(), instruction.cycles), (3, 16));
+
+        let instruction = decode(&[0xF8, 0xFE], 0x0150);
+        assert_eq!(instruction.to_string(), "LD HL,SP-$02");
+    }
+
+    #[test]
+    fn relative_jumps_show_their_target() {
+        // backwards from the end of the instruction: 0x0156 - 6
+        let instruction = decode(&[0x20, 0xFA], 0x0154);
+        assert_eq!(instruction.to_string(), "JR NZ,$0150");
+        assert_eq!(instruction.operand, Some(Operand::Target(0x0150)));
+        assert_eq!(instruction.template(), "JR NZ,e8");
+        // 8 when it falls through, 12 when it jumps
+        assert_eq!((instruction.cycles, instruction.taken_cycles), (8, 12));
+    }
+
+    #[test]
+    fn decodes_prefixed_opcodes() {
+        let instruction = decode(&[0xCB, 0x37], 0x0150);
+        assert_eq!(instruction.to_string(), "SWAP A");
+        assert_eq!((instruction.length(), instruction.cycles), (2, 8));
+
+        assert_eq!(decode(&[0xCB, 0x7E], 0x0150).to_string(), "BIT 7,[HL]");
+        assert_eq!(decode(&[0xCB, 0x86], 0x0150).cycles, 16);
+        assert_eq!(opcode_template(0xC7, true).unwrap(), "SET 0,A");
+    }
+
+    #[test]
+    fn unknown_and_cut_short_bytes_are_data() {
+        let instruction = decode(&[0xDD], 0x0150);
+        assert!(instruction.is_data());
+        assert_eq!(instruction.to_string(), "db $DD");
+        assert_eq!(opcode_template(0xDD, false), None);
+
+        // an immediate missing its second byte
+        let instruction = decode(&[0x21, 0x00], 0x0150);
+        assert!(instruction.is_data());
+        assert_eq!(instruction.length(), 1);
+
+        assert!(decode(&[0xCB], 0x0150).is_data());
+    }
+
+    #[test]
+    fn linear_disassembly_follows_the_lengths() {
+        let instructions = disassemble(&[0x00, 0x3E, 0x12, 0xCB, 0x37, 0xC3, 0x00, 0x01], 0x0150);
+        let addresses: Vec<u16> = instructions.iter().map(|instruction| instruction.address).collect();
+
+        assert_eq!(addresses, [0x0150, 0x0151, 0x0153, 0x0155]);
+        assert_eq!(format_line(&instructions[1], &Symbols::new(), 0), "0151: 3E 12     LD A,$12              ; 8");
+    }
+}
@@ -0,0 +1,55 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use crate::cpu::CPU;
+
+/*
+    Traces in the format of Gameboy Doctor (https://github.com/robert/gameboy-doctor),
+    one line per instruction with the state right before it runs:
+
+        A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+
+    PCMEM being the 4 bytes at PC. The reference logs start at 0x0100 with the post-boot
+    state and were made with LY stuck at 0x90 (so the tests never wait on the LCD),
+    which is what the CPU reads while tracing. Ours can then be diffed against the
+    published ones for each cpu_instrs test:
+
+        crusty-boy headless 01-special.gb --frames 600 --doctor-log data/doctor.txt
+*/
+
+pub const STUBBED_LY: u8 = 0x90;
+const PCMEM_LENGTH: u16 = 4;
+
+pub struct DoctorLog {
+    out: BufWriter<File>,
+}
+
+impl DoctorLog {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(DoctorLog { out: BufWriter::new(File::create(path)?) })
+    }
+
+    pub fn write_state(&mut self, cpu: &CPU) -> io::Result<()> {
+        writeln!(self.out, "{}", state_line(cpu))
+    }
+}
+
+// traces every instruction `cpu` runs from now on to `path`, with LY stubbed
+pub fn enable(cpu: &mut CPU, path: &Path) -> io::Result<()> {
+    cpu.doctor_log = Some(Box::new(DoctorLog::create(path)?));
+    cpu.mem_bus.stubbed_ly = Some(STUBBED_LY);
+    Ok(())
+}
+
+// "A:01 F:B0 ... PCMEM:00,C3,13,02", the CPU as it is now
+pub fn state_line(cpu: &CPU) -> String {
+    let regs = &cpu.regs;
+    let pcmem: Vec<String> = (0..PCMEM_LENGTH)
+        .map(|offset| format!("{:02X}", cpu.mem_bus.peek_byte(cpu.pc.wrapping_add(offset))))
+        .collect();
+
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+        regs.a, regs.flags.into_u8(), regs.b, regs.c, regs.d, regs.e, regs.h, regs.l,
+        regs.sp, cpu.pc, pcmem.join(",")
+    )
+}
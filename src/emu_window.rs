@@ -1,30 +1,388 @@
-use minifb::{Key, Window, WindowOptions};
-use crate::{cpu::CPU, gpu::{self, TilePixelValue}};
-
-const SCREEN_WIDTH: usize = 160 * SCREEN_MAGNIFIER;
-const SCREEN_HEIGHT: usize = 144 * SCREEN_MAGNIFIER;
-const SCREEN_MAGNIFIER: usize = 3;
-
-pub fn window_life(mut cpu: CPU) {
-    let mut window = Window::new(
-        "Crusty-Boy",
-        SCREEN_WIDTH,
-        SCREEN_HEIGHT,
-        WindowOptions::default(),
-    )
-    .expect("Could not create window");
-    let mut framebuffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT]; // 1 pixel = 4 bytes (RGBA)
+use std::path::Path;
+
+use minifb::{Key, KeyRepeat, Window};
+use crate::{boot::{self, BootAnimation}, capture::{self, GifRecorder, RawRecorder}, config::Config, cpu::CPU, debugger::{Debugger, DebuggerAction}, filters::PostProcessor, gpu::{self, LCD_HEIGHT, LCD_WIDTH}, joypad, movie::{self, Movie, MoviePlayer, MovieStart}, pacing::FramePacer, presentation::{self, MAX_SCALE, MIN_SCALE}, rewind::RewindBuffer, rom::ROM, savestate, utils::log};
+
+const WINDOW_TITLE: &str = "Crusty-Boy";
+
+const REWIND_KEY: Key = Key::Backspace;
+const SAVE_STATE_KEY: Key = Key::F5;
+const LOAD_STATE_KEY: Key = Key::F8;
+// shift + record starts from power-on instead of the current state
+const RECORD_MOVIE_KEY: Key = Key::F9;
+const PLAY_MOVIE_KEY: Key = Key::F10;
+const SCREENSHOT_KEY: Key = Key::F12;
+// shift + record dumps raw video + audio instead of a GIF
+const RECORD_VIDEO_KEY: Key = Key::F11;
+// held, runs `turbo_speed` frames per displayed frame (or uncapped)
+const TURBO_KEY: Key = Key::Tab;
+const SLOW_MOTION_KEY: Key = Key::M;
+const PAUSE_KEY: Key = Key::P;
+// runs a single frame while paused
+const FRAME_ADVANCE_KEY: Key = Key::N;
+const SCALE_UP_KEY: Key = Key::Equal;
+const SCALE_DOWN_KEY: Key = Key::Minus;
+// integer -> fit -> stretch
+const SCALE_MODE_KEY: Key = Key::F7;
+const FULLSCREEN_KEY: Key = Key::F6;
+const GHOSTING_KEY: Key = Key::F2;
+// none -> LCD grid -> Scale2x -> Scale3x
+const FILTER_KEY: Key = Key::F3;
+// pauses and opens the debugger's prompt in the terminal
+const DEBUGGER_KEY: Key = Key::F1;
+const SLOT_KEYS: [Key; savestate::SLOT_COUNT as usize] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+];
+
+const JOYPAD_KEYS: [(Key, u8); 8] = [
+    (Key::Right, joypad::RIGHT), (Key::Left, joypad::LEFT),
+    (Key::Up, joypad::UP), (Key::Down, joypad::DOWN),
+    (Key::X, joypad::A), (Key::Z, joypad::B),
+    (Key::RightShift, joypad::SELECT), (Key::Enter, joypad::START),
+];
+
+pub fn window_life(mut cpu: CPU, rom: ROM, mut boot: Option<BootAnimation>, config: &Config) {
+    let mut scale = config.scale.clamp(MIN_SCALE, MAX_SCALE);
+    let mut scale_mode = config.scale_mode;
+    let mut fullscreen = false;
+    let fullscreen_size = (config.fullscreen_width, config.fullscreen_height);
+
+    let mut window = presentation::create_window(WINDOW_TITLE, (LCD_WIDTH, LCD_HEIGHT), scale, None);
+    let mut frame = vec![0u32; LCD_WIDTH * LCD_HEIGHT]; // 1 pixel = 4 bytes (RGBA)
+    let mut framebuffer: Vec<u32> = Vec::new();
+    let mut post_processor = PostProcessor::new(config.filter, config.ghosting);
+    let mut show_tileset = false;
+    let mut state_slot: u8 = 1;
+    let mut rewind = RewindBuffer::new(config.rewind_interval, config.rewind_length);
+    let mut recording: Option<Movie> = None;
+    let mut playback: Option<MoviePlayer> = None;
+    let mut gif_recording: Option<GifRecorder> = None;
+    let mut raw_recording: Option<RawRecorder> = None;
+    let capture_dir = Path::new(&config.capture_dir);
+    let mut pacer = FramePacer::new(config.turbo_speed, config.slow_motion_factor);
+    let mut debugger = Debugger::new();
+    #[cfg(feature = "audio")]
+    let audio = crate::audio::AudioOutput::open();
+    // the debugger stopped the CPU in the middle of a frame, the rest is still to run
+    let mut frame_interrupted = false;
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
-        draw_vram_to_framebuffer(&cpu.mem_bus.gpu, &mut framebuffer);
-        // cpu.step();
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            show_tileset = !show_tileset;
+        }
+
+        if window.is_key_pressed(FILTER_KEY, KeyRepeat::No) {
+            post_processor.filter = post_processor.filter.next();
+            log(&format!("[WINDOW] Filter: {}", post_processor.filter));
+        }
+        if window.is_key_pressed(GHOSTING_KEY, KeyRepeat::No) {
+            post_processor.ghosting = !post_processor.ghosting;
+            log(if post_processor.ghosting { "[WINDOW] Ghosting on" } else { "[WINDOW] Ghosting off" });
+        }
+
+        let new_scale = if window.is_key_pressed(SCALE_UP_KEY, KeyRepeat::No) {
+            (scale + 1).min(MAX_SCALE)
+        } else if window.is_key_pressed(SCALE_DOWN_KEY, KeyRepeat::No) {
+            scale.saturating_sub(1).max(MIN_SCALE)
+        } else {
+            scale
+        };
+
+        let toggle_fullscreen = window.is_key_pressed(FULLSCREEN_KEY, KeyRepeat::No);
+
+        // minifb windows can't be resized from code, so they're made again
+        if new_scale != scale || toggle_fullscreen {
+            scale = new_scale;
+            fullscreen ^= toggle_fullscreen;
+
+            window = presentation::create_window(WINDOW_TITLE, (LCD_WIDTH, LCD_HEIGHT), scale, fullscreen.then_some(fullscreen_size));
+            log(&format!("[WINDOW] Scale {scale}x{}", if fullscreen { ", fullscreen" } else { "" }));
+
+            if let Err(error) = Config::store("scale", &scale.to_string()) {
+                log(&format!("[CONFIG] Couldn't save the scale: {error}"));
+            }
+        }
+
+        if window.is_key_pressed(SCALE_MODE_KEY, KeyRepeat::No) {
+            scale_mode = scale_mode.next();
+            log(&format!("[WINDOW] Scale mode: {scale_mode}"));
+
+            if let Err(error) = Config::store("scale_mode", &scale_mode.to_string()) {
+                log(&format!("[CONFIG] Couldn't save the scale mode: {error}"));
+            }
+        }
+
+        for (slot, key) in SLOT_KEYS.iter().enumerate() {
+            if window.is_key_pressed(*key, KeyRepeat::No) {
+                state_slot = slot as u8;
+                log(&format!("[STATE] Slot {state_slot} selected"));
+            }
+        }
+
+        if window.is_key_pressed(SAVE_STATE_KEY, KeyRepeat::No) {
+            // the animation itself isn't part of the machine, so there'd be nothing to resume
+            if boot.is_some() {
+                log("[STATE] Can't save during the boot animation");
+            } else {
+                match savestate::save_to_slot(&cpu, &rom, state_slot) {
+                    Ok(path) => log(&format!("[STATE] Saved slot {state_slot} to {}", path.display())),
+                    Err(error) => log(&format!("[STATE] Couldn't save slot {state_slot}: {error}")),
+                }
+            }
+        }
+
+        if window.is_key_pressed(LOAD_STATE_KEY, KeyRepeat::No) {
+            // the movie's inputs only make sense from where it started, a jump would desync it
+            if recording.is_some() || playback.is_some() {
+                log("[STATE] Can't load a state during a movie");
+            } else {
+                match savestate::load_from_slot(&mut cpu, &rom, state_slot) {
+                    Ok(path) => {
+                        // a state is always past the boot sequence
+                        boot = None;
+                        log(&format!("[STATE] Loaded slot {state_slot} from {}", path.display()));
+                    },
+                    Err(error) => log(&format!("[STATE] Couldn't load slot {state_slot}: {error}")),
+                }
+            }
+        }
+
+        if window.is_key_pressed(RECORD_MOVIE_KEY, KeyRepeat::No) {
+            match recording.take() {
+                Some(movie) => {
+                    let path = movie::movie_path(&rom);
+                    match movie.save(&path) {
+                        Ok(()) => log(&format!("[MOVIE] Saved {} frames to {}", movie.inputs.len(), path.display())),
+                        Err(error) => log(&format!("[MOVIE] Couldn't save movie: {error}")),
+                    }
+                },
+                None if window.is_key_down(Key::LeftShift) => {
+                    let symbols = std::mem::take(&mut cpu.symbols);
+                    cpu = boot::power_on(&rom);
+                    cpu.symbols = symbols;
+                    boot = None;
+                    playback = None;
+                    rewind = RewindBuffer::new(config.rewind_interval, config.rewind_length);
+                    recording = Some(Movie::new(&rom, MovieStart::PowerOn));
+                    log("[MOVIE] Recording from power-on");
+                },
+                None if boot.is_some() => log("[MOVIE] Can't start recording during the boot animation"),
+                None => {
+                    playback = None;
+                    recording = Some(Movie::new(&rom, MovieStart::SaveState(savestate::save_state(&cpu, &rom))));
+                    log("[MOVIE] Recording from the current state");
+                },
+            }
+        }
+
+        if window.is_key_pressed(PLAY_MOVIE_KEY, KeyRepeat::No) {
+            let path = movie::movie_path(&rom);
+            let started = Movie::load(&path).and_then(|movie| Ok((movie.start_cpu(&rom)?, movie)));
+
+            match started {
+                Ok((mut movie_cpu, movie)) => {
+                    movie_cpu.symbols = std::mem::take(&mut cpu.symbols);
+                    cpu = movie_cpu;
+                    boot = None;
+                    recording = None;
+                    rewind = RewindBuffer::new(config.rewind_interval, config.rewind_length);
+                    log(&format!("[MOVIE] Playing {} frames from {}", movie.inputs.len(), path.display()));
+                    playback = Some(MoviePlayer::new(movie));
+                },
+                Err(error) => log(&format!("[MOVIE] Couldn't play {}: {error}", path.display())),
+            }
+        }
+
+        if window.is_key_pressed(PAUSE_KEY, KeyRepeat::No) {
+            pacer.toggle_pause();
+            log(if pacer.paused { "[PACING] Paused" } else { "[PACING] Resumed" });
+        }
+        if window.is_key_pressed(FRAME_ADVANCE_KEY, KeyRepeat::Yes) {
+            pacer.advance_frame();
+        }
+        if window.is_key_pressed(SLOW_MOTION_KEY, KeyRepeat::No) {
+            pacer.slow_motion = !pacer.slow_motion;
+            log(if pacer.slow_motion { "[PACING] Slow motion on" } else { "[PACING] Slow motion off" });
+        }
+
+        // same as state loads, going back would desync the movie
+        let movie_running = recording.is_some() || playback.is_some();
+        if movie_running && window.is_key_pressed(REWIND_KEY, KeyRepeat::No) {
+            log("[REWIND] Can't rewind during a movie");
+        }
+        let rewinding = !movie_running && window.is_key_down(REWIND_KEY);
+
+        let turbo = window.is_key_down(TURBO_KEY);
+        let frames_to_run = pacer.frames_to_run(turbo);
+        // only this time around, while frame_interrupted stays set until the frame is done
+        let mut hit_breakpoint = false;
+
+        for _ in 0..frames_to_run {
+            if let Some(animation) = boot.as_mut() {
+                if animation.step_frame(&mut cpu) {
+                    boot = None;
+                }
+            } else if rewinding {
+                // one snapshot per frame while the key is held, stays on the oldest one when it runs out
+                if let Some(state) = rewind.pop() {
+                    if let Err(error) = savestate::load_state(&mut cpu, &rom, &state) {
+                        log(&format!("[REWIND] Couldn't restore snapshot: {error}"));
+                    }
+                }
+            } else {
+                if !frame_interrupted {
+                    let input = match playback.as_mut().map(MoviePlayer::next_input) {
+                        Some(Some(input)) => input,
+                        Some(None) => {
+                            playback = None;
+                            log("[MOVIE] Playback over");
+                            read_joypad(&window)
+                        },
+                        None => read_joypad(&window),
+                    };
+
+                    cpu.mem_bus.joypad.pressed = input;
+                    if let Some(movie) = recording.as_mut() {
+                        movie.inputs.push(input);
+                    }
+                }
+
+                // without breakpoints, there's no need to check every instruction
+                let result = if debugger.is_active(&cpu) {
+                    cpu.run_frame_until(|cpu| debugger.should_break(cpu))
+                } else {
+                    cpu.run_frame().map(|()| false)
+                };
+
+                // the CPU is locked from here, the screen stays as it is until a reset or a state load
+                frame_interrupted = result.unwrap_or_else(|error| {
+                    cpu.report_error(&error);
+                    false
+                });
+
+                if frame_interrupted {
+                    hit_breakpoint = true;
+                    break;
+                }
+                rewind.on_frame(&cpu, &rom);
+            }
+        }
+
+        // whatever the frames produced, paused or rewinding means nothing new
+        #[cfg(feature = "audio")]
+        if let Some(audio) = &audio {
+            audio.queue(&cpu.mem_bus.apu.take_samples());
+        }
+
+        if show_tileset {
+            draw_vram_to_frame(&cpu.mem_bus.gpu, &mut frame);
+        } else {
+            cpu.mem_bus.render_frame();
+            draw_screen_to_frame(&cpu.mem_bus.gpu, &mut frame);
+        }
+
+        if window.is_key_pressed(SCREENSHOT_KEY, KeyRepeat::No) {
+            take_screenshot(&cpu.mem_bus.gpu.screen, capture_dir, scale);
+        }
+
+        if window.is_key_pressed(RECORD_VIDEO_KEY, KeyRepeat::No) {
+            if let Some(recorder) = gif_recording.take() {
+                match recorder.finish() {
+                    Ok(frames) => log(&format!("[CAPTURE] GIF recording stopped after {frames} frames")),
+                    Err(error) => log(&format!("[CAPTURE] Couldn't finish the GIF: {error}")),
+                }
+            } else if let Some(recorder) = raw_recording.take() {
+                match recorder.finish() {
+                    Ok(frames) => log(&format!("[CAPTURE] Raw recording stopped after {frames} frames")),
+                    Err(error) => log(&format!("[CAPTURE] Couldn't finish the raw dump: {error}")),
+                }
+            } else if window.is_key_down(Key::LeftShift) {
+                let path = capture::timestamped_path(capture_dir, "recording", "rgb");
+                match RawRecorder::new(&path) {
+                    Ok(recorder) => {
+                        raw_recording = Some(recorder);
+                        log(&format!("[CAPTURE] Recording raw frames, encode with:\n{}", RawRecorder::encoder_hint(&path)));
+                    },
+                    Err(error) => log(&format!("[CAPTURE] Couldn't start recording: {error}")),
+                }
+            } else {
+                let path = capture::timestamped_path(capture_dir, "recording", "gif");
+                match GifRecorder::new(&path) {
+                    Ok(recorder) => {
+                        gif_recording = Some(recorder);
+                        log(&format!("[CAPTURE] Recording GIF to {}", path.display()));
+                    },
+                    Err(error) => log(&format!("[CAPTURE] Couldn't start recording: {error}")),
+                }
+            }
+        }
+
+        // a paused game doesn't make new frames
+        let new_frame = frames_to_run > 0;
+
+        if let Some(recorder) = gif_recording.as_mut().filter(|_| new_frame) {
+            if let Err(error) = recorder.add_frame(&cpu.mem_bus.gpu.screen) {
+                log(&format!("[CAPTURE] GIF recording stopped: {error}"));
+                gif_recording = None;
+            }
+        }
+        if let Some(recorder) = raw_recording.as_mut().filter(|_| new_frame) {
+            if let Err(error) = recorder.add_frame(&cpu.mem_bus.gpu.screen) {
+                log(&format!("[CAPTURE] Raw recording stopped: {error}"));
+                raw_recording = None;
+            }
+        }
+
+        // the window may have been resized since the last frame
+        let window_size = window.get_size();
+        framebuffer.resize(window_size.0 * window_size.1, 0);
+        let filter_factor = post_processor.filter.factor();
+        let (image, image_size) = post_processor.process(&frame, (LCD_WIDTH, LCD_HEIGHT));
+        presentation::present(image, image_size, filter_factor, &mut framebuffer, window_size, scale_mode);
 
         // Update the window with the pixel buffer
-        window.update_with_buffer(&framebuffer, SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
+        window.update_with_buffer(&framebuffer, window_size.0, window_size.1).unwrap();
+
+        if hit_breakpoint || window.is_key_pressed(DEBUGGER_KEY, KeyRepeat::No) {
+            log(&format!("[DEBUGGER] Stopped at 0x{:04X}, `help` for the commands", cpu.pc));
+
+            if let DebuggerAction::Quit = debugger.prompt(&mut cpu) {
+                break;
+            }
+        }
+
+        pacer.wait(turbo);
     }
 }
 
-fn draw_vram_to_framebuffer(gpu: &gpu::GPU, framebuffer: &mut [u32]) {
+// saves the frame twice: as is, and scaled like the window
+fn take_screenshot(screen: &gpu::Screen, capture_dir: &Path, scale: usize) {
+    let path = capture::timestamped_path(capture_dir, "screenshot", "png");
+    let scaled_path = path.with_extension(format!("x{scale}.png"));
+
+    let result = capture::write_png(&path, screen, 1)
+        .and_then(|_| capture::write_png(&scaled_path, screen, scale));
+
+    match result {
+        Ok(()) => log(&format!("[CAPTURE] Screenshot saved to {} and {}", path.display(), scaled_path.display())),
+        Err(error) => log(&format!("[CAPTURE] Couldn't save screenshot: {error}")),
+    }
+}
+
+fn read_joypad(window: &Window) -> u8 {
+    JOYPAD_KEYS.iter()
+        .filter(|(key, _)| window.is_key_down(*key))
+        .fold(0, |pressed, (_, button)| pressed | button)
+}
+
+fn draw_screen_to_frame(gpu: &gpu::GPU, frame: &mut [u32]) {
+    for (pixel, color) in gpu.screen.iter().zip(frame.iter_mut()) {
+        *color = pixel.to_rgb();
+    }
+}
+
+fn draw_vram_to_frame(gpu: &gpu::GPU, frame: &mut [u32]) {
     for tile_y in 0..18 { // 18 tiles fit vertically in 144px
         for tile_x in 0..20 { // 20 tiles fit horizontally in 160px
             let tile = gpu.tileset[(tile_y * 20 + tile_x) % 384]; // Get a tile
@@ -32,19 +390,14 @@ fn draw_vram_to_framebuffer(gpu: &gpu::GPU, framebuffer: &mut [u32]) {
             for row in 0..8 {
                 for col in 0..8 {
                     let pixel = tile[row][col]; // Get pixel value
-                    let color = match pixel {
-                        TilePixelValue::Black => 0x000000,  // Black
-                        TilePixelValue::DarkGray => 0x555555,  // Dark Gray
-                        TilePixelValue::LightGray => 0xAAAAAA,  // Light Gray
-                        TilePixelValue::White => 0xFFFFFF, // White
-                    };
+                    let color = pixel.to_rgb();
 
                     let x = tile_x * 8 + col;
                     let y = tile_y * 8 + row;
-                    let index = y * SCREEN_WIDTH + x;
-                    framebuffer[index] = color;
+                    let index = y * LCD_WIDTH + x;
+                    frame[index] = color;
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
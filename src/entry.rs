@@ -1,54 +1,152 @@
-use crate::{cpu::CPU, emu_window, rom::ROM, utils::{log, reset_logs}, playground::play};
+use std::fs;
+
+use crate::{boot::BootAnimation, cli, config::Config, cpu::CPU, emu_window, logger, rom::ROM, symbols::Symbols, utils::{log, panic_log}};
 
 const TETRIS_ROM_PATH: &str = "tests/tetris.gb";
 const BAKERY_ROM_PATH: &str = "tests/bakery_v1.0.3.gb";
 const TEST_ROM_PATH: &str = "tests/cpu_instrs/cpu_instrs.gb";
 
 
+pub struct LaunchOptions {
+    pub rom_path: String,
+    pub boot_rom_path: Option<String>,
+    pub patch_path: Option<String>,
+    pub fix_checksums: bool,
+}
+
+impl LaunchOptions {
+    /**
+        crusty-boy [rom path] [--boot-rom <path>] [--patch <path>] [--fix-checksums]
+     */
+    pub fn from_args(args: &[String]) -> Self {
+        let mut options = LaunchOptions {
+            rom_path: String::from(TETRIS_ROM_PATH),
+            boot_rom_path: None,
+            patch_path: None,
+            fix_checksums: false,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--boot-rom" => options.boot_rom_path = args.next().cloned(),
+                "--patch" => options.patch_path = args.next().cloned(),
+                "--fix-checksums" => options.fix_checksums = true,
+                _ => options.rom_path = arg.clone(),
+            }
+        }
+
+        options
+    }
+}
+
+
 pub fn setup() -> () {
-    reset_logs();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    // the logging options go first, for everything after to log the right way
+    let config = Config::load();
+    let mut log_settings = config.log.clone();
+    if let Err(message) = log_settings.take_args(&mut args) {
+        log(&format!("[LOG] {message}"));
+        exit(1);
+    }
+    if let Err(error) = logger::init(&log_settings) {
+        log(&format!("[LOG] Couldn't open {}: {error}", log_settings.path.display()));
+    }
+
+    if let Some((_, main)) = args.first().and_then(|arg| cli::SUBCOMMANDS.iter().find(|(name, _)| name == arg)) {
+        exit(main(&args[1..]));
+    }
+
+    let options = LaunchOptions::from_args(&args);
 
     let mut cpu = CPU::new();
-    let rom: ROM = ROM::read_rom(TETRIS_ROM_PATH);
+    let (rom, patch_report) = ROM::read_rom_with_patch(&options.rom_path, options.patch_path.as_deref(), options.fix_checksums)
+        .unwrap_or_else(|error| panic_log(&format!("Couldn't load \"{}\": {error}", options.rom_path)));
 
-    cpu.pc = 0x100; // TODO: don't always skip the bootrom!
+    if let Some(report) = patch_report {
+        log(&format!("[PATCH] {report}"));
+    }
 
     log(&format!(
         "\
         ROM Name: \"{}\"\n\
         ROM Size: {}KB\n\
+        RAM Size: {}KB\n\
         ROM Type: \"{}\"\n\
         ROM Region: \"{}\"\n\
         ROM Version: \"{}\"\n\
+        Licensee: {}\n\
+        Manufacturer Code: {}\n\
+        CGB Flag: {:?}\n\
+        SGB Flag: {:?}\n\
         Calculated Header Checksum: 0x{:04X}\n\
         Original Header Checksum: 0x{:04X}\n\
+        Calculated Global Checksum: 0x{:04X}\n\
         \n\
         CPU PC: 0x{:04X} | {}\n\
         ",
 
         rom.name,
         rom.size,
-        ROM::get_cartridge_type_name(rom.cartridge_type),
-        rom.region, rom.version,
+        rom.ram_size / 1024,
+        rom.cartridge_type.name(),
+        rom.region.name(), rom.version,
+        rom.licensee,
+        rom.manufacturer_code.as_deref().unwrap_or("NONE"),
+        rom.cgb_flag, rom.sgb_flag,
         rom.header_checksum,
         rom.data[0x14D],
+        rom.global_checksum,
         cpu.pc, cpu.pc
     ));
 
+    for warning in &rom.warnings {
+        log(&format!("[ROM] Warning: {warning}"));
+    }
+
     log("--------------------\n");
 
-    cpu.mem_bus.memory = rom.data.clone().try_into().expect("Couldn't convert ROM bytes vector to an array");
+    cpu.mem_bus.load_rom(&rom.data);
     cpu.rom_size = rom.size;
+    cpu.symbols = Symbols::for_rom(&options.rom_path);
+
+    // without a boot ROM (or one we can't read), we play our own logo animation
+    let boot_rom = options.boot_rom_path.and_then(|boot_rom_path| match fs::read(&boot_rom_path) {
+        Ok(boot_rom) => Some(boot_rom),
+        Err(error) => {
+            log(&format!("[BOOT] Couldn't read boot ROM \"{boot_rom_path}\": {error}, using the built-in animation"));
+            None
+        },
+    });
+
+    let boot = match boot_rom {
+        Some(boot_rom) => {
+            cpu.mem_bus.load_boot_rom(boot_rom);
+            cpu.pc = 0x0000;
+            None
+        },
+        None => Some(BootAnimation::new(&mut cpu)),
+    };
 
-    play();
+    emu_window::window_life(cpu, rom, boot, &config);
+    logger::flush();
+}
 
-    //emu_window::window_life(cpu);
+// std::process::exit skips destructors, so the log has to be flushed first
+fn exit(code: i32) -> ! {
+    logger::flush();
+    std::process::exit(code);
 }
 
 #[allow(unused)]
 pub fn process(mut cpu: CPU, rom_file: ROM) -> () {
     loop {
-        cpu.step();
+        if let Err(error) = cpu.step() {
+            cpu.report_error(&error);
+            break;
+        }
 
         if cpu.pc >= rom_file.size as u16 {
             break;
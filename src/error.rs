@@ -0,0 +1,37 @@
+use std::fmt;
+
+/*
+    What can go wrong while emulating, returned by CPU::step and everything under it
+    instead of panicking, so a ROM we can't run doesn't take the whole emulator down.
+
+    An illegal opcode is the game's doing (real hardware locks up on those), the rest
+    are emulator bugs. Either way the CPU locks, and the frontend reports it.
+*/
+
+#[derive(Clone, Debug)]
+pub enum EmuError {
+    // one of the 11 opcodes the SM83 doesn't have (0xD3, 0xDB, 0xDD, 0xE3, ...)
+    IllegalOpcode { opcode: u8, address: u16 },
+    // a real opcode the CPU doesn't emulate yet
+    UnknownOpcode { opcode: u8, prefixed: bool, address: u16 },
+    // an instruction given an operand its implementation can't take
+    InvalidOperand(String),
+    // from GPU::get_tile, which nothing calls yet
+    #[allow(unused)]
+    InvalidTileIndex(usize),
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::IllegalOpcode { opcode, address } => write!(f, "illegal opcode 0x{opcode:02X} at 0x{address:04X}, the CPU locked up"),
+            EmuError::UnknownOpcode { opcode, prefixed, address } => {
+                write!(f, "'0x{}{opcode:02X}' not recognized as an instruction at 0x{address:04X}", if *prefixed { "CB" } else { "" })
+            },
+            EmuError::InvalidOperand(message) => write!(f, "{message}"),
+            EmuError::InvalidTileIndex(index) => write!(f, "incorrect tile index {index} (must be 0-255)"),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
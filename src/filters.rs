@@ -0,0 +1,235 @@
+use std::{fmt, str::FromStr};
+
+/*
+    Software post-processing, between the emulated frame and the window:
+    LCD ghosting (frame blending) first, then an optional upscaling filter.
+    Everything works on 0xRRGGBB pixels.
+*/
+
+const GRID_CELL: usize = 3;
+// how much darker the gaps between the LCD's dots are, out of 256
+const GRID_DARKNESS: u32 = 96;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    None,
+    // the DMG's dot matrix: every pixel becomes a 3x3 dot with darker edges
+    LcdGrid,
+    // EPX/AdvMAME: smooths diagonal edges without blurring
+    Scale2x,
+    Scale3x,
+}
+
+impl Filter {
+    pub fn next(self) -> Self {
+        match self {
+            Filter::None => Filter::LcdGrid,
+            Filter::LcdGrid => Filter::Scale2x,
+            Filter::Scale2x => Filter::Scale3x,
+            Filter::Scale3x => Filter::None,
+        }
+    }
+
+    // how many output pixels per input pixel, on each axis
+    pub fn factor(self) -> usize {
+        match self {
+            Filter::None => 1,
+            Filter::LcdGrid => GRID_CELL,
+            Filter::Scale2x => 2,
+            Filter::Scale3x => 3,
+        }
+    }
+}
+
+pub struct PostProcessor {
+    pub filter: Filter,
+    // some games flicker sprites on purpose, counting on the LCD's slow response to make them look transparent
+    pub ghosting: bool,
+
+    previous: Vec<u32>,
+    blended: Vec<u32>,
+    output: Vec<u32>,
+}
+
+impl PostProcessor {
+    pub fn new(filter: Filter, ghosting: bool) -> Self {
+        PostProcessor {
+            filter,
+            ghosting,
+            previous: Vec::new(),
+            blended: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    // returns the processed image and its size
+    pub fn process(&mut self, frame: &[u32], (width, height): (usize, usize)) -> (&[u32], (usize, usize)) {
+        let source = if self.ghosting {
+            if self.previous.len() != frame.len() {
+                self.previous = frame.to_vec();
+            }
+
+            self.blended.clear();
+            self.blended.extend(frame.iter().zip(&self.previous).map(|(current, previous)| blend(*current, *previous)));
+            self.previous.copy_from_slice(&self.blended);
+
+            &self.blended
+        } else {
+            self.previous.clear();
+            frame
+        };
+
+        let factor = self.filter.factor();
+        self.output.resize(width * factor * height * factor, 0);
+
+        match self.filter {
+            Filter::None => self.output.copy_from_slice(source),
+            Filter::LcdGrid => lcd_grid(source, (width, height), &mut self.output),
+            Filter::Scale2x => scale2x(source, (width, height), &mut self.output),
+            Filter::Scale3x => scale3x(source, (width, height), &mut self.output),
+        }
+
+        (&self.output, (width * factor, height * factor))
+    }
+}
+
+
+// average of two colors, channel by channel
+fn blend(a: u32, b: u32) -> u32 {
+    // the last term puts back the low bits lost by the shifts, so white stays white
+    ((a & 0xFEFEFE) >> 1) + ((b & 0xFEFEFE) >> 1) + (a & b & 0x010101)
+}
+
+fn darken(color: u32, amount: u32) -> u32 {
+    let scale = |channel: u32| channel * (256 - amount) / 256;
+
+    (scale((color >> 16) & 0xFF) << 16) | (scale((color >> 8) & 0xFF) << 8) | scale(color & 0xFF)
+}
+
+fn lcd_grid(source: &[u32], (width, height): (usize, usize), output: &mut [u32]) {
+    let output_width = width * GRID_CELL;
+
+    for y in 0..height {
+        for x in 0..width {
+            let color = source[y * width + x];
+            let gap = darken(color, GRID_DARKNESS);
+
+            for cell_y in 0..GRID_CELL {
+                for cell_x in 0..GRID_CELL {
+                    // right column and bottom row of the cell are the gaps
+                    let is_gap = cell_x == GRID_CELL - 1 || cell_y == GRID_CELL - 1;
+                    output[(y * GRID_CELL + cell_y) * output_width + x * GRID_CELL + cell_x] = if is_gap { gap } else { color };
+                }
+            }
+        }
+    }
+}
+
+// the pixel at (x + dx, y + dy), edges repeated
+fn neighbour(source: &[u32], (width, height): (usize, usize), x: usize, y: usize, dx: isize, dy: isize) -> u32 {
+    let x = (x as isize + dx).clamp(0, width as isize - 1) as usize;
+    let y = (y as isize + dy).clamp(0, height as isize - 1) as usize;
+    source[y * width + x]
+}
+
+/**
+    Scale2x, on the neighbours:
+          B
+        D E F  ->  E0 E1
+          H        E2 E3
+ */
+fn scale2x(source: &[u32], size: (usize, usize), output: &mut [u32]) {
+    let (width, height) = size;
+    let output_width = width * 2;
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = |dx, dy| neighbour(source, size, x, y, dx, dy);
+            let (b, d, e, f, h) = (pixel(0, -1), pixel(-1, 0), pixel(0, 0), pixel(1, 0), pixel(0, 1));
+
+            let (e0, e1, e2, e3) = if b != h && d != f {
+                (
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                )
+            } else {
+                (e, e, e, e)
+            };
+
+            let top = (y * 2) * output_width + x * 2;
+            let bottom = top + output_width;
+            output[top] = e0;
+            output[top + 1] = e1;
+            output[bottom] = e2;
+            output[bottom + 1] = e3;
+        }
+    }
+}
+
+/**
+    Scale3x, on the neighbours:
+        A B C      E0 E1 E2
+        D E F  ->  E3 E4 E5
+        G H I      E6 E7 E8
+ */
+fn scale3x(source: &[u32], size: (usize, usize), output: &mut [u32]) {
+    let (width, height) = size;
+    let output_width = width * 3;
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = |dx, dy| neighbour(source, size, x, y, dx, dy);
+            let (a, b, c) = (pixel(-1, -1), pixel(0, -1), pixel(1, -1));
+            let (d, e, f) = (pixel(-1, 0), pixel(0, 0), pixel(1, 0));
+            let (g, h, i) = (pixel(-1, 1), pixel(0, 1), pixel(1, 1));
+
+            let cell = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+
+            for (index, color) in cell.iter().enumerate() {
+                output[(y * 3 + index / 3) * output_width + x * 3 + index % 3] = *color;
+            }
+        }
+    }
+}
+
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "none" => Ok(Filter::None),
+            "lcd_grid" => Ok(Filter::LcdGrid),
+            "scale2x" => Ok(Filter::Scale2x),
+            "scale3x" => Ok(Filter::Scale3x),
+            _ => Err(format!("unknown filter \"{value}\"")),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::None => write!(f, "none"),
+            Filter::LcdGrid => write!(f, "lcd_grid"),
+            Filter::Scale2x => write!(f, "scale2x"),
+            Filter::Scale3x => write!(f, "scale3x"),
+        }
+    }
+}
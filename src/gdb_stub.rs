@@ -0,0 +1,833 @@
+use std::{io::{self, BufReader, ErrorKind, Read, Write}, net::{TcpListener, TcpStream}, ops::Range};
+
+use crate::{boot, cli::CliError, cpu::CPU, registers::FlagsRegister, rom::ROM, utils::{self, log}, watchpoints::{WatchKind, Watchpoint, Watchpoints}};
+
+/*
+    GDB remote serial protocol server, to drive the emulator from GDB (or anything
+    speaking RSP) over TCP:
+
+        crusty-boy gdb <rom> [--port N]
+        (gdb) target remote :2345
+
+    The emulation runs without a window, as fast as it can, and only while the
+    debugger says so (continue/step). Ctrl-C in GDB stops it.
+
+    Registers, in the order of the target description: A F B C D E H L (8 bits), SP PC (16 bits).
+    GDB has no SM83 architecture, so the description doesn't name one; tools that
+    read the XML still get the registers' names, sizes and the flags' bits.
+*/
+
+const DEFAULT_PORT: u16 = 2345;
+// instructions between two checks for a Ctrl-C from the debugger
+const INTERRUPT_POLL_INTERVAL: u32 = 4096;
+const REGISTER_COUNT: usize = 10;
+// stop signals
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.crustyboy.sm83.core">
+    <flags id="sm83_flags" size="1">
+      <field name="C" start="4" end="4"/>
+      <field name="H" start="5" end="5"/>
+      <field name="N" start="6" end="6"/>
+      <field name="Z" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="f" bitsize="8" type="sm83_flags"/>
+    <reg name="b" bitsize="8" type="uint8"/>
+    <reg name="c" bitsize="8" type="uint8"/>
+    <reg name="d" bitsize="8" type="uint8"/>
+    <reg name="e" bitsize="8" type="uint8"/>
+    <reg name="h" bitsize="8" type="uint8"/>
+    <reg name="l" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// why the emulation stopped, sent back as a stop reply
+enum StopReason {
+    Step,
+    Breakpoint,
+    Watchpoint { kind: WatchKind, address: u16 },
+    Interrupted,
+    // the CPU locked up, PC is on the instruction that did it
+    Error,
+}
+
+// what a packet asks for, besides a plain reply
+enum Action {
+    Reply(String),
+    Resume { single_step: bool },
+    Detach,
+    Kill,
+}
+
+/**
+    What the stub talks to the debugger over, a TcpStream outside of the tests.
+    Reads block, except for the Ctrl-C check while the emulation runs.
+ */
+pub trait Connection: Read + Write {
+    // a byte the debugger already sent, None instead of waiting for one
+    fn poll_byte(&mut self) -> io::Result<Option<u8>>;
+}
+
+pub struct GdbStub<C: Connection> {
+    connection: BufReader<C>,
+    // after QStartNoAckMode, neither side sends +/- anymore
+    no_ack: bool,
+    breakpoints: Vec<u16>,
+}
+
+pub fn main(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(rom_path, port)| {
+        let rom = ROM::read_rom(&rom_path)?;
+        let mut cpu = boot::power_on(&rom);
+        Ok(serve(&mut cpu, port)?)
+    });
+
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            log(&format!("[GDB] {error}"));
+            1
+        },
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<(String, u16), CliError> {
+    let mut rom_path = None;
+    let mut port = DEFAULT_PORT;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args.next()
+                    .and_then(|value| utils::parse_number(value))
+                    .filter(|port| *port <= u16::MAX as u64)
+                    .ok_or(CliError::InvalidArgument(String::from("--port needs a port number")))? as u16;
+            },
+            _ if arg.starts_with("--") => return Err(CliError::InvalidArgument(format!("unknown option {arg}"))),
+            _ => rom_path = Some(arg.clone()),
+        }
+    }
+
+    Ok((rom_path.ok_or(CliError::InvalidArgument(String::from("no ROM given")))?, port))
+}
+
+/**
+    Waits for one debugger on localhost:`port` and serves it until it detaches,
+    kills the target or disconnects.
+ */
+pub fn serve(cpu: &mut CPU, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    log(&format!("[GDB] Waiting for a debugger on 127.0.0.1:{port} (target remote :{port})"));
+
+    let (stream, address) = listener.accept()?;
+    log(&format!("[GDB] {address} attached"));
+
+    stream.set_nodelay(true)?;
+
+    let result = match GdbStub::new(stream).run(cpu) {
+        // hanging up without detaching is fine too
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(()),
+        result => result,
+    };
+
+    // don't leave our watchpoints behind for whoever uses the CPU next
+    cpu.mem_bus.watchpoints = None;
+    log("[GDB] Debugger detached");
+
+    result
+}
+
+impl Connection for TcpStream {
+    fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.read(&mut byte);
+        self.set_nonblocking(false)?;
+
+        match result {
+            Ok(0) => Err(io::Error::from(ErrorKind::UnexpectedEof)),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(connection: C) -> Self {
+        GdbStub {
+            connection: BufReader::new(connection),
+            no_ack: false,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(cpu, &packet) {
+                Action::Reply(reply) => self.send_packet(&reply)?,
+                Action::Resume { single_step } => {
+                    let reason = self.resume(cpu, single_step)?;
+                    self.send_packet(&reason.reply())?;
+                },
+                Action::Detach => {
+                    self.send_packet("OK")?;
+                    break;
+                },
+                // no reply expected
+                Action::Kill => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, cpu: &mut CPU, packet: &str) -> Action {
+        let Some(command) = packet.chars().next() else {
+            return Action::Reply(String::new());
+        };
+        let arguments = &packet[command.len_utf8()..];
+
+        let reply = match command {
+            // the target starts out stopped, like after a step
+            '?' => StopReason::Step.reply(),
+
+            'g' => (0..REGISTER_COUNT).map(|index| encode_hex(&read_register(cpu, index))).collect(),
+            'G' => match decode_hex(arguments) {
+                Some(bytes) if bytes.len() == register_offset(REGISTER_COUNT) => {
+                    for index in 0..REGISTER_COUNT {
+                        write_register(cpu, index, &bytes[register_offset(index)..register_offset(index + 1)]);
+                    }
+                    ok_reply()
+                },
+                _ => error_reply(),
+            },
+            'p' => match usize::from_str_radix(arguments, 16) {
+                Ok(index) if index < REGISTER_COUNT => encode_hex(&read_register(cpu, index)),
+                _ => error_reply(),
+            },
+            'P' => match parse_register_write(arguments) {
+                Some((index, bytes)) => {
+                    write_register(cpu, index, &bytes);
+                    ok_reply()
+                },
+                None => error_reply(),
+            },
+
+            'm' => match parse_range(arguments).and_then(|(address, length)| memory_range(address, length)) {
+                Some(addresses) => addresses.map(|address| format!("{:02x}", cpu.mem_bus.peek_byte(address as u16))).collect(),
+                None => error_reply(),
+            },
+            'M' => match parse_memory_write(arguments) {
+                Some((addresses, bytes)) => {
+                    for (address, byte) in addresses.zip(bytes) {
+                        cpu.mem_bus.write_byte(address as u16, byte);
+                    }
+                    // our own writes aren't the program's
+                    if let Some(watchpoints) = &cpu.mem_bus.watchpoints {
+                        watchpoints.take_hits();
+                    }
+                    ok_reply()
+                },
+                None => error_reply(),
+            },
+
+            'Z' => self.insert_point(cpu, arguments),
+            'z' => self.remove_point(cpu, arguments),
+
+            // resuming at another address isn't supported, GDB sets PC with P instead
+            'c' => return Action::Resume { single_step: false },
+            's' => return Action::Resume { single_step: true },
+            'D' => return Action::Detach,
+            'k' => return Action::Kill,
+
+            // there's only one thread
+            'H' | 'T' => ok_reply(),
+
+            _ => self.handle_query(packet),
+        };
+
+        Action::Reply(reply)
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return String::from("PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+");
+        }
+
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_range(request) {
+                Some((offset, length)) => {
+                    let chunk = TARGET_XML.get(offset..).unwrap_or("");
+                    let chunk = &chunk[..chunk.len().min(length)];
+                    // 'l' for the last chunk, 'm' for more to come
+                    format!("{}{chunk}", if offset + chunk.len() >= TARGET_XML.len() { 'l' } else { 'm' })
+                },
+                None => error_reply(),
+            };
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                ok_reply()
+            },
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            // anything else isn't supported, which is an empty reply
+            _ => String::new(),
+        }
+    }
+
+    /**
+        Z<type>,<address>,<kind>: 0 is a software breakpoint, 1 a hardware one (same thing
+        here), 2 a write watchpoint, 3 a read one and 4 both. For watchpoints, kind is the length.
+     */
+    fn insert_point(&mut self, cpu: &mut CPU, arguments: &str) -> String {
+        let Some((point_type, address, length)) = parse_point(arguments) else {
+            return error_reply();
+        };
+
+        match point_type {
+            0 | 1 => {
+                if !self.breakpoints.contains(&address) {
+                    self.breakpoints.push(address);
+                }
+            },
+            2..=4 => {
+                let watchpoints = cpu.mem_bus.watchpoints.get_or_insert_with(|| Box::new(Watchpoints::new()));
+
+                for kind in watch_kinds(point_type) {
+                    watchpoints.list.push(Watchpoint { start: address, end: watch_end(address, length), kind, log_only: false });
+                }
+            },
+            _ => return String::new(),
+        }
+
+        ok_reply()
+    }
+
+    fn remove_point(&mut self, cpu: &mut CPU, arguments: &str) -> String {
+        let Some((point_type, address, length)) = parse_point(arguments) else {
+            return error_reply();
+        };
+
+        match point_type {
+            0 | 1 => self.breakpoints.retain(|breakpoint| *breakpoint != address),
+            2..=4 => {
+                if let Some(watchpoints) = cpu.mem_bus.watchpoints.as_mut() {
+                    let kinds = watch_kinds(point_type);
+                    watchpoints.list.retain(|watchpoint| {
+                        !(watchpoint.start == address && watchpoint.end == watch_end(address, length) && kinds.contains(&watchpoint.kind))
+                    });
+
+                    // back to no checks at all
+                    if watchpoints.list.is_empty() {
+                        cpu.mem_bus.watchpoints = None;
+                    }
+                }
+            },
+            _ => return String::new(),
+        }
+
+        ok_reply()
+    }
+
+    // runs until a breakpoint, a watchpoint, a Ctrl-C or the end of the single step
+    fn resume(&mut self, cpu: &mut CPU, single_step: bool) -> io::Result<StopReason> {
+        let mut instructions: u32 = 0;
+
+        // the instruction at PC always runs, so continuing from a breakpoint doesn't stop right away
+        loop {
+            if let Err(error) = cpu.step() {
+                cpu.report_error(&error);
+                return Ok(StopReason::Error);
+            }
+
+            if let Some(watchpoints) = &cpu.mem_bus.watchpoints {
+                if let Some(hit) = watchpoints.take_hits().into_iter().find(|hit| !hit.log_only) {
+                    return Ok(StopReason::Watchpoint { kind: hit.kind, address: hit.address });
+                }
+            }
+
+            if single_step {
+                return Ok(StopReason::Step);
+            }
+            if self.breakpoints.contains(&cpu.pc) {
+                return Ok(StopReason::Breakpoint);
+            }
+
+            instructions = instructions.wrapping_add(1);
+            if instructions.is_multiple_of(INTERRUPT_POLL_INTERVAL) && self.interrupted()? {
+                return Ok(StopReason::Interrupted);
+            }
+        }
+    }
+
+    // whether GDB sent a Ctrl-C (a lone 0x03 byte) while the emulation was running
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if !self.connection.buffer().is_empty() {
+            return Ok(self.read_byte()? == 0x03);
+        }
+
+        Ok(self.connection.get_mut().poll_byte()? == Some(0x03))
+    }
+
+    // `$<data>#<checksum>`, None once the debugger is gone
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // skips the acks and stray Ctrl-Cs between packets
+            match self.read_byte() {
+                Ok(b'$') => {},
+                Ok(_) => continue,
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(error) => return Err(error),
+            }
+
+            let mut data = Vec::new();
+            // of the bytes as sent, escapes included
+            let mut sum: u8 = 0;
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    // the next byte is escaped
+                    b'}' => {
+                        let escaped = self.read_byte()?;
+                        sum = sum.wrapping_add(b'}').wrapping_add(escaped);
+                        data.push(escaped ^ 0x20);
+                    },
+                    byte => {
+                        sum = sum.wrapping_add(byte);
+                        data.push(byte);
+                    },
+                }
+            }
+
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let valid = std::str::from_utf8(&checksum).ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                .is_some_and(|checksum| checksum == sum);
+
+            if !self.no_ack {
+                self.connection.get_mut().write_all(if valid { b"+" } else { b"-" })?;
+            }
+
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let mut escaped = Vec::with_capacity(data.len());
+        for byte in data.bytes() {
+            match byte {
+                b'$' | b'#' | b'}' | b'*' => escaped.extend_from_slice(&[b'}', byte ^ 0x20]),
+                _ => escaped.push(byte),
+            }
+        }
+
+        let mut packet = Vec::with_capacity(escaped.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", packet_checksum(&escaped)).as_bytes());
+
+        loop {
+            self.connection.get_mut().write_all(&packet)?;
+
+            if self.no_ack {
+                return Ok(());
+            }
+
+            // sent again until acknowledged
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.connection.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+
+impl StopReason {
+    fn reply(&self) -> String {
+        match self {
+            StopReason::Step => format!("S{SIGTRAP:02x}"),
+            StopReason::Breakpoint => format!("T{SIGTRAP:02x}swbreak:;"),
+            StopReason::Watchpoint { kind, address } => {
+                let name = if *kind == WatchKind::Read { "rwatch" } else { "watch" };
+                format!("T{SIGTRAP:02x}{name}:{address:04x};")
+            },
+            StopReason::Interrupted => format!("S{SIGINT:02x}"),
+            StopReason::Error => format!("S{SIGILL:02x}"),
+        }
+    }
+}
+
+// as little-endian bytes, in the target description's order
+fn read_register(cpu: &CPU, index: usize) -> Vec<u8> {
+    let regs = &cpu.regs;
+
+    match index {
+        0 => vec![regs.a],
+        1 => vec![regs.flags.into_u8()],
+        2 => vec![regs.b],
+        3 => vec![regs.c],
+        4 => vec![regs.d],
+        5 => vec![regs.e],
+        6 => vec![regs.h],
+        7 => vec![regs.l],
+        8 => regs.sp.to_le_bytes().to_vec(),
+        _ => cpu.pc.to_le_bytes().to_vec(),
+    }
+}
+
+fn write_register(cpu: &mut CPU, index: usize, bytes: &[u8]) {
+    let regs = &mut cpu.regs;
+    let byte = bytes[0];
+    let word = || u16::from_le_bytes([bytes[0], bytes[1]]);
+
+    match index {
+        0 => regs.a = byte,
+        1 => regs.flags = FlagsRegister::from_u8(byte),
+        2 => regs.b = byte,
+        3 => regs.c = byte,
+        4 => regs.d = byte,
+        5 => regs.e = byte,
+        6 => regs.h = byte,
+        7 => regs.l = byte,
+        8 => regs.sp = word(),
+        _ => cpu.pc = word(),
+    }
+}
+
+fn register_size(index: usize) -> usize {
+    if index < 8 { 1 } else { 2 }
+}
+
+// where register `index` starts in a `g` packet's bytes
+fn register_offset(index: usize) -> usize {
+    (0..index).map(register_size).sum()
+}
+
+// P<index>=<value>
+fn parse_register_write(arguments: &str) -> Option<(usize, Vec<u8>)> {
+    let (index, value) = arguments.split_once('=')?;
+    let index = usize::from_str_radix(index, 16).ok().filter(|index| *index < REGISTER_COUNT)?;
+    let bytes = decode_hex(value).filter(|bytes| bytes.len() == register_size(index))?;
+
+    Some((index, bytes))
+}
+
+// <address>,<length>
+fn parse_range(arguments: &str) -> Option<(usize, usize)> {
+    let (address, length) = arguments.split_once(',')?;
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+// M<address>,<length>:<bytes>
+fn parse_memory_write(arguments: &str) -> Option<(Range<usize>, Vec<u8>)> {
+    let (range, data) = arguments.split_once(':')?;
+    let (address, length) = parse_range(range)?;
+    let bytes = decode_hex(data).filter(|bytes| bytes.len() == length)?;
+
+    Some((memory_range(address, length)?, bytes))
+}
+
+// the addresses of a memory access, if they're all on the bus
+fn memory_range(address: usize, length: usize) -> Option<Range<usize>> {
+    let end = address.checked_add(length).filter(|end| *end <= 0x10000)?;
+    Some(address..end)
+}
+
+// <type>,<address>,<kind>
+fn parse_point(arguments: &str) -> Option<(u8, u16, u16)> {
+    let mut fields = arguments.split(',');
+    let point_type = fields.next()?.parse().ok()?;
+    let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let kind = u16::from_str_radix(fields.next()?.split(';').next()?, 16).ok()?;
+
+    Some((point_type, address, kind))
+}
+
+fn watch_kinds(point_type: u8) -> Vec<WatchKind> {
+    match point_type {
+        2 => vec![WatchKind::Write],
+        3 => vec![WatchKind::Read],
+        _ => vec![WatchKind::Read, WatchKind::Write],
+    }
+}
+
+fn watch_end(address: u16, length: u16) -> u16 {
+    address.saturating_add(length.max(1) - 1)
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len()).step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn ok_reply() -> String {
+    String::from("OK")
+}
+
+// GDB only cares that it's an error
+fn error_reply() -> String {
+    String::from("E01")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    // GDB's side: what it sent, and what it got back
+    struct FakeDebugger {
+        sent: io::Cursor<Vec<u8>>,
+        received: Vec<u8>,
+    }
+
+    impl Read for FakeDebugger {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.sent.read(buffer)
+        }
+    }
+
+    impl Write for FakeDebugger {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.received.write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for FakeDebugger {
+        fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+            let mut byte = [0];
+            Ok((self.sent.read(&mut byte)? == 1).then_some(byte[0]))
+        }
+    }
+
+    fn stub(sent: &[u8]) -> GdbStub<FakeDebugger> {
+        GdbStub::new(FakeDebugger { sent: io::Cursor::new(sent.to_vec()), received: Vec::new() })
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${data}#{:02x}", packet_checksum(data.as_bytes()))
+    }
+
+    // everything the stub sent back over a whole session
+    fn session(cpu: &mut CPU, sent: &str) -> String {
+        let mut stub = stub(sent.as_bytes());
+        stub.run(cpu).unwrap();
+        String::from_utf8(stub.connection.into_inner().received).unwrap()
+    }
+
+    fn reply(stub: &mut GdbStub<FakeDebugger>, cpu: &mut CPU, packet: &str) -> String {
+        match stub.handle(cpu, packet) {
+            Action::Reply(reply) => reply,
+            _ => panic!("{packet} didn't get a reply"),
+        }
+    }
+
+    fn cpu_running(source: &str) -> CPU {
+        let rom = assembler::assemble(source).unwrap();
+        let mut cpu = CPU::new();
+        cpu.mem_bus.load_rom(&rom);
+        cpu.rom_size = rom.len() / 1024;
+        cpu.pc = 0x0150;
+        cpu
+    }
+
+    #[test]
+    fn packets_are_checked_and_acked() {
+        let mut cpu = CPU::new();
+
+        assert_eq!(session(&mut cpu, "$?#3f+"), "+$S05#b8");
+        // a bad checksum asks for the packet again
+        assert_eq!(session(&mut cpu, "$?#00$?#3f+"), "-+$S05#b8");
+        // so does GDB for our replies
+        assert_eq!(session(&mut cpu, "$?#3f-+"), "+$S05#b8$S05#b8");
+        // stray bytes between packets are skipped
+        assert_eq!(session(&mut cpu, "+\x03$?#3f+"), "+$S05#b8");
+
+        let sent = format!("{}+{}", packet("QStartNoAckMode"), packet("?"));
+        assert_eq!(session(&mut cpu, &sent), format!("+{}{}", packet("OK"), packet("S05")));
+
+        let sent = format!("{}+", packet("D"));
+        assert_eq!(session(&mut cpu, &sent), format!("+{}", packet("OK")));
+    }
+
+    #[test]
+    fn escaped_bytes_are_checked_as_sent() {
+        let mut cpu = CPU::new();
+        cpu.mem_bus.write_byte(0xC000, 0x7D);
+
+        // "mc000,1" with its first '0' escaped, the checksum is over the bytes as sent
+        let sent = format!("{}+", packet("mc}\x1000,1"));
+        assert_eq!(session(&mut cpu, &sent), format!("+{}", packet("7d")));
+
+        // and escaped in the replies
+        let mut stub = stub(b"+");
+        stub.send_packet("a$b#c}d*").unwrap();
+        assert_eq!(stub.connection.into_inner().received, b"$a}\x04b}\x03c}]d}\x0a#ec");
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let mut cpu = CPU::new();
+        let mut stub = stub(b"");
+        cpu.regs.a = 0x01;
+        cpu.regs.flags = FlagsRegister::from_u8(0xB0);
+        cpu.regs.b = 0x02;
+        cpu.regs.c = 0x03;
+        cpu.regs.d = 0x04;
+        cpu.regs.e = 0x05;
+        cpu.regs.h = 0x06;
+        cpu.regs.l = 0x07;
+        cpu.regs.sp = 0xFFFE;
+        cpu.pc = 0x0150;
+
+        assert_eq!(reply(&mut stub, &mut cpu, "g"), "01b0020304050607feff5001");
+        assert_eq!(reply(&mut stub, &mut cpu, "p1"), "b0");
+        assert_eq!(reply(&mut stub, &mut cpu, "p9"), "5001");
+        assert_eq!(reply(&mut stub, &mut cpu, "pa"), "E01");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "G1170121314151617f0df0002"), "OK");
+        assert_eq!((cpu.regs.a, cpu.regs.flags.into_u8(), cpu.regs.b, cpu.regs.l), (0x11, 0x70, 0x12, 0x17));
+        assert_eq!((cpu.regs.sp, cpu.pc), (0xDFF0, 0x0200));
+        // one byte short
+        assert_eq!(reply(&mut stub, &mut cpu, "G1170121314151617f0df00"), "E01");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "P9=0001"), "OK");
+        assert_eq!(cpu.pc, 0x0100);
+        assert_eq!(reply(&mut stub, &mut cpu, "P0=42"), "OK");
+        assert_eq!(cpu.regs.a, 0x42);
+        // A is 8 bits
+        assert_eq!(reply(&mut stub, &mut cpu, "P0=4242"), "E01");
+        assert_eq!(reply(&mut stub, &mut cpu, "Pa=42"), "E01");
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let mut cpu = CPU::new();
+        let mut stub = stub(b"");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "Mc000,3:0a0b0c"), "OK");
+        assert_eq!(reply(&mut stub, &mut cpu, "mc000,3"), "0a0b0c");
+        assert_eq!(reply(&mut stub, &mut cpu, "mc000,0"), "");
+
+        // up to IE, not past it
+        assert_eq!(reply(&mut stub, &mut cpu, "Mffff,1:1f"), "OK");
+        assert_eq!(reply(&mut stub, &mut cpu, "mffff,1"), "1f");
+        assert_eq!(reply(&mut stub, &mut cpu, "mffff,2"), "E01");
+
+        // the length and the bytes don't agree
+        assert_eq!(reply(&mut stub, &mut cpu, "Mc000,2:0a"), "E01");
+        assert_eq!(reply(&mut stub, &mut cpu, "mc000"), "E01");
+    }
+
+    #[test]
+    fn breakpoints_stop_continue_and_step_doesnt() {
+        let mut cpu = cpu_running(r#"
+            SECTION "Main", ROM0[$0150]
+                nop
+                nop
+                nop
+                nop
+        "#);
+        let mut stub = stub(b"");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "Z0,0152,1"), "OK");
+        assert!(matches!(stub.handle(&mut cpu, "c"), Action::Resume { single_step: false }));
+        let reason = stub.resume(&mut cpu, false).unwrap();
+        assert_eq!(reason.reply(), "T05swbreak:;");
+        assert_eq!(cpu.pc, 0x0152);
+
+        // continuing from the breakpoint runs past it
+        assert!(matches!(stub.handle(&mut cpu, "s"), Action::Resume { single_step: true }));
+        assert_eq!(stub.resume(&mut cpu, true).unwrap().reply(), "S05");
+        assert_eq!(cpu.pc, 0x0153);
+
+        assert_eq!(reply(&mut stub, &mut cpu, "z0,0152,1"), "OK");
+        assert!(stub.breakpoints.is_empty());
+
+        // unsupported types get the empty reply, malformed ones an error
+        assert_eq!(reply(&mut stub, &mut cpu, "Z5,0152,1"), "");
+        assert_eq!(reply(&mut stub, &mut cpu, "Z0,0152"), "E01");
+    }
+
+    #[test]
+    fn watchpoints_stop_continue() {
+        let mut cpu = cpu_running(r#"
+            SECTION "Main", ROM0[$0150]
+                ld de, $C000
+                ld [de], a
+                ld a, [de]
+        "#);
+        let mut stub = stub(b"");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "Z2,c000,1"), "OK");
+        // the debugger's own writes don't count
+        assert_eq!(reply(&mut stub, &mut cpu, "Mc000,1:00"), "OK");
+
+        assert_eq!(stub.resume(&mut cpu, false).unwrap().reply(), "T05watch:c000;");
+        assert_eq!(cpu.pc, 0x0154);
+
+        assert_eq!(reply(&mut stub, &mut cpu, "z2,c000,1"), "OK");
+        assert!(cpu.mem_bus.watchpoints.is_none());
+
+        assert_eq!(reply(&mut stub, &mut cpu, "Z3,c000,1"), "OK");
+        assert_eq!(stub.resume(&mut cpu, false).unwrap().reply(), "T05rwatch:c000;");
+        assert_eq!(cpu.pc, 0x0155);
+    }
+
+    #[test]
+    fn ctrl_c_interrupts_continue() {
+        let mut cpu = cpu_running(r#"
+            SECTION "Main", ROM0[$0150]
+            Loop:
+                jr Loop
+        "#);
+        let mut stub = stub(b"\x03");
+
+        assert_eq!(stub.resume(&mut cpu, false).unwrap().reply(), "S02");
+    }
+}
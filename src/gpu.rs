@@ -1,4 +1,4 @@
-use crate::{registers::LCDControl, utils::panic_log};
+use crate::{error::EmuError, registers::LCDControl};
 
 pub const VRAM_START: usize = 0x8000;
 pub const VRAM_END: usize = 0x97FF;
@@ -10,13 +10,16 @@ pub const TILE_MAP_SIZE: usize = TILE_MAP_END - TILE_MAP_START + 1;
 
 pub const TILE_COUNT: usize = VRAM_SIZE / 0x10;
 
+pub const LCD_WIDTH: usize = 160;
+pub const LCD_HEIGHT: usize = 144;
+
 /* NINTENDO LOGO
 CE ED 66 66 CC 0D 00 0B 03 73 00 83 00 0C 00 0D
 00 08 11 1F 88 89 00 0E DC CC 6E E6 DD DD D9 99
 BB BB 67 63 6E 0E EC CC DD DC 99 9F BB B9 33 3E
 */
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TilePixelValue {
     White,
     Black,
@@ -27,6 +30,9 @@ pub enum TilePixelValue {
 
 pub type Tile = [[TilePixelValue; 8]; 8];
 
+// what the LCD shows, row by row (160x144)
+pub type Screen = [TilePixelValue; LCD_WIDTH * LCD_HEIGHT];
+
 
 pub struct GPU {
     // should start at 0x8000 in gameboy memory
@@ -34,6 +40,9 @@ pub struct GPU {
 
     // 0x1800 (vram available for tiles) / 0x10 (size of one tiles) = 0x180 = 384 (total available tile spots)
     pub tileset: [Tile; TILE_COUNT],
+
+    // last rendered frame
+    pub screen: Screen,
 }
 
 
@@ -42,6 +51,7 @@ impl GPU {
         GPU {
             vram: [0; VRAM_SIZE],
             tileset: [[[TilePixelValue::White; 8]; 8]; TILE_COUNT],
+            screen: [TilePixelValue::White; LCD_WIDTH * LCD_HEIGHT],
         }
     }
 
@@ -49,9 +59,9 @@ impl GPU {
         *lcdc = LCDControl::new();  // default LCDC
     }
 
-    pub fn get_tile(&self, tile_index: usize, area_method: bool) -> Tile {
+    pub fn get_tile(&self, tile_index: usize, area_method: bool) -> Result<Tile, EmuError> {
         if tile_index > 255 {
-            panic_log(&format!("[GPU] Incorrect tile index {tile_index} (must be 0-255)"));
+            return Err(EmuError::InvalidTileIndex(tile_index));
         }
 
         let vram_index: usize;
@@ -83,7 +93,49 @@ impl GPU {
         let tile_bytes: &[u8] = &self.vram[vram_index..vram_index + 16*16];
         let tile = TileManipulation::tile_from_bytes(tile_bytes.to_vec());
 
-        tile
+        Ok(tile)
+    }
+
+    /**
+        Renders the background layer into `screen`.
+        `tile_map` is the 32x32 tile map selected by LCDC bit 3, `lcdc` / `scy` / `scx` / `bgp`
+        are the raw values of 0xFF40, 0xFF42, 0xFF43 and 0xFF47.
+     */
+    pub fn render_background(&mut self, tile_map: &[u8], lcdc: u8, scy: u8, scx: u8, bgp: u8) {
+        let lcd_enabled = lcdc & 0b1000_0000 != 0;
+        let bg_enabled = lcdc & 0b0000_0001 != 0;
+
+        if !lcd_enabled || !bg_enabled {
+            self.screen = [TilePixelValue::White; LCD_WIDTH * LCD_HEIGHT];
+            return;
+        }
+
+        // LCDC bit 4: 0x8000 method if set, 0x8800 method otherwise
+        let area_method = lcdc & 0b0001_0000 != 0;
+
+        for y in 0..LCD_HEIGHT {
+            let bg_y = (y + scy as usize) & 0xFF;
+
+            for x in 0..LCD_WIDTH {
+                let bg_x = (x + scx as usize) & 0xFF;
+
+                let tile_id = tile_map[(bg_y / 8) * 32 + bg_x / 8];
+                let tile_address = if area_method {
+                    tile_id as usize * 16
+                } else {
+                    (0x1000 + (tile_id as i8 as isize) * 16) as usize
+                };
+
+                let row_address = tile_address + (bg_y % 8) * 2;
+                let mask = 1 << (7 - (bg_x % 8));
+
+                let lsb = (self.vram[row_address] & mask != 0) as u8;
+                let msb = (self.vram[row_address + 1] & mask != 0) as u8;
+                let color_id = (msb << 1) | lsb;
+
+                self.screen[y * LCD_WIDTH + x] = TilePixelValue::from_palette(bgp, color_id);
+            }
+        }
     }
 
     pub fn vram_read_byte(&self, addr: usize) -> u8 {
@@ -124,6 +176,28 @@ impl GPU {
 }
 
 
+impl TilePixelValue {
+    // runs a color ID (0-3) through a palette register (BGP, OBP0, OBP1)
+    pub fn from_palette(palette: u8, color_id: u8) -> Self {
+        match (palette >> (color_id * 2)) & 0b11 {
+            0 => TilePixelValue::White,
+            1 => TilePixelValue::LightGray,
+            2 => TilePixelValue::DarkGray,
+            _ => TilePixelValue::Black,
+        }
+    }
+
+    pub fn to_rgb(self) -> u32 {
+        match self {
+            TilePixelValue::Black => 0x000000,
+            TilePixelValue::DarkGray => 0x555555,
+            TilePixelValue::LightGray => 0xAAAAAA,
+            TilePixelValue::White => 0xFFFFFF,
+        }
+    }
+}
+
+
 #[allow(non_snake_case)]
 pub mod TileManipulation {
     use super::{Tile, TilePixelValue};
@@ -0,0 +1,287 @@
+use std::{fmt, fs, path::PathBuf};
+
+use crate::{boot, capture, cli::CliError, cpu::{CPU, CYCLES_PER_FRAME}, doctor, error::EmuError, joypad, movie::{self, Movie}, rom::ROM, utils::{self, log}};
+
+/*
+    Runs a ROM without a window, for regression tests on machines with no display:
+
+        crusty-boy headless <rom> [--frames N] [--cycles N] [--until-pc ADDR] [--until-halt]
+                                  [--inputs <script>] [--movie <path>]
+                                  [--out <png>] [--every N] [--frames-dir <dir>] [--scale N]
+                                  [--doctor-log <path>]
+
+    It stops at the first condition met. Without any, it runs DEFAULT_FRAMES frames
+    (or the whole movie when playing one).
+
+    Input scripts hold buttons from a given frame until the next line:
+        # frame  buttons
+        0        -
+        120      start
+        125      a+right
+*/
+
+const DEFAULT_FRAMES: u64 = 60;
+const DEFAULT_OUTPUT: &str = "data/headless.png";
+
+pub struct HeadlessOptions {
+    pub rom_path: String,
+
+    pub max_frames: Option<u64>,
+    pub max_cycles: Option<u64>,
+    pub until_pc: Option<u16>,
+    pub until_halt: bool,
+
+    pub inputs_path: Option<String>,
+    pub movie_path: Option<String>,
+
+    pub output_path: PathBuf,
+    // also dump every Nth frame to `frames_dir`
+    pub every: Option<u64>,
+    pub frames_dir: PathBuf,
+    pub scale: usize,
+
+    // Gameboy Doctor trace of every instruction, see doctor.rs
+    pub doctor_log_path: Option<PathBuf>,
+}
+
+pub enum StopReason {
+    Frames,
+    Cycles,
+    ReachedPc(u16),
+    Halted,
+    MovieOver,
+    // the CPU locked up, see error.rs
+    Error(EmuError),
+}
+
+pub struct HeadlessReport {
+    pub frames: u64,
+    pub cycles: u64,
+    pub pc: u16,
+    pub reason: StopReason,
+    pub frame_hash: u32,
+}
+
+impl HeadlessOptions {
+    pub fn from_args(args: &[String]) -> Result<Self, CliError> {
+        let mut options = HeadlessOptions {
+            rom_path: String::new(),
+            max_frames: None,
+            max_cycles: None,
+            until_pc: None,
+            until_halt: false,
+            inputs_path: None,
+            movie_path: None,
+            output_path: PathBuf::from(DEFAULT_OUTPUT),
+            every: None,
+            frames_dir: PathBuf::from("data/frames"),
+            scale: 1,
+            doctor_log_path: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().ok_or(CliError::InvalidArgument(format!("{arg} needs a value")));
+
+            match arg.as_str() {
+                "--frames" => options.max_frames = Some(parse_number(arg, &value()?)?),
+                "--cycles" => options.max_cycles = Some(parse_number(arg, &value()?)?),
+                "--until-pc" => options.until_pc = Some(parse_number(arg, &value()?)? as u16),
+                "--until-halt" => options.until_halt = true,
+                "--inputs" => options.inputs_path = Some(value()?),
+                "--movie" => options.movie_path = Some(value()?),
+                "--out" => options.output_path = PathBuf::from(value()?),
+                "--every" => options.every = Some(parse_number(arg, &value()?)?.max(1)),
+                "--frames-dir" => options.frames_dir = PathBuf::from(value()?),
+                "--scale" => options.scale = parse_number(arg, &value()?)? as usize,
+                "--doctor-log" => options.doctor_log_path = Some(PathBuf::from(value()?)),
+                _ if arg.starts_with("--") => return Err(CliError::InvalidArgument(format!("unknown option {arg}"))),
+                _ => options.rom_path = arg.clone(),
+            }
+        }
+
+        if options.rom_path.is_empty() {
+            return Err(CliError::InvalidArgument(String::from("no ROM given")));
+        }
+
+        Ok(options)
+    }
+}
+
+
+pub fn main(args: &[String]) -> i32 {
+    let result = HeadlessOptions::from_args(args).and_then(|options| run(&options));
+
+    match result {
+        Ok(report) => {
+            log(&format!(
+                "[HEADLESS] Stopped ({}) after {} frames, {} cycles, PC: 0x{:04X}, frame hash: {:08X}",
+                report.reason, report.frames, report.cycles, report.pc, report.frame_hash
+            ));
+            // a ROM that locks the CPU is a failed run
+            if matches!(report.reason, StopReason::Error(_)) { 1 } else { 0 }
+        },
+        Err(error) => {
+            log(&format!("[HEADLESS] {error}"));
+            1
+        },
+    }
+}
+
+pub fn run(options: &HeadlessOptions) -> Result<HeadlessReport, CliError> {
+    let rom = ROM::read_rom(&options.rom_path)?;
+
+    let (mut cpu, movie) = match &options.movie_path {
+        Some(path) => {
+            let movie = Movie::load(path.as_ref())?;
+            (movie.start_cpu(&rom)?, Some(movie))
+        },
+        None => (boot::power_on(&rom), None),
+    };
+
+    if let Some(path) = &options.doctor_log_path {
+        doctor::enable(&mut cpu, path)?;
+    }
+
+    let script = match &options.inputs_path {
+        Some(path) => parse_input_script(&fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+
+    let no_stop_condition = options.max_cycles.is_none() && options.until_pc.is_none() && !options.until_halt;
+    let max_frames = match (options.max_frames, &movie) {
+        (Some(frames), _) => Some(frames),
+        (None, Some(movie)) => Some(movie.inputs.len() as u64),
+        (None, None) if no_stop_condition => Some(DEFAULT_FRAMES),
+        (None, None) => None,
+    };
+
+    let start_cycles = cpu.cycles;
+    let mut frame: u64 = 0;
+
+    let reason = loop {
+        if max_frames.is_some_and(|max| frame >= max) {
+            break StopReason::Frames;
+        }
+
+        cpu.mem_bus.joypad.pressed = match &movie {
+            Some(movie) => match movie.inputs.get(frame as usize) {
+                Some(input) => *input,
+                None => break StopReason::MovieOver,
+            },
+            None => script_input(&script, frame),
+        };
+
+        let stopped = run_frame_until(&mut cpu, options, start_cycles);
+        cpu.mem_bus.render_frame();
+        frame += 1;
+
+        if options.every.is_some_and(|every| frame.is_multiple_of(every)) {
+            let path = options.frames_dir.join(format!("frame_{frame:06}.png"));
+            capture::write_png(&path, &cpu.mem_bus.gpu.screen, options.scale)?;
+        }
+
+        if let Some(reason) = stopped {
+            break reason;
+        }
+    };
+
+    capture::write_png(&options.output_path, &cpu.mem_bus.gpu.screen, options.scale)?;
+    log(&format!("[HEADLESS] Final frame written to {}", options.output_path.display()));
+
+    Ok(HeadlessReport {
+        frames: frame,
+        cycles: cpu.cycles - start_cycles,
+        pc: cpu.pc,
+        reason,
+        frame_hash: movie::frame_hash(&cpu.mem_bus.gpu.screen),
+    })
+}
+
+// like CPU::run_frame, but checks the stop conditions after every instruction
+fn run_frame_until(cpu: &mut CPU, options: &HeadlessOptions, start_cycles: u64) -> Option<StopReason> {
+    let frame_end = (cpu.cycles / CYCLES_PER_FRAME as u64 + 1) * CYCLES_PER_FRAME as u64;
+
+    while cpu.cycles < frame_end {
+        if let Err(error) = cpu.step() {
+            cpu.report_error(&error);
+            return Some(StopReason::Error(error));
+        }
+
+        if options.max_cycles.is_some_and(|max| cpu.cycles - start_cycles >= max) {
+            return Some(StopReason::Cycles);
+        }
+        if options.until_pc == Some(cpu.pc) {
+            return Some(StopReason::ReachedPc(cpu.pc));
+        }
+        if options.until_halt && cpu.is_halted {
+            return Some(StopReason::Halted);
+        }
+    }
+
+    None
+}
+
+
+// (first frame, buttons held), sorted by frame
+fn parse_input_script(text: &str) -> Result<Vec<(u64, u8)>, CliError> {
+    let mut script: Vec<(u64, u8)> = Vec::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let script_error = |message: String| CliError::Script { line: line_index + 1, message };
+
+        let (frame, buttons) = line.split_once(char::is_whitespace).unwrap_or((line, "-"));
+        let frame: u64 = frame.parse().map_err(|_| script_error(format!("invalid frame \"{frame}\"")))?;
+
+        let mut pressed: u8 = 0;
+        for button in buttons.trim().split('+').filter(|button| *button != "-") {
+            pressed |= match button.to_ascii_lowercase().as_str() {
+                "right" => joypad::RIGHT,
+                "left" => joypad::LEFT,
+                "up" => joypad::UP,
+                "down" => joypad::DOWN,
+                "a" => joypad::A,
+                "b" => joypad::B,
+                "select" => joypad::SELECT,
+                "start" => joypad::START,
+                _ => return Err(script_error(format!("unknown button \"{button}\""))),
+            };
+        }
+
+        script.push((frame, pressed));
+    }
+
+    script.sort_by_key(|(frame, _)| *frame);
+    Ok(script)
+}
+
+fn script_input(script: &[(u64, u8)], frame: u64) -> u8 {
+    script.iter()
+        .take_while(|(start, _)| *start <= frame)
+        .last()
+        .map_or(0, |(_, pressed)| *pressed)
+}
+
+fn parse_number(option: &str, value: &str) -> Result<u64, CliError> {
+    utils::parse_number(value).ok_or(CliError::InvalidArgument(format!("invalid value \"{value}\" for {option}")))
+}
+
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Frames => write!(f, "frame limit"),
+            StopReason::Cycles => write!(f, "cycle limit"),
+            StopReason::ReachedPc(pc) => write!(f, "reached 0x{pc:04X}"),
+            StopReason::Halted => write!(f, "CPU halted"),
+            StopReason::MovieOver => write!(f, "end of the movie"),
+            StopReason::Error(error) => write!(f, "{error}"),
+        }
+    }
+}
+
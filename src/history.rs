@@ -0,0 +1,85 @@
+use crate::{registers::Registers, symbols::{Symbols, MAPPED_ROM_BANK}};
+
+/*
+    The last instructions the CPU ran, to see how it got wherever it broke without
+    tracing everything. Recording one is a copy into a fixed-size ring, so it's
+    always on.
+
+    Dumped to the log when the emulator panics (unknown opcode included) and shown
+    by the debugger when it stops (`history` for all of it).
+*/
+
+pub const HISTORY_LENGTH: usize = 64;
+
+#[derive(Clone, Copy, Default)]
+pub struct HistoryEntry {
+    // of the instruction's first byte, the 0xCB prefix for prefixed ones
+    pub pc: u16,
+    pub opcode: u8,
+    pub prefixed: bool,
+    // before the instruction ran
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+}
+
+pub struct InstructionHistory {
+    entries: [HistoryEntry; HISTORY_LENGTH],
+    // where the next one goes, the oldest once it's full
+    next: usize,
+    len: usize,
+}
+
+impl InstructionHistory {
+    pub fn new() -> Self {
+        InstructionHistory {
+            entries: [HistoryEntry::default(); HISTORY_LENGTH],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn record(&mut self, pc: u16, opcode: u8, prefixed: bool, regs: &Registers) {
+        self.entries[self.next] = HistoryEntry {
+            pc, opcode, prefixed,
+            a: regs.a, f: regs.flags.into_u8(), b: regs.b, c: regs.c,
+            d: regs.d, e: regs.e, h: regs.h, l: regs.l, sp: regs.sp,
+        };
+        self.next = (self.next + 1) % HISTORY_LENGTH;
+        self.len = (self.len + 1).min(HISTORY_LENGTH);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+        let start = (self.next + HISTORY_LENGTH - self.len) % HISTORY_LENGTH;
+        (0..self.len).map(move |i| &self.entries[(start + i) % HISTORY_LENGTH])
+    }
+
+    // one line per instruction, the last `count` of them, oldest first
+    pub fn lines(&self, count: usize, symbols: &Symbols) -> Vec<String> {
+        self.iter().skip(self.len.saturating_sub(count)).map(|entry| entry.line(symbols)).collect()
+    }
+}
+
+impl HistoryEntry {
+    // "0150 CB 37  A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE  (Main.loop)"
+    fn line(&self, symbols: &Symbols) -> String {
+        let opcode = if self.prefixed { format!("CB {:02X}", self.opcode) } else { format!("{:02X}   ", self.opcode) };
+
+        format!(
+            "{:04X} {opcode}  A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X}{}",
+            self.pc, self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp,
+            symbols.describe(self.pc, MAPPED_ROM_BANK).map(|name| format!("  ({name})")).unwrap_or_default(),
+        )
+    }
+}
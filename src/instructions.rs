@@ -1,6 +1,72 @@
 use crate::registers::{Reg, Reg16};
 
 
+// T-cycles of each opcode, when a conditional jump/call/ret isn't taken.
+// Illegal opcodes are given 4 so the clock always moves forward.
+pub const OPCODE_CYCLES: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4,  // 0x
+     4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4,  // 1x
+     8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4,  // 2x
+     8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4,  // 3x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,  // 4x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,  // 5x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,  // 6x
+     8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4,  // 7x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,  // 8x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,  // 9x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,  // Ax
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,  // Bx
+     8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  4, 12, 24,  8, 16,  // Cx
+     8, 12, 12,  4, 12, 16,  8, 16,  8, 16, 12,  4, 12,  4,  8, 16,  // Dx
+    12, 12,  8,  4,  4, 16,  8, 16, 16,  4, 16,  4,  4,  4,  8, 16,  // Ex
+    12, 12,  8,  4,  4, 16,  8, 16, 12,  8, 16,  4,  4,  4,  8, 16,  // Fx
+];
+
+// size of each instruction in bytes, opcode included (0xCB opcodes are all 2 bytes)
+pub const OPCODE_LENGTHS: [u8; 256] = [
+//  x0 x1 x2 x3 x4 x5 x6 x7 x8 x9 xA xB xC xD xE xF
+    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,  // 0x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,  // 1x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,  // 2x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,  // 3x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,  // 4x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,  // 5x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,  // 6x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,  // 7x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,  // 8x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,  // 9x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,  // Ax
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,  // Bx
+    1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,  // Cx
+    1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1,  // Dx
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,  // Ex
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,  // Fx
+];
+
+// T-cycles of the 0xCB-prefixed opcodes, prefix included
+pub fn prefixed_opcode_cycles(byte: u8) -> u8 {
+    let uses_hl = byte & 0x07 == 0x06;
+    let is_bit = (0x40..=0x7F).contains(&byte);
+
+    match (uses_hl, is_bit) {
+        (false, _) => 8,
+        (true, true) => 12,     // BIT b,[HL] only reads
+        (true, false) => 16,
+    }
+}
+
+// what a conditional JR/JP/CALL/RET costs on top of OPCODE_CYCLES when it's taken
+pub fn branch_taken_extra_cycles(byte: u8) -> u8 {
+    match byte {
+        0x20 | 0x28 | 0x30 | 0x38 => 4,     // JR cc
+        0xC2 | 0xCA | 0xD2 | 0xDA => 4,     // JP cc
+        0xC4 | 0xCC | 0xD4 | 0xDC => 12,    // CALL cc
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => 12,    // RET cc
+        _ => 0,
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum JumpTypes {
     NotZero,
@@ -10,11 +76,18 @@ pub enum JumpTypes {
     Always
 }
 
+/**
+    Every SM83 opcode, each its own value: [HL] is Reg::Addr(Reg16::HL), Reg::HL
+    is always the register pair. D8/D16 are the immediates after the opcode,
+    A16 the byte at the address after it.
+ */
 #[derive(PartialEq, Debug, Clone)]
 pub enum Instruction {
     ADD(Reg),
     ADC(Reg),
     ADDHL(Reg16),
+    // ADD SP,s8
+    ADDSP,
 
     SUB(Reg),
     SBC(Reg),
@@ -29,6 +102,7 @@ pub enum Instruction {
 
     CCF,
     SCF,
+    DAA,
 
     RRA,
     RLA,
@@ -51,21 +125,32 @@ pub enum Instruction {
     SWAP(Reg),
 
     JP(JumpTypes),
+    // JP HL
+    JPHL,
     JR(JumpTypes),
     CALL(JumpTypes),
     RET(JumpTypes),
+    RETI,
+    // the vector, 0x00 to 0x38
+    RST(u8),
 
     LD(Reg, Reg),
+    // LD HL,SP+s8
+    LDHLSP,
+    // at 0xFF00 + the byte after the opcode (D8) or + C
+    LDH(Reg, Reg),
 
     PUSH(Reg16),
     POP(Reg16),
 
+    DI,
+    EI,
     HALT,
+    STOP,
     NOP,
 
 
     IDK,    // DEBUG PURPOSES! CALLED WHEN IDFK WHAT'S HAPPENING
-    EXIT,   // DEBUG PURPQSRPSS
 }
 
 impl Instruction {
@@ -79,6 +164,14 @@ impl Instruction {
         }
     }
 
+    // the other way around: the opcode this decodes from, and whether it's after 0xCB
+    pub fn opcode(&self) -> Option<(u8, bool)> {
+        let unprefixed = (0..=0xFF).map(|byte| (byte, false));
+        let prefixed = (0..=0xFF).map(|byte| (byte, true));
+
+        unprefixed.chain(prefixed).find(|&(byte, prefixed)| Instruction::from_byte(byte, prefixed).as_ref() == Some(self))
+    }
+
     fn from_byte_prefixed(byte: u8) -> Option<Instruction> {
         match byte {
             0x00 => Some(Instruction::RLC(Reg::B)),
@@ -87,7 +180,7 @@ impl Instruction {
             0x03 => Some(Instruction::RLC(Reg::E)),
             0x04 => Some(Instruction::RLC(Reg::H)),
             0x05 => Some(Instruction::RLC(Reg::L)),
-            0x06 => Some(Instruction::RLC(Reg::HL)),
+            0x06 => Some(Instruction::RLC(Reg::Addr(Reg16::HL))),
             0x07 => Some(Instruction::RLC(Reg::A)),
 
             0x08 => Some(Instruction::RRC(Reg::B)),
@@ -96,7 +189,7 @@ impl Instruction {
             0x0b => Some(Instruction::RRC(Reg::E)),
             0x0c => Some(Instruction::RRC(Reg::H)),
             0x0d => Some(Instruction::RRC(Reg::L)),
-            0x0e => Some(Instruction::RRC(Reg::HL)),
+            0x0e => Some(Instruction::RRC(Reg::Addr(Reg16::HL))),
             0x0f => Some(Instruction::RRC(Reg::A)),
 
             0x10 => Some(Instruction::RL(Reg::B)),
@@ -105,7 +198,7 @@ impl Instruction {
             0x13 => Some(Instruction::RL(Reg::E)),
             0x14 => Some(Instruction::RL(Reg::H)),
             0x15 => Some(Instruction::RL(Reg::L)),
-            0x16 => Some(Instruction::RL(Reg::HL)),
+            0x16 => Some(Instruction::RL(Reg::Addr(Reg16::HL))),
             0x17 => Some(Instruction::RL(Reg::A)),
 
             0x18 => Some(Instruction::RR(Reg::B)),
@@ -114,7 +207,7 @@ impl Instruction {
             0x1b => Some(Instruction::RR(Reg::E)),
             0x1c => Some(Instruction::RR(Reg::H)),
             0x1d => Some(Instruction::RR(Reg::L)),
-            0x1e => Some(Instruction::RR(Reg::HL)),
+            0x1e => Some(Instruction::RR(Reg::Addr(Reg16::HL))),
             0x1f => Some(Instruction::RR(Reg::A)),
 
             0x20 => Some(Instruction::SLA(Reg::B)),
@@ -123,7 +216,7 @@ impl Instruction {
             0x23 => Some(Instruction::SLA(Reg::E)),
             0x24 => Some(Instruction::SLA(Reg::H)),
             0x25 => Some(Instruction::SLA(Reg::L)),
-            0x26 => Some(Instruction::SLA(Reg::HL)),
+            0x26 => Some(Instruction::SLA(Reg::Addr(Reg16::HL))),
             0x27 => Some(Instruction::SLA(Reg::A)),
 
             0x28 => Some(Instruction::SRA(Reg::B)),
@@ -132,7 +225,7 @@ impl Instruction {
             0x2b => Some(Instruction::SRA(Reg::E)),
             0x2c => Some(Instruction::SRA(Reg::H)),
             0x2d => Some(Instruction::SRA(Reg::L)),
-            0x2e => Some(Instruction::SRA(Reg::HL)),
+            0x2e => Some(Instruction::SRA(Reg::Addr(Reg16::HL))),
             0x2f => Some(Instruction::SRA(Reg::A)),
 
             0x30 => Some(Instruction::SWAP(Reg::B)),
@@ -141,7 +234,7 @@ impl Instruction {
             0x33 => Some(Instruction::SWAP(Reg::E)),
             0x34 => Some(Instruction::SWAP(Reg::H)),
             0x35 => Some(Instruction::SWAP(Reg::L)),
-            0x36 => Some(Instruction::SWAP(Reg::HL)),
+            0x36 => Some(Instruction::SWAP(Reg::Addr(Reg16::HL))),
             0x37 => Some(Instruction::SWAP(Reg::A)),
 
             0x38 => Some(Instruction::SRL(Reg::B)),
@@ -150,7 +243,7 @@ impl Instruction {
             0x3b => Some(Instruction::SRL(Reg::E)),
             0x3c => Some(Instruction::SRL(Reg::H)),
             0x3d => Some(Instruction::SRL(Reg::L)),
-            0x3e => Some(Instruction::SRL(Reg::HL)),
+            0x3e => Some(Instruction::SRL(Reg::Addr(Reg16::HL))),
             0x3f => Some(Instruction::SRL(Reg::A)),
 
             0x40 => Some(Instruction::BIT(Reg::B, 0)),
@@ -159,7 +252,7 @@ impl Instruction {
             0x43 => Some(Instruction::BIT(Reg::E, 0)),
             0x44 => Some(Instruction::BIT(Reg::H, 0)),
             0x45 => Some(Instruction::BIT(Reg::L, 0)),
-            0x46 => Some(Instruction::BIT(Reg::HL, 0)),
+            0x46 => Some(Instruction::BIT(Reg::Addr(Reg16::HL), 0)),
             0x47 => Some(Instruction::BIT(Reg::A, 0)),
 
             0x48 => Some(Instruction::BIT(Reg::B, 1)),
@@ -168,7 +261,7 @@ impl Instruction {
             0x4b => Some(Instruction::BIT(Reg::E, 1)),
             0x4c => Some(Instruction::BIT(Reg::H, 1)),
             0x4d => Some(Instruction::BIT(Reg::L, 1)),
-            0x4e => Some(Instruction::BIT(Reg::HL, 1)),
+            0x4e => Some(Instruction::BIT(Reg::Addr(Reg16::HL), 1)),
             0x4f => Some(Instruction::BIT(Reg::A, 1)),
 
             0x50 => Some(Instruction::BIT(Reg::B, 2)),
@@ -177,7 +270,7 @@ impl Instruction {
             0x53 => Some(Instruction::BIT(Reg::E, 2)),
             0x54 => Some(Instruction::BIT(Reg::H, 2)),
             0x55 => Some(Instruction::BIT(Reg::L, 2)),
-            0x56 => Some(Instruction::BIT(Reg::HL, 2)),
+            0x56 => Some(Instruction::BIT(Reg::Addr(Reg16::HL), 2)),
             0x57 => Some(Instruction::BIT(Reg::A, 2)),
 
             0x58 => Some(Instruction::BIT(Reg::B, 3)),
@@ -186,7 +279,7 @@ impl Instruction {
             0x5b => Some(Instruction::BIT(Reg::E, 3)),
             0x5c => Some(Instruction::BIT(Reg::H, 3)),
             0x5d => Some(Instruction::BIT(Reg::L, 3)),
-            0x5e => Some(Instruction::BIT(Reg::HL, 3)),
+            0x5e => Some(Instruction::BIT(Reg::Addr(Reg16::HL), 3)),
             0x5f => Some(Instruction::BIT(Reg::A, 3)),
 
             0x60 => Some(Instruction::BIT(Reg::B, 4)),
@@ -195,7 +288,7 @@ impl Instruction {
             0x63 => Some(Instruction::BIT(Reg::E, 4)),
             0x64 => Some(Instruction::BIT(Reg::H, 4)),
             0x65 => Some(Instruction::BIT(Reg::L, 4)),
-            0x66 => Some(Instruction::BIT(Reg::HL, 4)),
+            0x66 => Some(Instruction::BIT(Reg::Addr(Reg16::HL), 4)),
             0x67 => Some(Instruction::BIT(Reg::A, 4)),
 
             0x68 => Some(Instruction::BIT(Reg::B, 5)),
@@ -204,7 +297,7 @@ impl Instruction {
             0x6b => Some(Instruction::BIT(Reg::E, 5)),
             0x6c => Some(Instruction::BIT(Reg::H, 5)),
             0x6d => Some(Instruction::BIT(Reg::L, 5)),
-            0x6e => Some(Instruction::BIT(Reg::HL, 5)),
+            0x6e => Some(Instruction::BIT(Reg::Addr(Reg16::HL), 5)),
             0x6f => Some(Instruction::BIT(Reg::A, 5)),
 
             0x70 => Some(Instruction::BIT(Reg::B, 6)),
@@ -213,7 +306,7 @@ impl Instruction {
             0x73 => Some(Instruction::BIT(Reg::E, 6)),
             0x74 => Some(Instruction::BIT(Reg::H, 6)),
             0x75 => Some(Instruction::BIT(Reg::L, 6)),
-            0x76 => Some(Instruction::BIT(Reg::HL, 6)),
+            0x76 => Some(Instruction::BIT(Reg::Addr(Reg16::HL), 6)),
             0x77 => Some(Instruction::BIT(Reg::A, 6)),
 
             0x78 => Some(Instruction::BIT(Reg::B, 7)),
@@ -222,7 +315,7 @@ impl Instruction {
             0x7b => Some(Instruction::BIT(Reg::E, 7)),
             0x7c => Some(Instruction::BIT(Reg::H, 7)),
             0x7d => Some(Instruction::BIT(Reg::L, 7)),
-            0x7e => Some(Instruction::BIT(Reg::HL, 7)),
+            0x7e => Some(Instruction::BIT(Reg::Addr(Reg16::HL), 7)),
             0x7f => Some(Instruction::BIT(Reg::A, 7)),
 
             0x80 => Some(Instruction::RESET(Reg::B, 0)),
@@ -231,7 +324,7 @@ impl Instruction {
             0x83 => Some(Instruction::RESET(Reg::E, 0)),
             0x84 => Some(Instruction::RESET(Reg::H, 0)),
             0x85 => Some(Instruction::RESET(Reg::L, 0)),
-            0x86 => Some(Instruction::RESET(Reg::HL, 0)),
+            0x86 => Some(Instruction::RESET(Reg::Addr(Reg16::HL), 0)),
             0x87 => Some(Instruction::RESET(Reg::A, 0)),
 
             0x88 => Some(Instruction::RESET(Reg::B, 1)),
@@ -240,7 +333,7 @@ impl Instruction {
             0x8b => Some(Instruction::RESET(Reg::E, 1)),
             0x8c => Some(Instruction::RESET(Reg::H, 1)),
             0x8d => Some(Instruction::RESET(Reg::L, 1)),
-            0x8e => Some(Instruction::RESET(Reg::HL, 1)),
+            0x8e => Some(Instruction::RESET(Reg::Addr(Reg16::HL), 1)),
             0x8f => Some(Instruction::RESET(Reg::A, 1)),
 
             0x90 => Some(Instruction::RESET(Reg::B, 2)),
@@ -249,7 +342,7 @@ impl Instruction {
             0x93 => Some(Instruction::RESET(Reg::E, 2)),
             0x94 => Some(Instruction::RESET(Reg::H, 2)),
             0x95 => Some(Instruction::RESET(Reg::L, 2)),
-            0x96 => Some(Instruction::RESET(Reg::HL, 2)),
+            0x96 => Some(Instruction::RESET(Reg::Addr(Reg16::HL), 2)),
             0x97 => Some(Instruction::RESET(Reg::A, 2)),
 
             0x98 => Some(Instruction::RESET(Reg::B, 3)),
@@ -258,7 +351,7 @@ impl Instruction {
             0x9b => Some(Instruction::RESET(Reg::E, 3)),
             0x9c => Some(Instruction::RESET(Reg::H, 3)),
             0x9d => Some(Instruction::RESET(Reg::L, 3)),
-            0x9e => Some(Instruction::RESET(Reg::HL, 3)),
+            0x9e => Some(Instruction::RESET(Reg::Addr(Reg16::HL), 3)),
             0x9f => Some(Instruction::RESET(Reg::A, 3)),
 
             0xa0 => Some(Instruction::RESET(Reg::B, 4)),
@@ -267,7 +360,7 @@ impl Instruction {
             0xa3 => Some(Instruction::RESET(Reg::E, 4)),
             0xa4 => Some(Instruction::RESET(Reg::H, 4)),
             0xa5 => Some(Instruction::RESET(Reg::L, 4)),
-            0xa6 => Some(Instruction::RESET(Reg::HL, 4)),
+            0xa6 => Some(Instruction::RESET(Reg::Addr(Reg16::HL), 4)),
             0xa7 => Some(Instruction::RESET(Reg::A, 4)),
 
             0xa8 => Some(Instruction::RESET(Reg::B, 5)),
@@ -276,7 +369,7 @@ impl Instruction {
             0xab => Some(Instruction::RESET(Reg::E, 5)),
             0xac => Some(Instruction::RESET(Reg::H, 5)),
             0xad => Some(Instruction::RESET(Reg::L, 5)),
-            0xae => Some(Instruction::RESET(Reg::HL, 5)),
+            0xae => Some(Instruction::RESET(Reg::Addr(Reg16::HL), 5)),
             0xaf => Some(Instruction::RESET(Reg::A, 5)),
 
             0xb0 => Some(Instruction::RESET(Reg::B, 6)),
@@ -285,7 +378,7 @@ impl Instruction {
             0xb3 => Some(Instruction::RESET(Reg::E, 6)),
             0xb4 => Some(Instruction::RESET(Reg::H, 6)),
             0xb5 => Some(Instruction::RESET(Reg::L, 6)),
-            0xb6 => Some(Instruction::RESET(Reg::HL, 6)),
+            0xb6 => Some(Instruction::RESET(Reg::Addr(Reg16::HL), 6)),
             0xb7 => Some(Instruction::RESET(Reg::A, 6)),
 
             0xb8 => Some(Instruction::RESET(Reg::B, 7)),
@@ -294,7 +387,7 @@ impl Instruction {
             0xbb => Some(Instruction::RESET(Reg::E, 7)),
             0xbc => Some(Instruction::RESET(Reg::H, 7)),
             0xbd => Some(Instruction::RESET(Reg::L, 7)),
-            0xbe => Some(Instruction::RESET(Reg::HL, 7)),
+            0xbe => Some(Instruction::RESET(Reg::Addr(Reg16::HL), 7)),
             0xbf => Some(Instruction::RESET(Reg::A, 7)),
 
             0xc0 => Some(Instruction::SET(Reg::B, 0)),
@@ -303,7 +396,7 @@ impl Instruction {
             0xc3 => Some(Instruction::SET(Reg::E, 0)),
             0xc4 => Some(Instruction::SET(Reg::H, 0)),
             0xc5 => Some(Instruction::SET(Reg::L, 0)),
-            0xc6 => Some(Instruction::SET(Reg::HL, 0)),
+            0xc6 => Some(Instruction::SET(Reg::Addr(Reg16::HL), 0)),
             0xc7 => Some(Instruction::SET(Reg::A, 0)),
 
             0xc8 => Some(Instruction::SET(Reg::B, 1)),
@@ -312,7 +405,7 @@ impl Instruction {
             0xcb => Some(Instruction::SET(Reg::E, 1)),
             0xcc => Some(Instruction::SET(Reg::H, 1)),
             0xcd => Some(Instruction::SET(Reg::L, 1)),
-            0xce => Some(Instruction::SET(Reg::HL, 1)),
+            0xce => Some(Instruction::SET(Reg::Addr(Reg16::HL), 1)),
             0xcf => Some(Instruction::SET(Reg::A, 1)),
 
             0xd0 => Some(Instruction::SET(Reg::B, 2)),
@@ -321,7 +414,7 @@ impl Instruction {
             0xd3 => Some(Instruction::SET(Reg::E, 2)),
             0xd4 => Some(Instruction::SET(Reg::H, 2)),
             0xd5 => Some(Instruction::SET(Reg::L, 2)),
-            0xd6 => Some(Instruction::SET(Reg::HL, 2)),
+            0xd6 => Some(Instruction::SET(Reg::Addr(Reg16::HL), 2)),
             0xd7 => Some(Instruction::SET(Reg::A, 2)),
 
             0xd8 => Some(Instruction::SET(Reg::B, 3)),
@@ -330,7 +423,7 @@ impl Instruction {
             0xdb => Some(Instruction::SET(Reg::E, 3)),
             0xdc => Some(Instruction::SET(Reg::H, 3)),
             0xdd => Some(Instruction::SET(Reg::L, 3)),
-            0xde => Some(Instruction::SET(Reg::HL, 3)),
+            0xde => Some(Instruction::SET(Reg::Addr(Reg16::HL), 3)),
             0xdf => Some(Instruction::SET(Reg::A, 3)),
 
             0xe0 => Some(Instruction::SET(Reg::B, 4)),
@@ -339,7 +432,7 @@ impl Instruction {
             0xe3 => Some(Instruction::SET(Reg::E, 4)),
             0xe4 => Some(Instruction::SET(Reg::H, 4)),
             0xe5 => Some(Instruction::SET(Reg::L, 4)),
-            0xe6 => Some(Instruction::SET(Reg::HL, 4)),
+            0xe6 => Some(Instruction::SET(Reg::Addr(Reg16::HL), 4)),
             0xe7 => Some(Instruction::SET(Reg::A, 4)),
 
             0xe8 => Some(Instruction::SET(Reg::B, 5)),
@@ -348,7 +441,7 @@ impl Instruction {
             0xeb => Some(Instruction::SET(Reg::E, 5)),
             0xec => Some(Instruction::SET(Reg::H, 5)),
             0xed => Some(Instruction::SET(Reg::L, 5)),
-            0xee => Some(Instruction::SET(Reg::HL, 5)),
+            0xee => Some(Instruction::SET(Reg::Addr(Reg16::HL), 5)),
             0xef => Some(Instruction::SET(Reg::A, 5)),
 
             0xf0 => Some(Instruction::SET(Reg::B, 6)),
@@ -357,7 +450,7 @@ impl Instruction {
             0xf3 => Some(Instruction::SET(Reg::E, 6)),
             0xf4 => Some(Instruction::SET(Reg::H, 6)),
             0xf5 => Some(Instruction::SET(Reg::L, 6)),
-            0xf6 => Some(Instruction::SET(Reg::HL, 6)),
+            0xf6 => Some(Instruction::SET(Reg::Addr(Reg16::HL), 6)),
             0xf7 => Some(Instruction::SET(Reg::A, 6)),
 
             0xf8 => Some(Instruction::SET(Reg::B, 7)),
@@ -366,7 +459,7 @@ impl Instruction {
             0xfb => Some(Instruction::SET(Reg::E, 7)),
             0xfc => Some(Instruction::SET(Reg::H, 7)),
             0xfd => Some(Instruction::SET(Reg::L, 7)),
-            0xfe => Some(Instruction::SET(Reg::HL, 7)),
+            0xfe => Some(Instruction::SET(Reg::Addr(Reg16::HL), 7)),
             0xff => Some(Instruction::SET(Reg::A, 7)),
         }
     }
@@ -384,7 +477,7 @@ impl Instruction {
             0x1c => Some(Instruction::INC(Reg::E)),
             0x2c => Some(Instruction::INC(Reg::L)),
 
-            0x34 => Some(Instruction::INC(Reg::HL)),
+            0x34 => Some(Instruction::INC(Reg::Addr(Reg16::HL))),
             0x03 => Some(Instruction::INC(Reg::BC)),
             0x13 => Some(Instruction::INC(Reg::DE)),
             0x23 => Some(Instruction::INC(Reg::HL)),
@@ -400,7 +493,7 @@ impl Instruction {
             0x25 => Some(Instruction::DEC(Reg::H)),
             0x2d => Some(Instruction::DEC(Reg::L)),
 
-            0x35 => Some(Instruction::DEC(Reg::HL)),
+            0x35 => Some(Instruction::DEC(Reg::Addr(Reg16::HL))),
             0x0b => Some(Instruction::DEC(Reg::BC)),
             0x1b => Some(Instruction::DEC(Reg::DE)),
             0x2b => Some(Instruction::DEC(Reg::HL)),
@@ -415,7 +508,7 @@ impl Instruction {
             0x83 => Some(Instruction::ADD(Reg::E)),
             0x84 => Some(Instruction::ADD(Reg::H)),
             0x85 => Some(Instruction::ADD(Reg::L)),
-            0x86 => Some(Instruction::ADD(Reg::HL)),
+            0x86 => Some(Instruction::ADD(Reg::Addr(Reg16::HL))),
             0xC6 => Some(Instruction::ADD(Reg::D8)),
 
 
@@ -433,8 +526,8 @@ impl Instruction {
             0x8b => Some(Instruction::ADC(Reg::E)),
             0x8c => Some(Instruction::ADC(Reg::H)),
             0x8d => Some(Instruction::ADC(Reg::L)),
-            0x8e => Some(Instruction::ADC(Reg::HL)),
-            // 0xce => Some(Instruction::ADC(Reg::D8)),
+            0x8e => Some(Instruction::ADC(Reg::Addr(Reg16::HL))),
+            0xce => Some(Instruction::ADC(Reg::D8)),
 
 
             0x97 => Some(Instruction::SUB(Reg::A)),
@@ -445,8 +538,8 @@ impl Instruction {
             0x93 => Some(Instruction::SUB(Reg::E)),
             0x94 => Some(Instruction::SUB(Reg::H)),
             0x95 => Some(Instruction::SUB(Reg::L)),
-            0x96 => Some(Instruction::SUB(Reg::HL)),
-            // 0xd6 => Some(Instruction::SUB(Reg::D8)),
+            0x96 => Some(Instruction::SUB(Reg::Addr(Reg16::HL))),
+            0xd6 => Some(Instruction::SUB(Reg::D8)),
 
 
             0x9f => Some(Instruction::SBC(Reg::A)),
@@ -457,8 +550,8 @@ impl Instruction {
             0x9b => Some(Instruction::SBC(Reg::E)),
             0x9c => Some(Instruction::SBC(Reg::H)),
             0x9d => Some(Instruction::SBC(Reg::L)),
-            0x9e => Some(Instruction::SBC(Reg::HL)),
-            // 0xde => Some(Instruction::SBC(Reg::D8)),
+            0x9e => Some(Instruction::SBC(Reg::Addr(Reg16::HL))),
+            0xde => Some(Instruction::SBC(Reg::D8)),
 
 
             0xa7 => Some(Instruction::AND(Reg::A)),
@@ -469,8 +562,8 @@ impl Instruction {
             0xa3 => Some(Instruction::AND(Reg::E)),
             0xa4 => Some(Instruction::AND(Reg::H)),
             0xa5 => Some(Instruction::AND(Reg::L)),
-            0xa6 => Some(Instruction::AND(Reg::HL)),
-            // 0xe6 => Some(Instruction::AND(Reg::D8)),
+            0xa6 => Some(Instruction::AND(Reg::Addr(Reg16::HL))),
+            0xe6 => Some(Instruction::AND(Reg::D8)),
 
 
             0xb7 => Some(Instruction::OR(Reg::A)),
@@ -481,8 +574,8 @@ impl Instruction {
             0xb3 => Some(Instruction::OR(Reg::E)),
             0xb4 => Some(Instruction::OR(Reg::H)),
             0xb5 => Some(Instruction::OR(Reg::L)),
-            0xb6 => Some(Instruction::OR(Reg::HL)),
-            // 0xf6 => Some(Instruction::OR(Reg::D8)),
+            0xb6 => Some(Instruction::OR(Reg::Addr(Reg16::HL))),
+            0xf6 => Some(Instruction::OR(Reg::D8)),
 
 
             0xaf => Some(Instruction::XOR(Reg::A)),
@@ -493,8 +586,8 @@ impl Instruction {
             0xab => Some(Instruction::XOR(Reg::E)),
             0xac => Some(Instruction::XOR(Reg::H)),
             0xad => Some(Instruction::XOR(Reg::L)),
-            0xae => Some(Instruction::XOR(Reg::HL)),
-            // 0xee => Some(Instruction::XOR(Reg::D8)),
+            0xae => Some(Instruction::XOR(Reg::Addr(Reg16::HL))),
+            0xee => Some(Instruction::XOR(Reg::D8)),
 
 
             0xbf => Some(Instruction::CP(Reg::A)),
@@ -505,11 +598,11 @@ impl Instruction {
             0xbb => Some(Instruction::CP(Reg::E)),
             0xbc => Some(Instruction::CP(Reg::H)),
             0xbd => Some(Instruction::CP(Reg::L)),
-            0xbe => Some(Instruction::CP(Reg::HL)),
-            // 0xfe => Some(Instruction::CP(Reg::D8)),
+            0xbe => Some(Instruction::CP(Reg::Addr(Reg16::HL))),
+            0xfe => Some(Instruction::CP(Reg::D8)),
 
 
-            // 0xe8 => Some(Instruction::ADDSP),
+            0xE8 => Some(Instruction::ADDSP),
 
             0x3f => Some(Instruction::CCF),
             0x37 => Some(Instruction::SCF),
@@ -549,6 +642,18 @@ impl Instruction {
             0xC0 => Some(Instruction::RET(JumpTypes::NotZero)),
             0xD8 => Some(Instruction::RET(JumpTypes::Carry)),
             0xD0 => Some(Instruction::RET(JumpTypes::NotCarry)),
+            0xD9 => Some(Instruction::RETI),
+
+            0xE9 => Some(Instruction::JPHL),
+
+            0xC7 => Some(Instruction::RST(0x00)),
+            0xCF => Some(Instruction::RST(0x08)),
+            0xD7 => Some(Instruction::RST(0x10)),
+            0xDF => Some(Instruction::RST(0x18)),
+            0xE7 => Some(Instruction::RST(0x20)),
+            0xEF => Some(Instruction::RST(0x28)),
+            0xF7 => Some(Instruction::RST(0x30)),
+            0xFF => Some(Instruction::RST(0x38)),
 
 
 
@@ -556,7 +661,7 @@ impl Instruction {
             0x06 => Some(Instruction::LD(Reg::B, Reg::D8)),
             0x16 => Some(Instruction::LD(Reg::D, Reg::D8)),
             0x26 => Some(Instruction::LD(Reg::H, Reg::D8)),
-            0x36 => Some(Instruction::LD(Reg::HL, Reg::D8)),
+            0x36 => Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::D8)),
 
             0x0E => Some(Instruction::LD(Reg::C, Reg::D8)),
             0x1E => Some(Instruction::LD(Reg::E, Reg::D8)),
@@ -570,7 +675,7 @@ impl Instruction {
 
             0x44 => Some(Instruction::LD(Reg::B, Reg::H)),
             0x45 => Some(Instruction::LD(Reg::B, Reg::L)),
-            0x46 => Some(Instruction::LD(Reg::B, Reg::HL)),
+            0x46 => Some(Instruction::LD(Reg::B, Reg::Addr(Reg16::HL))),
             0x47 => Some(Instruction::LD(Reg::B, Reg::A)),
 
             0x48 => Some(Instruction::LD(Reg::C, Reg::B)),
@@ -580,7 +685,7 @@ impl Instruction {
 
             0x4C => Some(Instruction::LD(Reg::C, Reg::H)),
             0x4D => Some(Instruction::LD(Reg::C, Reg::L)),
-            0x4E => Some(Instruction::LD(Reg::C, Reg::HL)),
+            0x4E => Some(Instruction::LD(Reg::C, Reg::Addr(Reg16::HL))),
             0x4F => Some(Instruction::LD(Reg::C, Reg::A)),
 
             0x50 => Some(Instruction::LD(Reg::D, Reg::B)),
@@ -589,8 +694,8 @@ impl Instruction {
             0x53 => Some(Instruction::LD(Reg::D, Reg::E)),
 
             0x54 => Some(Instruction::LD(Reg::D, Reg::H)),
-            0x55 => Some(Instruction::LD(Reg::D, Reg::H)),
-            0x56 => Some(Instruction::LD(Reg::D, Reg::H)),
+            0x55 => Some(Instruction::LD(Reg::D, Reg::L)),
+            0x56 => Some(Instruction::LD(Reg::D, Reg::Addr(Reg16::HL))),
             0x57 => Some(Instruction::LD(Reg::D, Reg::A)),
 
             0x58 => Some(Instruction::LD(Reg::E, Reg::B)),
@@ -600,7 +705,7 @@ impl Instruction {
 
             0x5C => Some(Instruction::LD(Reg::E, Reg::H)),
             0x5D => Some(Instruction::LD(Reg::E, Reg::L)),
-            0x5E => Some(Instruction::LD(Reg::E, Reg::HL)),
+            0x5E => Some(Instruction::LD(Reg::E, Reg::Addr(Reg16::HL))),
             0x5F => Some(Instruction::LD(Reg::E, Reg::A)),
 
             0x60 => Some(Instruction::LD(Reg::H, Reg::B)),
@@ -610,7 +715,7 @@ impl Instruction {
 
             0x64 => Some(Instruction::LD(Reg::H, Reg::H)),
             0x65 => Some(Instruction::LD(Reg::H, Reg::L)),
-            0x66 => Some(Instruction::LD(Reg::H, Reg::HL)),
+            0x66 => Some(Instruction::LD(Reg::H, Reg::Addr(Reg16::HL))),
             0x67 => Some(Instruction::LD(Reg::H, Reg::A)),
 
             0x68 => Some(Instruction::LD(Reg::L, Reg::B)),
@@ -620,18 +725,18 @@ impl Instruction {
 
             0x6C => Some(Instruction::LD(Reg::L, Reg::H)),
             0x6D => Some(Instruction::LD(Reg::L, Reg::L)),
-            0x6E => Some(Instruction::LD(Reg::L, Reg::HL)),
+            0x6E => Some(Instruction::LD(Reg::L, Reg::Addr(Reg16::HL))),
             0x6F => Some(Instruction::LD(Reg::L, Reg::A)),
 
-            0x70 => Some(Instruction::LD(Reg::HL, Reg::B)),
-            0x71 => Some(Instruction::LD(Reg::HL, Reg::C)),
-            0x72 => Some(Instruction::LD(Reg::HL, Reg::D)),
-            0x73 => Some(Instruction::LD(Reg::HL, Reg::E)),
+            0x70 => Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::B)),
+            0x71 => Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::C)),
+            0x72 => Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::D)),
+            0x73 => Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::E)),
 
-            0x74 => Some(Instruction::LD(Reg::HL, Reg::H)),
-            0x75 => Some(Instruction::LD(Reg::HL, Reg::L)),
+            0x74 => Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::H)),
+            0x75 => Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::L)),
             // 0x76: halt
-            0x77 => Some(Instruction::LD(Reg::HL, Reg::A)),
+            0x77 => Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::A)),
 
             0x78 => Some(Instruction::LD(Reg::A, Reg::B)),
             0x79 => Some(Instruction::LD(Reg::A, Reg::C)),
@@ -640,13 +745,13 @@ impl Instruction {
 
             0x7C => Some(Instruction::LD(Reg::A, Reg::H)),
             0x7D => Some(Instruction::LD(Reg::A, Reg::L)),
-            0x7E => Some(Instruction::LD(Reg::A, Reg::HL)),
+            0x7E => Some(Instruction::LD(Reg::A, Reg::Addr(Reg16::HL))),
             0x7F => Some(Instruction::LD(Reg::A, Reg::A)),
 
             0x01 => Some(Instruction::LD(Reg::BC, Reg::D16)),
             0x11 => Some(Instruction::LD(Reg::DE, Reg::D16)),
             0x21 => Some(Instruction::LD(Reg::HL, Reg::D16)),
-            // 0x31 => Some(Instruction::LD(Reg::SP, Reg::D16)),
+            0x31 => Some(Instruction::LD(Reg::SP, Reg::D16)),
 
             0x02 => Some(Instruction::LD(Reg::Addr(Reg16::BC), Reg::A)),
             0x12 => Some(Instruction::LD(Reg::Addr(Reg16::DE), Reg::A)),
@@ -658,6 +763,17 @@ impl Instruction {
             0x2A => Some(Instruction::LD(Reg::A, Reg::Addr(Reg16::HLI))),
             0x3A => Some(Instruction::LD(Reg::A, Reg::Addr(Reg16::HLD))),
 
+            0x08 => Some(Instruction::LD(Reg::A16, Reg::SP)),
+            0xEA => Some(Instruction::LD(Reg::A16, Reg::A)),
+            0xFA => Some(Instruction::LD(Reg::A, Reg::A16)),
+            0xF9 => Some(Instruction::LD(Reg::SP, Reg::HL)),
+            0xF8 => Some(Instruction::LDHLSP),
+
+            0xE0 => Some(Instruction::LDH(Reg::D8, Reg::A)),
+            0xF0 => Some(Instruction::LDH(Reg::A, Reg::D8)),
+            0xE2 => Some(Instruction::LDH(Reg::C, Reg::A)),
+            0xF2 => Some(Instruction::LDH(Reg::A, Reg::C)),
+
 
             0xC5 => Some(Instruction::PUSH(Reg16::BC)),
             0xD5 => Some(Instruction::PUSH(Reg16::DE)),
@@ -672,21 +788,50 @@ impl Instruction {
 
 
             0x76 => Some(Instruction::HALT),
+            0x10 => Some(Instruction::STOP),
+            0xF3 => Some(Instruction::DI),
+            0xFB => Some(Instruction::EI),
 
 
-            0x10 => Some(Instruction::IDK),  // TODO: STOP INSTRUCTION
+            0x27 => Some(Instruction::DAA),
 
 
-            // 0x27 => Some(Instruction::DAA),
+            // the illegal opcodes: 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD
+            _ => None,
+        }
+    }
+}
 
 
-            // UNREAL INSTRUCIONS!
-            // DELULU INSTRUCTIONS!
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler;
 
-            0xF4 => Some(Instruction::IDK), // doesn't exist in the gameboy. debug purposes (custom)
-            0xD3 => Some(Instruction::EXIT),    // not a real instruction(custom)
+    #[test]
+    fn every_opcode_is_its_own_instruction() {
+        for prefixed in [false, true] {
+            for byte in 0..=0xFF {
+                let instruction = Instruction::from_byte(byte, prefixed);
 
-            _ => None,
+                // 0xCB is only a prefix
+                if disassembler::opcode_template(byte, prefixed).is_none() || (!prefixed && byte == 0xCB) {
+                    assert_eq!(instruction, None, "0x{byte:02X}");
+                    continue;
+                }
+
+                let instruction = instruction.unwrap_or_else(|| panic!("0x{byte:02X} (prefixed: {prefixed}) doesn't decode"));
+                assert_eq!(instruction.opcode(), Some((byte, prefixed)), "{instruction:?}");
+            }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hl_and_its_byte_are_different_operands() {
+        assert_eq!(Instruction::from_byte(0x23, false), Some(Instruction::INC(Reg::HL)));
+        assert_eq!(Instruction::from_byte(0x34, false), Some(Instruction::INC(Reg::Addr(Reg16::HL))));
+        assert_eq!(Instruction::from_byte(0x77, false), Some(Instruction::LD(Reg::Addr(Reg16::HL), Reg::A)));
+        assert_eq!(Instruction::from_byte(0x36, true), Some(Instruction::SWAP(Reg::Addr(Reg16::HL))));
+    }
+}
+
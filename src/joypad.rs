@@ -0,0 +1,42 @@
+/*
+    The joypad register (0xFF00): the game selects a button group with bits 4-5
+    (0 = selected), then reads the group's buttons in bits 0-3 (0 = pressed).
+*/
+
+// one bit per button in `Joypad::pressed`, also how movies store them
+pub const RIGHT: u8 = 1 << 0;
+pub const LEFT: u8 = 1 << 1;
+pub const UP: u8 = 1 << 2;
+pub const DOWN: u8 = 1 << 3;
+pub const A: u8 = 1 << 4;
+pub const B: u8 = 1 << 5;
+pub const SELECT: u8 = 1 << 6;
+pub const START: u8 = 1 << 7;
+
+const SELECT_DIRECTIONS: u8 = 1 << 4;
+const SELECT_ACTIONS: u8 = 1 << 5;
+
+pub struct Joypad {
+    pub pressed: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad { pressed: 0 }
+    }
+
+    // what the game reads at 0xFF00, `select` being the last value written there
+    pub fn read(&self, select: u8) -> u8 {
+        let mut buttons: u8 = 0x0F;
+
+        if select & SELECT_DIRECTIONS == 0 {
+            buttons &= !(self.pressed & 0x0F);
+        }
+        if select & SELECT_ACTIONS == 0 {
+            buttons &= !(self.pressed >> 4);
+        }
+
+        // the unused top bits always read as 1
+        0xC0 | (select & 0x30) | buttons
+    }
+}
@@ -0,0 +1,333 @@
+use std::{fmt, fs::{self, File}, io::{self, BufWriter, Write}, ops::RangeInclusive, path::PathBuf, str::FromStr, sync::{atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering}, Mutex}};
+
+use crate::utils;
+
+/*
+    Leveled logging, split by subsystem, set up at launch (no recompiling):
+
+        crusty-boy <rom> --log info,cpu=trace,mem=debug --log-path data/cpu.txt
+                         --trace-pc 0x0150-0x01FF --trace-after 100000
+
+    or the same keys in data/config.txt (`log = info,cpu=trace`, `log_path = ...`,
+    `trace_pc = ...`, `trace_after = ...`). The levels are off, error, warn, info, debug
+    and trace; a bare one sets every category, `category=level` just one.
+
+    Everything enabled goes to the log file (buffered, flushed on errors, on exit and
+    on panics) and to stdout, except trace messages, which are one per instruction
+    and only go to the file. Trace messages can be limited to the instructions in a
+    PC range and/or the ones after the first N. A frontend drawing in the terminal
+    turns stdout off while it does (`set_console`), the file still gets everything.
+
+    Checking a level is a couple of atomic loads, so hot paths can ask `enabled`
+    before formatting anything.
+*/
+
+const DEFAULT_PATH: &str = "data/logs.txt";
+const DEFAULT_LEVEL: Level = Level::Info;
+const CATEGORY_COUNT: usize = 7;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Category {
+    // the frontends and tools, what isn't emulation
+    General,
+    Cpu,
+    Mem,
+    Ppu,
+    Apu,
+    Mbc,
+    Serial,
+}
+
+const CATEGORIES: [Category; CATEGORY_COUNT] = [
+    Category::General, Category::Cpu, Category::Mem, Category::Ppu, Category::Apu, Category::Mbc, Category::Serial,
+];
+
+#[derive(Clone)]
+pub struct LogSettings {
+    // in the order of CATEGORIES
+    pub levels: [Level; CATEGORY_COUNT],
+    pub path: PathBuf,
+    pub trace_pc: Option<RangeInclusive<u16>>,
+    pub trace_after: Option<u64>,
+}
+
+struct Output {
+    file: Option<BufWriter<File>>,
+    // what was logged before `init`, written once the file is open
+    pending: Option<Vec<String>>,
+}
+
+static LEVELS: [AtomicU8; CATEGORY_COUNT] = [const { AtomicU8::new(DEFAULT_LEVEL as u8) }; CATEGORY_COUNT];
+static OUTPUT: Mutex<Output> = Mutex::new(Output { file: None, pending: Some(Vec::new()) });
+
+// the trace window, only looked at when a category traces
+static ANY_TRACE: AtomicBool = AtomicBool::new(false);
+static TRACE_ACTIVE: AtomicBool = AtomicBool::new(true);
+static INSTRUCTIONS: AtomicU64 = AtomicU64::new(0);
+static TRACE_WINDOW: Mutex<(Option<RangeInclusive<u16>>, Option<u64>)> = Mutex::new((None, None));
+
+// off while the terminal frontend owns the screen
+static CONSOLE: AtomicBool = AtomicBool::new(true);
+
+impl LogSettings {
+    pub fn new() -> Self {
+        LogSettings {
+            levels: [DEFAULT_LEVEL; CATEGORY_COUNT],
+            path: PathBuf::from(DEFAULT_PATH),
+            trace_pc: None,
+            trace_after: None,
+        }
+    }
+
+    // the config file's keys, also what the command line options set
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "log" => self.set_levels(value)?,
+            "log_path" => self.path = PathBuf::from(value),
+            "trace_pc" => {
+                let (start, end) = value.split_once('-').unwrap_or((value, value));
+                let address = |text: &str| utils::parse_number(text.trim())
+                    .filter(|address| *address <= 0xFFFF)
+                    .ok_or(format!("invalid address \"{text}\""));
+
+                self.trace_pc = Some(address(start)? as u16..=address(end)? as u16);
+            },
+            "trace_after" => self.trace_after = Some(utils::parse_number(value).ok_or(format!("invalid count \"{value}\""))?),
+            _ => return Err(format!("unknown log setting \"{key}\"")),
+        }
+
+        Ok(())
+    }
+
+    // "info,cpu=trace": a bare level is for every category
+    fn set_levels(&mut self, spec: &str) -> Result<(), String> {
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                Some((category, level)) => {
+                    let category: Category = category.trim().parse()?;
+                    self.levels[category as usize] = level.trim().parse()?;
+                },
+                None => self.levels = [part.parse()?; CATEGORY_COUNT],
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+        Takes the logging options out of the command line, so whatever runs next
+        doesn't see them: --log, --log-path, --trace-pc and --trace-after.
+     */
+    pub fn take_args(&mut self, args: &mut Vec<String>) -> Result<(), String> {
+        let mut index = 0;
+
+        while index < args.len() {
+            let key = match args[index].as_str() {
+                "--log" => "log",
+                "--log-path" => "log_path",
+                "--trace-pc" => "trace_pc",
+                "--trace-after" => "trace_after",
+                _ => {
+                    index += 1;
+                    continue;
+                },
+            };
+
+            let value = args.get(index + 1).cloned().ok_or(format!("{} needs a value", args[index]))?;
+            self.set(key, &value)?;
+            args.drain(index..index + 2);
+        }
+
+        Ok(())
+    }
+}
+
+/**
+    Applies `settings` and opens the log file (truncated), with whatever was
+    logged until now. Also makes panics flush the file first.
+ */
+pub fn init(settings: &LogSettings) -> io::Result<()> {
+    for (level, setting) in LEVELS.iter().zip(settings.levels) {
+        level.store(setting as u8, Ordering::Relaxed);
+    }
+
+    let traces = settings.levels.contains(&Level::Trace);
+    ANY_TRACE.store(traces, Ordering::Relaxed);
+    TRACE_ACTIVE.store(settings.trace_pc.is_none() && settings.trace_after.is_none_or(|after| after == 0), Ordering::Relaxed);
+    *lock(&TRACE_WINDOW) = (settings.trace_pc.clone(), settings.trace_after);
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // the panic might come from the logger itself, holding the lock
+        if let Ok(mut output) = OUTPUT.try_lock() {
+            output.flush();
+        }
+        default_hook(info);
+    }));
+
+    // from here on, without a file, lines are only printed
+    let pending = lock(&OUTPUT).pending.take().unwrap_or_default();
+
+    if let Some(parent) = settings.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = BufWriter::new(File::create(&settings.path)?);
+
+    for line in pending {
+        writeln!(file, "{line}")?;
+    }
+    lock(&OUTPUT).file = Some(file);
+
+    Ok(())
+}
+
+pub fn enabled(category: Category, level: Level) -> bool {
+    level as u8 <= LEVELS[category as usize].load(Ordering::Relaxed)
+        && (level != Level::Trace || TRACE_ACTIVE.load(Ordering::Relaxed))
+}
+
+/**
+    Called by the CPU before each instruction, moves the trace window along.
+    Costs a single load when nothing traces.
+ */
+pub fn on_instruction(pc: u16) {
+    if !ANY_TRACE.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let count = INSTRUCTIONS.fetch_add(1, Ordering::Relaxed);
+    let window = lock(&TRACE_WINDOW);
+
+    let in_range = window.0.as_ref().is_none_or(|range| range.contains(&pc));
+    let past_start = window.1.is_none_or(|after| count >= after);
+    TRACE_ACTIVE.store(in_range && past_start, Ordering::Relaxed);
+}
+
+// whether messages also go to stdout, the file always gets them
+pub fn set_console(enabled: bool) {
+    CONSOLE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn write(category: Category, level: Level, message: &str) {
+    if !enabled(category, level) {
+        return;
+    }
+
+    // not println!, a closed stdout (piped into `head`) would panic while dumping a crash
+    if level != Level::Trace && CONSOLE.load(Ordering::Relaxed) {
+        let _ = writeln!(io::stdout(), "{message}");
+    }
+
+    let mut output = lock(&OUTPUT);
+    let output = &mut *output;
+    let line = format!("{:<5} {:<7} {message}", level.to_string(), category.to_string());
+
+    match (&mut output.file, &mut output.pending) {
+        (Some(file), _) => {
+            let _ = writeln!(file, "{line}");
+        },
+        (None, Some(pending)) => pending.push(line),
+        (None, None) => {},
+    }
+
+    // so they're in the file even if what comes next takes the process down
+    if level <= Level::Warn {
+        output.flush();
+    }
+}
+
+pub fn error(category: Category, message: &str) {
+    write(category, Level::Error, message);
+}
+
+pub fn warn(category: Category, message: &str) {
+    write(category, Level::Warn, message);
+}
+
+pub fn info(category: Category, message: &str) {
+    write(category, Level::Info, message);
+}
+
+pub fn debug(category: Category, message: &str) {
+    write(category, Level::Debug, message);
+}
+
+pub fn trace(category: Category, message: &str) {
+    write(category, Level::Trace, message);
+}
+
+// before exiting, what's buffered would be lost otherwise
+pub fn flush() {
+    lock(&OUTPUT).flush();
+}
+
+impl Output {
+    fn flush(&mut self) {
+        if let Some(file) = &mut self.file {
+            let _ = file.flush();
+        }
+    }
+}
+
+// a panic while logging leaves the lock poisoned, the data is still fine
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        })
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        [Level::Off, Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace].into_iter()
+            .find(|level| level.to_string().eq_ignore_ascii_case(text))
+            .ok_or(format!("unknown log level \"{text}\""))
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Category::General => "general",
+            Category::Cpu => "cpu",
+            Category::Mem => "mem",
+            Category::Ppu => "ppu",
+            Category::Apu => "apu",
+            Category::Mbc => "mbc",
+            Category::Serial => "serial",
+        })
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        CATEGORIES.into_iter()
+            .find(|category| category.to_string().eq_ignore_ascii_case(text))
+            .ok_or(format!("unknown log category \"{text}\""))
+    }
+}
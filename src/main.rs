@@ -6,8 +6,37 @@ mod registers;
 mod utils;
 mod types;
 mod gpu;
+mod apu;
+#[cfg(feature = "audio")]
+mod audio;
 mod emu_window;
 mod rom;
+mod boot;
+mod patch;
+mod savestate;
+mod rewind;
+mod config;
+mod joypad;
+mod movie;
+mod capture;
+mod headless;
+mod pacing;
+mod presentation;
+mod filters;
+mod terminal;
+mod debugger;
+mod watchpoints;
+mod gdb_stub;
+mod disassembler;
+mod tracer;
+mod symbols;
+mod assembler;
+mod doctor;
+mod trace_diff;
+mod logger;
+mod history;
+mod error;
+mod cli;
 
 mod playground;
 
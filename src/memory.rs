@@ -1,26 +1,95 @@
-use crate::gpu::{GPU, VRAM_START, VRAM_END};
+use crate::{apu::{APU, NR10, NR52}, gpu::{GPU, VRAM_START, VRAM_END}, joypad::Joypad, logger::{self, Category, Level}, watchpoints::Watchpoints};
+
+pub const BOOT_ROM_SIZE: usize = 0x100;
+
+// bank 0 + bank 1, what's mapped without a memory bank controller
+pub const ROM_AREA_SIZE: usize = 0x8000;
+
+pub const BG_TILE_MAP_0: usize = 0x9800;
+pub const BG_TILE_MAP_1: usize = 0x9C00;
+
+// I/O registers
+pub const JOYP: u16 = 0xFF00;
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+pub const LCDC: u16 = 0xFF40;
+pub const SCY: u16 = 0xFF42;
+pub const SCX: u16 = 0xFF43;
+pub const LY: u16 = 0xFF44;
+pub const BGP: u16 = 0xFF47;
+pub const BOOT_ROM_DISABLE: u16 = 0xFF50;
+pub const IE: u16 = 0xFFFF;
 
 pub struct MemoryBus {
-    pub memory: [u8; 0xFFFF],
+    // the whole address space, IE (0xFFFF) included
+    pub memory: [u8; 0x10000],
     pub gpu: GPU,
+    pub apu: APU,
+    pub joypad: Joypad,
+
+    // mapped over 0x0000-0x00FF until something is written to 0xFF50
+    pub boot_rom: Option<Vec<u8>>,
+
+    // None unless the debugger set some, so the checks are skipped entirely
+    pub watchpoints: Option<Box<Watchpoints>>,
+
+    // what LY reads as, whatever the PPU does, see doctor.rs
+    pub stubbed_ly: Option<u8>,
 }
 
 impl MemoryBus {
     pub fn new() -> Self {
         MemoryBus {
-            memory: [0; 0xFFFF],
+            memory: [0; 0x10000],
             gpu: GPU::new(),
+            apu: APU::new(),
+            joypad: Joypad::new(),
+            boot_rom: None,
+            watchpoints: None,
+            stubbed_ly: None,
         }
     }
 
+    pub fn load_boot_rom(&mut self, mut data: Vec<u8>) {
+        data.resize(BOOT_ROM_SIZE, 0);
+        self.boot_rom = Some(data);
+    }
+
+    // TODO: banks past the first two need an MBC
+    pub fn load_rom(&mut self, data: &[u8]) {
+        let mapped_size = data.len().min(ROM_AREA_SIZE);
+        self.memory[..mapped_size].copy_from_slice(&data[..mapped_size]);
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
+        let byte = self.peek_byte(addr);
+
+        if let Some(watchpoints) = &self.watchpoints {
+            watchpoints.on_read(addr, byte);
+        }
+
+        byte
+    }
+
+    // reads without triggering watchpoints, for the debugger's own views
+    pub fn peek_byte(&self, addr: u16) -> u8 {
         let addr = addr as usize;
 
         match addr {
-            VRAM_START..VRAM_END => {
+            0..BOOT_ROM_SIZE if self.boot_rom.is_some() => {
+                return self.boot_rom.as_ref().unwrap()[addr];
+            },
+
+            VRAM_START..=VRAM_END => {
                 return self.gpu.vram_read_byte(addr - VRAM_START);
             },
 
+            _ if addr == JOYP as usize => {
+                return self.joypad.read(self.memory[addr]);
+            },
+
+            _ if addr == LY as usize && self.stubbed_ly.is_some() => self.stubbed_ly.unwrap(),
+
             _ => {
                 return self.memory[addr as usize];
             }
@@ -28,16 +97,93 @@ impl MemoryBus {
     }
 
     pub fn write_byte(&mut self, addr: u16, byte: u8) {
+        if let Some(watchpoints) = &self.watchpoints {
+            watchpoints.on_write(addr, self.peek_byte(addr), byte);
+        }
+
         let addr = addr as usize;
 
         match addr {
-            VRAM_START..VRAM_END => {
+            VRAM_START..=VRAM_END => {
                 return self.gpu.vram_write_byte(addr - VRAM_START, byte);
             },
 
+            // only the button group selection is writable
+            _ if addr == JOYP as usize => {
+                self.memory[addr] = (self.memory[addr] & !0x30) | (byte & 0x30);
+            },
+
             _ => {
+                // a non-zero write unmaps the boot ROM for good
+                if addr == BOOT_ROM_DISABLE as usize && byte != 0 && self.boot_rom.is_some() {
+                    logger::debug(Category::Mem, "[MEM] Boot ROM unmapped");
+                    self.boot_rom = None;
+                }
+                self.log_write(addr as u16, byte);
+
                 self.memory[addr as usize] = byte;
+
+                if (NR10..=NR52).contains(&(addr as u16)) {
+                    self.apu.write_register(addr as u16, byte);
+                }
             }
         }
     }
-}
\ No newline at end of file
+
+    // the writes that mean something to a subsystem, for --log mbc=debug and such
+    fn log_write(&self, addr: u16, byte: u8) {
+        match addr {
+            // no MBC yet, so bank switches land in the ROM itself
+            0x0000..=0x7FFF if logger::enabled(Category::Mbc, Level::Debug) => {
+                logger::debug(Category::Mbc, &format!("[MBC] Write of 0x{byte:02X} to 0x{addr:04X}, no MBC to handle it"));
+            },
+            SC if byte & 0x80 != 0 && logger::enabled(Category::Serial, Level::Debug) => {
+                let data = self.memory[SB as usize];
+                logger::debug(Category::Serial, &format!("[SERIAL] Transfer of 0x{data:02X} ({:?})", data as char));
+            },
+            LCDC if logger::enabled(Category::Ppu, Level::Debug) => {
+                logger::debug(Category::Ppu, &format!("[PPU] LCDC = 0b{byte:08b}"));
+            },
+            _ => {},
+        }
+    }
+
+    // reads past the watchpoints, the game isn't the one reading
+    pub fn render_frame(&mut self) {
+        let lcdc = self.peek_byte(LCDC);
+        let tile_map_start = if lcdc & 0b0000_1000 != 0 { BG_TILE_MAP_1 } else { BG_TILE_MAP_0 };
+        let tile_map = &self.memory[tile_map_start..tile_map_start + 0x400];
+
+        self.gpu.render_background(tile_map, lcdc, self.memory[SCY as usize], self.memory[SCX as usize], self.memory[BGP as usize]);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watchpoints::Watchpoint;
+
+    #[test]
+    fn ie_is_mapped() {
+        let mut mem_bus = MemoryBus::new();
+        mem_bus.write_byte(IE, 0x1F);
+
+        assert_eq!(mem_bus.read_byte(IE), 0x1F);
+        assert_eq!(mem_bus.peek_byte(0xFFFF), 0x1F);
+    }
+
+    #[test]
+    fn rendering_doesnt_trigger_watchpoints() {
+        let mut mem_bus = MemoryBus::new();
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.list.push(Watchpoint::parse("$FF40-$FF47 r").unwrap());
+        mem_bus.watchpoints = Some(Box::new(watchpoints));
+
+        mem_bus.render_frame();
+        assert!(mem_bus.watchpoints.as_ref().unwrap().take_hits().is_empty());
+
+        mem_bus.read_byte(LCDC);
+        assert_eq!(mem_bus.watchpoints.as_ref().unwrap().take_hits().len(), 1);
+    }
+}
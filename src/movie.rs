@@ -0,0 +1,317 @@
+use std::{fmt, fs, io, path::{Path, PathBuf}};
+
+use crate::{boot, cpu::CPU, gpu::Screen, rom::ROM, savestate::{self, SaveStateError}};
+
+/*
+    Input movies: the joypad state of every frame, plus where the run started from.
+    The emulator being deterministic, replaying the inputs from the same start
+    gives back the exact same frames.
+
+    Layout (little-endian):
+        magic "CBMV", version (u16)
+        ROM CRC32 (u32), ROM name length (u8) + name
+        start: 0 = power-on, 1 = save state (+ length (u32) + the state)
+        frame count (u32), then one byte per frame (see the bits in joypad.rs)
+*/
+
+const MAGIC: &[u8; 4] = b"CBMV";
+pub const MOVIE_VERSION: u16 = 1;
+
+const MOVIES_DIR: &str = "data/movies";
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    NotAMovie,
+    UnsupportedVersion(u16),
+    WrongRom { movie_rom: String, loaded_rom: String },
+    Truncated,
+    SaveState(SaveStateError),
+}
+
+pub enum MovieStart {
+    PowerOn,
+    SaveState(Vec<u8>),
+}
+
+pub struct Movie {
+    pub rom_crc32: u32,
+    pub rom_name: String,
+    pub start: MovieStart,
+    pub inputs: Vec<u8>,
+}
+
+impl Movie {
+    pub fn new(rom: &ROM, start: MovieStart) -> Self {
+        Movie {
+            rom_crc32: rom.crc32,
+            rom_name: rom.name.clone(),
+            start,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+
+        bytes.extend_from_slice(&self.rom_crc32.to_le_bytes());
+        let rom_name = &self.rom_name.as_bytes()[..self.rom_name.len().min(u8::MAX as usize)];
+        bytes.push(rom_name.len() as u8);
+        bytes.extend_from_slice(rom_name);
+
+        match &self.start {
+            MovieStart::PowerOn => bytes.push(0),
+            MovieStart::SaveState(state) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(state.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(state);
+            },
+        }
+
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.inputs);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = MovieReader { bytes, offset: 0 };
+
+        if reader.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+
+        let version = reader.read_u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_crc32 = reader.read_u32()?;
+        let name_length = reader.read_u8()? as usize;
+        let rom_name = String::from_utf8_lossy(reader.read_bytes(name_length)?).into_owned();
+
+        let start = match reader.read_u8()? {
+            0 => MovieStart::PowerOn,
+            _ => {
+                let state_length = reader.read_u32()? as usize;
+                MovieStart::SaveState(reader.read_bytes(state_length)?.to_vec())
+            },
+        };
+
+        let frame_count = reader.read_u32()? as usize;
+        let inputs = reader.read_bytes(frame_count)?.to_vec();
+
+        Ok(Movie { rom_crc32, rom_name, start, inputs })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MovieError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MovieError> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+
+    /**
+        The machine in the state the movie starts from.
+        Fails if the movie was recorded with another ROM.
+     */
+    pub fn start_cpu(&self, rom: &ROM) -> Result<CPU, MovieError> {
+        if self.rom_crc32 != rom.crc32 {
+            return Err(MovieError::WrongRom { movie_rom: self.rom_name.clone(), loaded_rom: rom.name.clone() });
+        }
+
+        let mut cpu = boot::power_on(rom);
+
+        if let MovieStart::SaveState(state) = &self.start {
+            savestate::load_state(&mut cpu, rom, state)?;
+        }
+
+        Ok(cpu)
+    }
+}
+
+
+// feeds a movie's inputs one frame at a time
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer { movie, frame: 0 }
+    }
+
+    // None once the movie is over
+    pub fn next_input(&mut self) -> Option<u8> {
+        let input = self.movie.inputs.get(self.frame).copied();
+        self.frame += 1;
+        input
+    }
+}
+
+
+/**
+    Replays a whole movie without a window. `on_frame` gets the frame index and the
+    machine after each frame, with the screen already rendered.
+ */
+#[allow(unused)]
+pub fn play(movie: &Movie, rom: &ROM, mut on_frame: impl FnMut(usize, &CPU)) -> Result<CPU, MovieError> {
+    let mut cpu = movie.start_cpu(rom)?;
+
+    for (frame, input) in movie.inputs.iter().enumerate() {
+        cpu.mem_bus.joypad.pressed = *input;
+        // like in the window, a locked CPU keeps the screen as it is
+        if let Err(error) = cpu.run_frame() {
+            cpu.report_error(&error);
+        }
+        cpu.mem_bus.render_frame();
+
+        on_frame(frame, &cpu);
+    }
+
+    Ok(cpu)
+}
+
+// CRC32 of the screen's color ids, two runs giving the same hashes showed the same frames
+pub fn frame_hash(screen: &Screen) -> u32 {
+    let pixels: Vec<u8> = screen.iter().map(|pixel| *pixel as u8).collect();
+    crc32fast::hash(&pixels)
+}
+
+// data/movies/TETRIS_1A2B3C4D.cbm
+pub fn movie_path(rom: &ROM) -> PathBuf {
+    let rom_name: String = rom.name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    PathBuf::from(MOVIES_DIR).join(format!("{rom_name}_{:08X}.cbm", rom.crc32))
+}
+
+
+struct MovieReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> MovieReader<'a> {
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], MovieError> {
+        let bytes = self.bytes.get(self.offset..self.offset + count).ok_or(MovieError::Truncated)?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, MovieError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, MovieError> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, MovieError> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+}
+
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(error) => write!(f, "{error}"),
+            MovieError::NotAMovie => write!(f, "not a Crusty-Boy movie"),
+            MovieError::UnsupportedVersion(version) => write!(
+                f, "movie version {version} isn't supported (expected {MOVIE_VERSION})"
+            ),
+            MovieError::WrongRom { movie_rom, loaded_rom } => write!(
+                f, "movie was recorded with \"{movie_rom}\", not the loaded ROM (\"{loaded_rom}\")"
+            ),
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::SaveState(error) => write!(f, "movie's starting state: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(error: io::Error) -> Self {
+        MovieError::Io(error)
+    }
+}
+
+impl From<SaveStateError> for MovieError {
+    fn from(error: SaveStateError) -> Self {
+        MovieError::SaveState(error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fills VRAM (tiles and map) with the joypad plus a counter, forever
+    fn drawing_rom() -> ROM {
+        let mut data = vec![0; 0x8000];
+        // jp 0x0150
+        data[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        data[0x150..0x166].copy_from_slice(&[
+            0x11, 0x00, 0xFF,   // ld de, 0xFF00
+            0x3E, 0x20,         // ld a, 0x20
+            0x12,               // ld (de), a (the d-pad)
+            0x21, 0x00, 0x80,   // ld hl, 0x8000
+            0x06, 0xA0,         // ld b, 0xA0
+            0x1A,               // loop: ld a, (de)
+            0x85,               // add a, l
+            0x22,               // ld (hl+), a
+            0x7C,               // ld a, h
+            0x90,               // sub b
+            0x20, 0xF9,         // jr nz, loop
+            0x26, 0x80,         // ld h, 0x80
+            0x18, 0xF5,         // jr loop
+        ]);
+        ROM::from_bytes(data)
+    }
+
+    fn frame_hashes(movie: &Movie, rom: &ROM) -> Vec<u32> {
+        let mut hashes = Vec::new();
+        play(movie, rom, |_, cpu| hashes.push(frame_hash(&cpu.mem_bus.gpu.screen))).unwrap();
+        hashes
+    }
+
+    #[test]
+    fn replays_the_same_frames() {
+        let rom = drawing_rom();
+        let mut movie = Movie::new(&rom, MovieStart::PowerOn);
+        movie.inputs = (0..60).map(|frame| if frame % 7 < 3 { 0x01 << (frame % 8) } else { 0 }).collect();
+
+        let first = frame_hashes(&movie, &rom);
+        let second = frame_hashes(&movie, &rom);
+
+        assert_eq!(first.len(), 60);
+        assert_eq!(first, second);
+        // something was drawn, so equal hashes mean something
+        assert!(first.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn survives_a_save_round_trip() {
+        let rom = drawing_rom();
+        let mut movie = Movie::new(&rom, MovieStart::PowerOn);
+        movie.inputs = vec![0x10, 0x00, 0x80, 0x04];
+
+        let loaded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(loaded.inputs, movie.inputs);
+        assert_eq!(frame_hashes(&loaded, &rom), frame_hashes(&movie, &rom));
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+/*
+    Keeps the window loop at the Game Boy's refresh rate (59.73 Hz), one emulated
+    frame per displayed frame, unless it's paused, in turbo or in slow motion.
+*/
+
+pub const REFRESH_RATE: f64 = 59.73;
+
+// past this many late frames, we stop trying to catch up
+const MAX_LAG_FRAMES: u32 = 5;
+
+pub struct FramePacer {
+    frame_duration: Duration,
+    next_frame: Instant,
+
+    pub paused: bool,
+    pub slow_motion: bool,
+    advance_requested: bool,
+
+    // emulated frames per displayed frame in turbo, 0 meaning as fast as possible
+    turbo_speed: u32,
+    // how many times longer a frame lasts in slow motion
+    slow_motion_factor: u32,
+}
+
+impl FramePacer {
+    pub fn new(turbo_speed: u32, slow_motion_factor: u32) -> Self {
+        FramePacer {
+            frame_duration: Duration::from_secs_f64(1.0 / REFRESH_RATE),
+            next_frame: Instant::now(),
+            paused: false,
+            slow_motion: false,
+            advance_requested: false,
+            turbo_speed,
+            slow_motion_factor: slow_motion_factor.max(1),
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // runs a single frame, only does something while paused
+    pub fn advance_frame(&mut self) {
+        if self.paused {
+            self.advance_requested = true;
+        }
+    }
+
+    // how many emulated frames to run before the next displayed one
+    pub fn frames_to_run(&mut self, turbo: bool) -> u32 {
+        if self.paused {
+            return std::mem::take(&mut self.advance_requested) as u32;
+        }
+
+        match (turbo, self.turbo_speed) {
+            (true, 0) => 1,     // uncapped: one frame, but no waiting in `wait`
+            (true, speed) => speed,
+            (false, _) => 1,
+        }
+    }
+
+    /**
+        Sleeps until it's time for the next displayed frame.
+        If we're running late (a slow frame, the window being dragged...), the schedule
+        is reset instead of running a burst of frames to catch up.
+     */
+    pub fn wait(&mut self, turbo: bool) {
+        let now = Instant::now();
+
+        if turbo && self.turbo_speed == 0 && !self.paused {
+            self.next_frame = now;
+            return;
+        }
+
+        let frame_duration = if self.slow_motion {
+            self.frame_duration * self.slow_motion_factor
+        } else {
+            self.frame_duration
+        };
+
+        self.next_frame += frame_duration;
+
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > frame_duration * MAX_LAG_FRAMES {
+            self.next_frame = now;
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use std::{fmt, fs, io, path::Path};
+
+/*
+    Soft-patching: IPS, UPS and BPS patches are applied to the ROM bytes in memory,
+    the ROM file itself is never touched.
+*/
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// source CRC32 + target CRC32 + patch CRC32
+const FOOTER_SIZE: usize = 12;
+
+const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+// the biggest cartridges (MBC5) are 8 MiB, a patch asking for more is broken
+const MAX_TARGET_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    Io(io::Error),
+    UnknownFormat,
+    // the patch ends in the middle of something
+    Truncated,
+    // offsets or sizes that overflow, or a target bigger than any cartridge
+    Malformed,
+    SourceSizeMismatch { expected: usize, actual: usize },
+    SourceChecksumMismatch { expected: u32, actual: u32 },
+    TargetChecksumMismatch { expected: u32, actual: u32 },
+    PatchChecksumMismatch { expected: u32, actual: u32 },
+}
+
+// what got patched, for the logs
+pub struct PatchReport {
+    pub path: String,
+    pub format: PatchFormat,
+    pub records: usize,
+    pub bytes_changed: usize,
+    pub old_size: usize,
+    pub new_size: usize,
+}
+
+
+/**
+    Looks for a patch with the same name as the ROM, right next to it
+    (`game.gb` -> `game.ips`, `game.ups` or `game.bps`).
+ */
+pub fn find_patch_for(rom_path: &str) -> Option<String> {
+    for extension in PATCH_EXTENSIONS {
+        let patch_path = Path::new(rom_path).with_extension(extension);
+
+        if patch_path.is_file() {
+            return Some(patch_path.to_string_lossy().into_owned());
+        }
+    }
+
+    None
+}
+
+pub fn apply_patch_file(data: &[u8], patch_path: &str) -> Result<(Vec<u8>, PatchReport), PatchError> {
+    let patch = fs::read(patch_path)?;
+
+    let (format, patched, records) = if patch.starts_with(IPS_MAGIC) {
+        let (patched, records) = apply_ips(data, &patch)?;
+        (PatchFormat::Ips, patched, records)
+    } else if patch.starts_with(UPS_MAGIC) {
+        let (patched, records) = apply_ups(data, &patch)?;
+        (PatchFormat::Ups, patched, records)
+    } else if patch.starts_with(BPS_MAGIC) {
+        let (patched, records) = apply_bps(data, &patch)?;
+        (PatchFormat::Bps, patched, records)
+    } else {
+        return Err(PatchError::UnknownFormat);
+    };
+
+    let common_size = data.len().min(patched.len());
+    let bytes_changed = data[..common_size].iter().zip(&patched[..common_size]).filter(|(old, new)| old != new).count()
+        + data.len().abs_diff(patched.len());
+
+    let report = PatchReport {
+        path: String::from(patch_path),
+        format,
+        records,
+        bytes_changed,
+        old_size: data.len(),
+        new_size: patched.len(),
+    };
+
+    Ok((patched, report))
+}
+
+/**
+    IPS: a list of (3 bytes offset, 2 bytes size, data) records, a size of 0 meaning
+    a run of the same byte. An optional 3 bytes size after "EOF" truncates the output.
+ */
+pub fn apply_ips(data: &[u8], patch: &[u8]) -> Result<(Vec<u8>, usize), PatchError> {
+    let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+    let mut output = data.to_vec();
+    let mut records = 0;
+
+    loop {
+        let offset_bytes = reader.read_bytes(3)?;
+        if offset_bytes == IPS_EOF {
+            break;
+        }
+
+        let offset = read_be(offset_bytes);
+        let size = read_be(reader.read_bytes(2)?);
+
+        let (size, bytes) = if size == 0 {
+            // RLE record
+            let run_length = read_be(reader.read_bytes(2)?);
+            let value = reader.read_byte()?;
+            (run_length, vec![value; run_length])
+        } else {
+            (size, reader.read_bytes(size)?.to_vec())
+        };
+
+        let end = offset.checked_add(size).ok_or(PatchError::Malformed)?;
+        if output.len() < end {
+            output.resize(end, 0);
+        }
+        output[offset..end].copy_from_slice(&bytes);
+
+        records += 1;
+    }
+
+    if let Ok(truncate_bytes) = reader.read_bytes(3) {
+        output.truncate(read_be(truncate_bytes));
+    }
+
+    Ok((output, records))
+}
+
+/**
+    UPS: hunks of XOR-ed bytes (each ends with a 0), separated by relative offsets.
+ */
+pub fn apply_ups(data: &[u8], patch: &[u8]) -> Result<(Vec<u8>, usize), PatchError> {
+    let footer = verify_footer(data, patch)?;
+
+    let mut reader = PatchReader::new(&patch[..patch.len() - FOOTER_SIZE], UPS_MAGIC.len());
+    let source_size = reader.read_number()?;
+    let target_size = checked_target_size(reader.read_number()?)?;
+
+    if source_size != data.len() {
+        return Err(PatchError::SourceSizeMismatch { expected: source_size, actual: data.len() });
+    }
+
+    let mut output = data.to_vec();
+    output.resize(target_size, 0);
+
+    let mut output_offset: usize = 0;
+    let mut records = 0;
+
+    while !reader.is_done() {
+        output_offset = output_offset.checked_add(reader.read_number()?).ok_or(PatchError::Malformed)?;
+
+        loop {
+            let xor_byte = reader.read_byte()?;
+
+            if output_offset < output.len() {
+                output[output_offset] ^= xor_byte;
+            }
+            output_offset += 1;
+
+            if xor_byte == 0 {
+                break;
+            }
+        }
+
+        records += 1;
+    }
+
+    verify_target(&output, footer)?;
+
+    Ok((output, records))
+}
+
+/**
+    BPS: the target is rebuilt from 4 kinds of actions (copy from the same offset in the source,
+    read from the patch, copy from anywhere in the source, copy from earlier in the target).
+ */
+pub fn apply_bps(data: &[u8], patch: &[u8]) -> Result<(Vec<u8>, usize), PatchError> {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+    const SOURCE_COPY: usize = 2;
+
+    let footer = verify_footer(data, patch)?;
+
+    let mut reader = PatchReader::new(&patch[..patch.len() - FOOTER_SIZE], BPS_MAGIC.len());
+    let source_size = reader.read_number()?;
+    let target_size = checked_target_size(reader.read_number()?)?;
+    let metadata_size = reader.read_number()?;
+    reader.read_bytes(metadata_size)?;
+
+    if source_size != data.len() {
+        return Err(PatchError::SourceSizeMismatch { expected: source_size, actual: data.len() });
+    }
+
+    let mut output: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+    let mut records = 0;
+
+    while !reader.is_done() {
+        let action = reader.read_number()?;
+        let length = (action >> 2) + 1;
+
+        // so a broken patch can't make the output grow past what it announced
+        if length > target_size - output.len() {
+            return Err(PatchError::Malformed);
+        }
+
+        match action & 0b11 {
+            SOURCE_READ => {
+                let start = output.len();
+                let bytes = data.get(start..start + length).ok_or(PatchError::Truncated)?;
+                output.extend_from_slice(bytes);
+            },
+
+            TARGET_READ => {
+                let bytes = reader.read_bytes(length)?;
+                output.extend_from_slice(bytes);
+            },
+
+            SOURCE_COPY => {
+                source_offset = source_offset.checked_add(reader.read_signed_number()?).ok_or(PatchError::Malformed)?;
+
+                for _ in 0..length {
+                    let byte = *data.get(source_offset as usize).ok_or(PatchError::Truncated)?;
+                    output.push(byte);
+                    source_offset += 1;
+                }
+            },
+
+            // TARGET_COPY, can overlap with what it's writing so it's byte by byte
+            _ => {
+                target_offset = target_offset.checked_add(reader.read_signed_number()?).ok_or(PatchError::Malformed)?;
+
+                for _ in 0..length {
+                    let byte = *output.get(target_offset as usize).ok_or(PatchError::Truncated)?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            },
+        }
+
+        records += 1;
+    }
+
+    if output.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+
+    verify_target(&output, footer)?;
+
+    Ok((output, records))
+}
+
+
+// UPS and BPS end the same way: CRC32 of the source, of the target, then of the patch itself
+struct Footer {
+    target_crc: u32,
+}
+
+fn verify_footer(data: &[u8], patch: &[u8]) -> Result<Footer, PatchError> {
+    if patch.len() < 4 + FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let source_crc = u32::from_le_bytes(footer[0..4].try_into().unwrap());
+    let target_crc = u32::from_le_bytes(footer[4..8].try_into().unwrap());
+    let patch_crc = u32::from_le_bytes(footer[8..12].try_into().unwrap());
+
+    let actual_patch_crc = crc32fast::hash(&patch[..patch.len() - 4]);
+    if actual_patch_crc != patch_crc {
+        return Err(PatchError::PatchChecksumMismatch { expected: patch_crc, actual: actual_patch_crc });
+    }
+
+    let actual_source_crc = crc32fast::hash(data);
+    if actual_source_crc != source_crc {
+        return Err(PatchError::SourceChecksumMismatch { expected: source_crc, actual: actual_source_crc });
+    }
+
+    Ok(Footer { target_crc })
+}
+
+fn verify_target(output: &[u8], footer: Footer) -> Result<(), PatchError> {
+    let actual_target_crc = crc32fast::hash(output);
+
+    if actual_target_crc != footer.target_crc {
+        return Err(PatchError::TargetChecksumMismatch { expected: footer.target_crc, actual: actual_target_crc });
+    }
+
+    Ok(())
+}
+
+fn checked_target_size(size: usize) -> Result<usize, PatchError> {
+    if size > MAX_TARGET_SIZE {
+        return Err(PatchError::Malformed);
+    }
+    Ok(size)
+}
+
+fn read_be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |value, byte| (value << 8) | *byte as usize)
+}
+
+
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(patch: &'a [u8], offset: usize) -> Self {
+        PatchReader { patch, offset }
+    }
+
+    fn is_done(&self) -> bool {
+        self.offset >= self.patch.len()
+    }
+
+    fn read_byte(&mut self) -> Result<u8, PatchError> {
+        let byte = *self.patch.get(self.offset).ok_or(PatchError::Truncated)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.offset.checked_add(count).ok_or(PatchError::Truncated)?;
+        let bytes = self.patch.get(self.offset..end).ok_or(PatchError::Truncated)?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    // UPS/BPS variable-length number: 7 bits per byte, the last one has bit 7 set
+    fn read_number(&mut self) -> Result<usize, PatchError> {
+        let mut number: usize = 0;
+        let mut shift: usize = 1;
+
+        loop {
+            let byte = self.read_byte()? as usize;
+            number = (byte & 0x7F).checked_mul(shift)
+                .and_then(|value| number.checked_add(value))
+                .ok_or(PatchError::Malformed)?;
+
+            if byte & 0x80 != 0 {
+                break;
+            }
+
+            shift = shift.checked_mul(1 << 7).ok_or(PatchError::Malformed)?;
+            number = number.checked_add(shift).ok_or(PatchError::Malformed)?;
+        }
+
+        Ok(number)
+    }
+
+    // BPS relative offsets: bit 0 is the sign
+    fn read_signed_number(&mut self) -> Result<isize, PatchError> {
+        let number = self.read_number()?;
+        let magnitude = (number >> 1) as isize;
+
+        Ok(if number & 1 != 0 { -magnitude } else { magnitude })
+    }
+}
+
+
+impl fmt::Display for PatchFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchFormat::Ips => write!(f, "IPS"),
+            PatchFormat::Ups => write!(f, "UPS"),
+            PatchFormat::Bps => write!(f, "BPS"),
+        }
+    }
+}
+
+impl fmt::Display for PatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "Applied {} patch \"{}\": {} records, {} bytes changed, size {} -> {} bytes",
+            self.format, self.path, self.records, self.bytes_changed, self.old_size, self.new_size
+        )
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Io(error) => write!(f, "couldn't read patch: {error}"),
+            PatchError::UnknownFormat => write!(f, "not an IPS, UPS or BPS patch"),
+            PatchError::Truncated => write!(f, "patch is truncated or corrupted"),
+            PatchError::Malformed => write!(f, "patch has out of range offsets or sizes"),
+            PatchError::SourceSizeMismatch { expected, actual } => write!(
+                f, "patch expects a {expected} bytes ROM, this one is {actual} bytes"
+            ),
+            PatchError::SourceChecksumMismatch { expected, actual } => write!(
+                f, "patch is for another ROM (expected CRC32 {expected:08X}, got {actual:08X})"
+            ),
+            PatchError::TargetChecksumMismatch { expected, actual } => write!(
+                f, "patched ROM doesn't have the expected CRC32 (expected {expected:08X}, got {actual:08X})"
+            ),
+            PatchError::PatchChecksumMismatch { expected, actual } => write!(
+                f, "patch file is corrupted (expected CRC32 {expected:08X}, got {actual:08X})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<io::Error> for PatchError {
+    fn from(error: io::Error) -> Self {
+        PatchError::Io(error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the inverse of PatchReader::read_number
+    fn encode_number(mut number: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (number & 0x7F) as u8;
+            number >>= 7;
+            if number == 0 {
+                bytes.push(0x80 | low);
+                return bytes;
+            }
+            bytes.push(low);
+            number -= 1;
+        }
+    }
+
+    // UPS and BPS patches end with the source, target and patch CRC32s
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for number in [0, 1, 0x7F, 0x80, 0x4080, 123_456_789] {
+            let bytes = encode_number(number);
+            assert_eq!(PatchReader::new(&bytes, 0).read_number().unwrap(), number);
+        }
+    }
+
+    #[test]
+    fn ips_applies_records_and_runs() {
+        let data = [0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);
+        // RLE: 3 times 0xCC from offset 6, past the end
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        patch.extend_from_slice(IPS_EOF);
+
+        let (output, records) = apply_ips(&data, &patch).unwrap();
+        assert_eq!(output, [0, 0, 0xAA, 0xBB, 0, 0, 0xCC, 0xCC, 0xCC]);
+        assert_eq!(records, 2);
+    }
+
+    #[test]
+    fn ips_truncated_record() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x04, 0xAA]);
+
+        assert!(matches!(apply_ips(&[0; 8], &patch), Err(PatchError::Truncated)));
+    }
+
+    #[test]
+    fn ups_xors_hunks() {
+        let source = [1, 2, 3, 4];
+        let target = [1, 9, 3, 4, 5];
+
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(target.len()));
+        patch.extend(encode_number(1));
+        patch.extend_from_slice(&[2 ^ 9, 0]);
+        patch.extend(encode_number(1));
+        patch.extend_from_slice(&[5, 0]);
+        let patch = with_footer(patch, &source, &target);
+
+        let (output, records) = apply_ups(&source, &patch).unwrap();
+        assert_eq!(output, target);
+        assert_eq!(records, 2);
+    }
+
+    #[test]
+    fn ups_malformed_number() {
+        let source = [1, 2, 3, 4];
+
+        // 11 bytes without the end bit don't fit in a usize
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x7F; 11]);
+        let patch = with_footer(patch, &source, &source);
+
+        assert!(matches!(apply_ups(&source, &patch), Err(PatchError::Malformed)));
+    }
+
+    #[test]
+    fn bps_rebuilds_target() {
+        let source = [1, 2, 3, 4];
+        let target = [1, 2, 3, 4, 2, 3, 7];
+
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(target.len()));
+        patch.extend(encode_number(0));
+        // source read of 4, source copy of 2 from +1, target read of 1
+        patch.extend(encode_number(3 << 2));
+        patch.extend(encode_number((1 << 2) | 2));
+        patch.extend(encode_number(1 << 1));
+        patch.extend(encode_number(1));
+        patch.push(7);
+        let patch = with_footer(patch, &source, &target);
+
+        let (output, records) = apply_bps(&source, &patch).unwrap();
+        assert_eq!(output, target);
+        assert_eq!(records, 3);
+    }
+
+    #[test]
+    fn bps_target_too_big() {
+        let source = [1, 2, 3, 4];
+
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(usize::MAX / 2));
+        let patch = with_footer(patch, &source, &source);
+
+        assert!(matches!(apply_bps(&source, &patch), Err(PatchError::Malformed)));
+    }
+}
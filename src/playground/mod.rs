@@ -28,6 +28,7 @@ const a_tile: [u8; 16] = [
     0x00, 0x00
 ];
 
+#[allow(unused)]
 pub fn play() {
     // 0-23
 
@@ -0,0 +1,124 @@
+use std::{fmt, str::FromStr};
+
+use minifb::{Window, WindowOptions};
+
+/*
+    Puts the emulator's image in the window: picks the window size from the scale,
+    and fits the image in whatever size the window ends up (resized, fullscreen...),
+    with black bars around it when the aspect ratios don't match.
+*/
+
+pub const MIN_SCALE: usize = 1;
+pub const MAX_SCALE: usize = 8;
+
+const LETTERBOX_COLOR: u32 = 0x000000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScaleMode {
+    // biggest whole multiple that fits, pixels stay square and sharp
+    Integer,
+    // as big as it fits, keeping the aspect ratio
+    Fit,
+    // fills the whole window
+    Stretch,
+}
+
+impl ScaleMode {
+    pub fn next(self) -> Self {
+        match self {
+            ScaleMode::Integer => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::Stretch,
+            ScaleMode::Stretch => ScaleMode::Integer,
+        }
+    }
+}
+
+/**
+    minifb can't switch a window to fullscreen nor tell the monitor's size, so
+    fullscreen is a borderless, topmost window of `fullscreen_size` (from the config).
+ */
+pub fn create_window(title: &str, (width, height): (usize, usize), scale: usize, fullscreen: Option<(usize, usize)>) -> Window {
+    let (window_width, window_height) = fullscreen.unwrap_or((width * scale, height * scale));
+
+    let options = WindowOptions {
+        resize: fullscreen.is_none(),
+        borderless: fullscreen.is_some(),
+        title: fullscreen.is_none(),
+        topmost: fullscreen.is_some(),
+        // we do the scaling ourselves, the buffer is always the window's size
+        scale_mode: minifb::ScaleMode::UpperLeft,
+        ..WindowOptions::default()
+    };
+
+    let mut window = Window::new(title, window_width, window_height, options).expect("Could not create window");
+    // FramePacer takes care of the timing
+    window.set_target_fps(0);
+
+    window
+}
+
+/**
+    Scales `source` (width x height) into `target` (target_width x target_height),
+    nearest neighbour, centered. `pixel_ratio` is how many source pixels make one
+    Game Boy pixel (filters upscale), integer scaling is done in Game Boy pixels.
+ */
+pub fn present(
+    source: &[u32], (width, height): (usize, usize), pixel_ratio: usize,
+    target: &mut [u32], (target_width, target_height): (usize, usize),
+    mode: ScaleMode,
+) {
+    let (dest_width, dest_height) = match mode {
+        ScaleMode::Integer => {
+            let (lcd_width, lcd_height) = (width / pixel_ratio.max(1), height / pixel_ratio.max(1));
+            let scale = (target_width / lcd_width).min(target_height / lcd_height).max(1);
+            (lcd_width * scale, lcd_height * scale)
+        },
+        ScaleMode::Fit => {
+            if target_width * height > target_height * width {
+                (target_height * width / height, target_height)
+            } else {
+                (target_width, target_width * height / width)
+            }
+        },
+        ScaleMode::Stretch => (target_width, target_height),
+    };
+
+    // a window smaller than one scale just crops the image
+    let offset_x = target_width.saturating_sub(dest_width) / 2;
+    let offset_y = target_height.saturating_sub(dest_height) / 2;
+
+    target.fill(LETTERBOX_COLOR);
+
+    for y in 0..dest_height.min(target_height) {
+        let source_row = (y * height / dest_height) * width;
+        let target_row = (y + offset_y) * target_width + offset_x;
+
+        for x in 0..dest_width.min(target_width) {
+            target[target_row + x] = source[source_row + x * width / dest_width];
+        }
+    }
+}
+
+
+impl FromStr for ScaleMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "integer" => Ok(ScaleMode::Integer),
+            "fit" => Ok(ScaleMode::Fit),
+            "stretch" => Ok(ScaleMode::Stretch),
+            _ => Err(format!("unknown scale mode \"{value}\"")),
+        }
+    }
+}
+
+impl fmt::Display for ScaleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScaleMode::Integer => write!(f, "integer"),
+            ScaleMode::Fit => write!(f, "fit"),
+            ScaleMode::Stretch => write!(f, "stretch"),
+        }
+    }
+}
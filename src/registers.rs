@@ -1,4 +1,4 @@
-use crate::utils::{log, panic_log};
+use crate::{error::EmuError, logger::{self, Category}};
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 #[allow(dead_code)]
@@ -6,6 +6,8 @@ pub enum Reg {
     A, B, C, D, E, FLAGS, H, L, 
     AF, BC, HL, DE, D8, D16, HLI, HLD,
     SP,
+    // the byte at the address after the opcode, [a16]
+    A16,
     // only r16 can store addresses
     Addr(Reg16),
 }
@@ -63,14 +65,18 @@ impl From<Reg16> for Reg {
             Reg16::BC => Reg::BC,
             Reg16::DE => Reg::DE,
             Reg16::AF => Reg::AF,
-            _ => unreachable!("the others are unique, man!"),
+            Reg16::HLI => Reg::HLI,
+            Reg16::HLD => Reg::HLD,
+            Reg16::SP => Reg::SP,
         }
     }
 }
 
-impl From<Reg> for Reg16 {
-    fn from(reg: Reg) -> Reg16 {
-        match reg {
+impl TryFrom<Reg> for Reg16 {
+    type Error = EmuError;
+
+    fn try_from(reg: Reg) -> Result<Reg16, EmuError> {
+        Ok(match reg {
             Reg::HL => Reg16::HL,
             Reg::BC => Reg16::BC,
             Reg::DE => Reg16::DE,
@@ -79,10 +85,8 @@ impl From<Reg> for Reg16 {
             Reg::HLI => Reg16::HLI,
             Reg::SP => Reg16::SP,
 
-            _ => {
-                unreachable!("Normally, you wouldn't try to convert a 8bit register into a 16bit one");
-            }
-        }
+            _ => return Err(EmuError::InvalidOperand(format!("{reg:?} isn't a 16bit register"))),
+        })
     }
 }
 
@@ -105,8 +109,8 @@ impl Registers {
         }
     }
 
-    pub fn get_reg_value(&self, reg: Reg) -> (u8, Reg) {
-        (
+    pub fn get_reg_value(&self, reg: Reg) -> Result<(u8, Reg), EmuError> {
+        Ok((
             match reg {
                 Reg::A => self.a,
                 Reg::B => self.b,
@@ -116,21 +120,15 @@ impl Registers {
                 Reg::H => self.h,
                 Reg::L => self.l,
                 Reg::FLAGS => self.flags.into_u8(),
-                Reg::HL | Reg::DE | Reg::AF | 
-                Reg::BC | Reg::SP => panic_log(&format!("Hey smarty, {reg:?} is 16bit.")),
-                Reg::D8 => unreachable!("'D8' means '8 direct bits'. It's not a register"),
-                Reg::D16 => unreachable!("'D16' means '16 direct bits'. It's not a register"),
-                Reg::HLI => unreachable!("'HLI' is 'HL' but incremented. It's not EXACTLY a register"),
-                Reg::HLD => unreachable!("'HLD' is 'HL' but decremented. It's not EXACTLY a register"),
-                Reg::Addr(_) => todo!("Can't get address as reg, yet..."),
+                _ => return Err(not_an_8bit_register(reg)),
             },
             reg
-        )
+        ))
     }
 
-    pub fn get_reg(&mut self, reg: Reg) -> (&mut u8, Reg) {
+    pub fn get_reg(&mut self, reg: Reg) -> Result<(&mut u8, Reg), EmuError> {
 
-        (
+        Ok((
             match reg {
                 Reg::A => &mut self.a,
                 Reg::B => &mut self.b,
@@ -142,20 +140,11 @@ impl Registers {
                 Reg::L | Reg::HL => &mut self.l,
 
                 // normally, u can't get the reference of non-existing variable.
-                // the following registers are calculated on runtime (ex. reg A + reg B)
-                Reg::FLAGS => unreachable!("Cannot get REG!"),
-                Reg::DE => unreachable!("Cannot get REG!"),
-                Reg::AF => unreachable!("Cannot get REG!"),
-                Reg::BC => unreachable!("Cannot get REG!"),
-                Reg::SP => panic_log("SP is 16bit, sucker!"),
-                Reg::D8 => unreachable!("'D8' means '8 direct bits'. It's not a register"),
-                Reg::D16 => unreachable!("'D16' means '16 direct bits'. It's not a register"),
-                Reg::HLI => unreachable!("'HLI' is 'HL' but incremented. It's not EXACTLY a register"),
-                Reg::HLD => unreachable!("'HLD' is 'HL' but decremented. It's not EXACTLY a register"),
-                Reg::Addr(_) => todo!("Can't get address as reg, yet..."),
+                // FLAGS is packed from the flags, the pairs are calculated on runtime (ex. reg A + reg B)
+                _ => return Err(not_an_8bit_register(reg)),
             },
             reg
-        )
+        ))
     }
 
     pub fn get_vreg_value(&self, vreg: Reg16) -> (u16, Reg16) {
@@ -188,16 +177,11 @@ impl Registers {
             },
 
             Reg16::SP => self.sp,
-
-            #[allow(unreachable_patterns)]
-            _ => {
-                panic_log("[vreg_value] Invalid registers pair! (must be AF, BC, DE, HL)");
-            },
         }, vreg)
     }
 
     #[allow(unused)]
-    pub fn set_vreg_liberal(&mut self, reg1: Reg, reg2: Reg, value: u16) -> () {
+    pub fn set_vreg_liberal(&mut self, reg1: Reg, reg2: Reg, value: u16) -> Result<(), EmuError> {
         // VRegs list: af, bc, de, hl
         let left_bits: u8 = ((value & 0xFF00) >> 8) as u8;
         let right_bits: u8 = (value & 0x00FF) as u8;
@@ -223,8 +207,10 @@ impl Registers {
                 self.l = right_bits;
             },
 
-            _ => panic_log("[liberal_vreg] Invalid registers pair! (must be AF, BC, DE, HL)"),
+            _ => return Err(EmuError::InvalidOperand(format!("[liberal_vreg] Invalid registers pair {reg1:?}{reg2:?}! (must be AF, BC, DE, HL)"))),
         }
+
+        Ok(())
     }
 
     pub fn set_vreg(&mut self, reg: Reg16, value: u16) -> () {
@@ -254,7 +240,7 @@ impl Registers {
 
             #[allow(unreachable_patterns)]
             _ => {
-                log("Can't set non-vreg");
+                logger::warn(Category::Cpu, "Can't set non-vreg");
             }
         }
     }
@@ -267,6 +253,11 @@ impl Registers {
     }
 }
 
+// D8/D16 are immediates, HLI/HLD and Addr are memory, the rest are 16bit
+fn not_an_8bit_register(reg: Reg) -> EmuError {
+    EmuError::InvalidOperand(format!("{reg:?} isn't an 8bit register"))
+}
+
 impl FlagsRegister {
     pub fn new() -> Self {
         FlagsRegister {
@@ -322,4 +313,28 @@ impl LCDControl {
             bg_window_enabled_priority: true,
         }
     }
+
+    pub fn into_u8(&self) -> u8 {
+        (self.lcd_ppu_enabled as u8) << 7 |
+        (self.window_tilemap_area as u8) << 6 |
+        (self.window_enabled as u8) << 5 |
+        (self.bg_window_tilemap_area as u8) << 4 |
+        (self.bg_tilemap_area as u8) << 3 |
+        (self.obj_size as u8) << 2 |
+        (self.obj_enabled as u8) << 1 |
+        (self.bg_window_enabled_priority as u8)
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        LCDControl {
+            lcd_ppu_enabled: (byte >> 7) & 0b1 != 0,
+            window_tilemap_area: (byte >> 6) & 0b1 != 0,
+            window_enabled: (byte >> 5) & 0b1 != 0,
+            bg_window_tilemap_area: (byte >> 4) & 0b1 != 0,
+            bg_tilemap_area: (byte >> 3) & 0b1 != 0,
+            obj_size: (byte >> 2) & 0b1 != 0,
+            obj_enabled: (byte >> 1) & 0b1 != 0,
+            bg_window_enabled_priority: byte & 0b1 != 0,
+        }
+    }
 }
\ No newline at end of file
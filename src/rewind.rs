@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+
+use crate::{cpu::CPU, rom::ROM, savestate};
+
+/*
+    Rewind: save states captured every few frames, kept in a ring buffer.
+
+    To keep memory bounded, only one state out of KEYFRAME_INTERVAL is stored whole,
+    the others are XOR-ed against their keyframe (almost all zeros, since little changes
+    between two snapshots). Everything is then compressed by packing the runs of zeros.
+
+    A group is dropped whole once the buffer is over capacity, so it holds between
+    `capacity - interval + 1` and `capacity` snapshots, the interval being at most the capacity.
+*/
+
+const KEYFRAME_INTERVAL: usize = 30;
+
+// a keyframe and the deltas that depend on it, dropped together
+struct SnapshotGroup {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+pub struct RewindBuffer {
+    groups: VecDeque<SnapshotGroup>,
+    snapshot_count: usize,
+
+    capture_interval: u32,
+    capacity: usize,
+    // KEYFRAME_INTERVAL, unless the whole buffer is smaller than that
+    keyframe_interval: usize,
+    frames_since_capture: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(capture_interval: u32, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+
+        RewindBuffer {
+            groups: VecDeque::new(),
+            snapshot_count: 0,
+            capture_interval: capture_interval.max(1),
+            capacity,
+            keyframe_interval: KEYFRAME_INTERVAL.min(capacity),
+            frames_since_capture: 0,
+        }
+    }
+
+    // call once per emulated frame, captures a snapshot when it's time to
+    pub fn on_frame(&mut self, cpu: &CPU, rom: &ROM) {
+        self.frames_since_capture += 1;
+
+        if self.frames_since_capture >= self.capture_interval {
+            self.frames_since_capture = 0;
+            self.push(savestate::save_state(cpu, rom));
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        let needs_keyframe = match self.groups.back() {
+            Some(group) => group.deltas.len() + 1 >= self.keyframe_interval || group_keyframe_len(group) != state.len(),
+            None => true,
+        };
+
+        if needs_keyframe {
+            self.groups.push_back(SnapshotGroup {
+                keyframe: compress(&state),
+                deltas: Vec::new(),
+            });
+        } else {
+            let group = self.groups.back_mut().unwrap();
+            let keyframe = decompress(&group.keyframe);
+            let delta: Vec<u8> = state.iter().zip(&keyframe).map(|(new, old)| new ^ old).collect();
+            group.deltas.push(compress(&delta));
+        }
+
+        self.snapshot_count += 1;
+
+        // the newest group has the snapshot just pushed, it's never the one to go
+        while self.snapshot_count > self.capacity && self.groups.len() > 1 {
+            let oldest = self.groups.pop_front().unwrap();
+            self.snapshot_count -= 1 + oldest.deltas.len();
+        }
+    }
+
+    // takes the most recent snapshot out of the buffer
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let group = self.groups.back_mut()?;
+        let keyframe = decompress(&group.keyframe);
+
+        let state = match group.deltas.pop() {
+            Some(delta) => decompress(&delta).iter().zip(&keyframe).map(|(delta, old)| delta ^ old).collect(),
+            None => {
+                self.groups.pop_back();
+                keyframe
+            },
+        };
+
+        self.snapshot_count -= 1;
+        self.frames_since_capture = 0;
+
+        Some(state)
+    }
+}
+
+
+fn group_keyframe_len(group: &SnapshotGroup) -> usize {
+    u32::from_le_bytes(group.keyframe[0..4].try_into().unwrap()) as usize
+}
+
+/**
+    Packs runs of zeros: the output starts with the original length (u32),
+    then it's (zero run length, literal length, literal bytes) chunks, lengths being u16.
+ */
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(data.len() / 8);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    let mut i = 0;
+    while i < data.len() {
+        let zeros_start = i;
+        while i < data.len() && data[i] == 0 && i - zeros_start < u16::MAX as usize {
+            i += 1;
+        }
+        let zero_run = i - zeros_start;
+
+        let literal_start = i;
+        while i < data.len() && i - literal_start < u16::MAX as usize {
+            // a couple of zeros aren't worth starting a new chunk
+            if data[i] == 0 && data.get(i + 1) == Some(&0) && data.get(i + 2) == Some(&0) {
+                break;
+            }
+            i += 1;
+        }
+
+        output.extend_from_slice(&(zero_run as u16).to_le_bytes());
+        output.extend_from_slice(&((i - literal_start) as u16).to_le_bytes());
+        output.extend_from_slice(&data[literal_start..i]);
+    }
+
+    output
+}
+
+fn decompress(compressed: &[u8]) -> Vec<u8> {
+    let length = u32::from_le_bytes(compressed[0..4].try_into().unwrap()) as usize;
+    let mut output: Vec<u8> = Vec::with_capacity(length);
+
+    let mut i = 4;
+    while i < compressed.len() {
+        let zero_run = u16::from_le_bytes([compressed[i], compressed[i + 1]]) as usize;
+        let literal_length = u16::from_le_bytes([compressed[i + 2], compressed[i + 3]]) as usize;
+        i += 4;
+
+        output.resize(output.len() + zero_run, 0);
+        output.extend_from_slice(&compressed[i..i + literal_length]);
+        i += literal_length;
+    }
+
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a state that's different for each index, with a long run of zeros like real ones
+    fn state(index: usize) -> Vec<u8> {
+        let mut state = vec![0u8; 256];
+        state[..8].copy_from_slice(&(index as u64).to_le_bytes());
+        state[200] = (index * 7) as u8;
+        state
+    }
+
+    #[test]
+    fn newest_state_survives_eviction() {
+        for capacity in [1, 10, 600] {
+            let mut buffer = RewindBuffer::new(1, capacity);
+            for index in 0..=capacity {
+                buffer.push(state(index));
+            }
+
+            assert!(buffer.snapshot_count <= capacity);
+            assert_eq!(buffer.pop(), Some(state(capacity)), "capacity {capacity}");
+        }
+    }
+
+    #[test]
+    fn pops_in_reverse_order() {
+        let mut buffer = RewindBuffer::new(1, 45);
+        for index in 0..45 {
+            buffer.push(state(index));
+        }
+
+        for index in (0..45).rev() {
+            assert_eq!(buffer.pop(), Some(state(index)));
+        }
+        assert_eq!(buffer.pop(), None);
+    }
+}
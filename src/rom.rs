@@ -1,27 +1,124 @@
-use std::{fs::File, io::Read};
+use std::{fmt, fs, io::{self, Cursor, Read}};
 
-use crate::utils::log;
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
 
+use crate::{patch::{self, PatchError, PatchReport}, playground::NIN};
+
+const ROM_LOGO_RANGE: std::ops::Range<usize> = 0x0104..0x0134;
+const ROM_TITLE_START: usize = 0x0134;
+const ROM_MANUFACTURER_CODE_RANGE: std::ops::Range<usize> = 0x013F..0x0143;
+const ROM_CGB_FLAG_BYTE_POS: usize = 0x0143;
+const ROM_NEW_LICENSEE_RANGE: std::ops::Range<usize> = 0x0144..0x0146;
+const ROM_SGB_FLAG_BYTE_POS: usize = 0x0146;
 const ROM_TYPE_BYTE_POS: usize = 0x0147;
-const ROM_SIZE_BYTE_POS: usize = 0x0147;
+const ROM_SIZE_BYTE_POS: usize = 0x0148;
+const ROM_RAM_SIZE_BYTE_POS: usize = 0x0149;
 const ROM_REGION_BYTE_POS: usize = 0x014A;
+const ROM_OLD_LICENSEE_BYTE_POS: usize = 0x014B;
 const ROM_VERSION_BYTE_POS: usize = 0x014C;
+const ROM_HEADER_CHECKSUM_BYTE_POS: usize = 0x014D;
+const ROM_GLOBAL_CHECKSUM_BYTE_POS: usize = 0x014E;
 const ROM_HEADER_CHECKSUM_RANGE: std::ops::RangeInclusive<usize> = 0x134..=0x14C;
+const ROM_HEADER_END: usize = 0x0150;
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ROM_EXTENSIONS: [&str; 2] = [".gb", ".gbc"];
+
+// when the old licensee code is this, the new one (0x0144-0x0145) is used instead
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
 
 #[derive(Clone)]
 pub struct ROM {
     pub name: String,
-    pub size: usize,
-    // TODO: RAM Size
+    pub size: usize,        // KB
+    pub ram_size: usize,    // bytes
     pub data: Vec<u8>,
-    pub cartridge_type: u8,
-    pub region: &'static str,
+    pub cartridge_type: CartridgeType,
+    pub region: Destination,
     pub version: u8,
-    pub header_checksum: u8,
-    // TODO: Manufacturer Code
-    // TODO: CGB Flag
-    // TODO: New  +  Old Licensee Code
-    // TODO SGB Flag
+    pub header_checksum: u8,    // calculated, the one in the header is at 0x014D
+    pub global_checksum: u16,   // same, the header's one is at 0x014E-0x014F
+    pub crc32: u32,             // of the whole file, identifies the ROM for save states
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: CgbFlag,
+    pub licensee: Licensee,
+    pub sgb_flag: SgbFlag,
+
+    // everything that looked off in the header, none of it stops us from running the ROM
+    pub warnings: Vec<HeaderWarning>,
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    // the file ends before the header does
+    TooSmall(usize),
+    // the file is smaller than what the header says
+    Truncated { declared: usize, actual: usize },
+    Archive(String),
+    NoRomInArchive,
+    MultipleRomsInArchive(Vec<String>),
+    Patch(PatchError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1 { ram: bool, battery: bool },
+    Mbc2 { battery: bool },
+    RomRam { battery: bool },
+    Mmm01 { ram: bool, battery: bool },
+    Mbc3 { timer: bool, ram: bool, battery: bool },
+    Mbc5 { rumble: bool, ram: bool, battery: bool },
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1,
+    Unknown(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgbFlag {
+    DmgOnly,
+    CgbEnhanced,    // 0x80: works on both
+    CgbOnly,        // 0xC0
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SgbFlag {
+    Unsupported,
+    Supported,      // 0x03
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeaderWarning {
+    LogoMismatch,
+    HeaderChecksumMismatch { expected: u8, calculated: u8 },
+    GlobalChecksumMismatch { expected: u16, calculated: u16 },
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    UnknownDestination(u8),
+    UnknownLicensee(Licensee),
+    // the file is bigger than what the header says, the rest is ignored by real hardware
+    OversizedFile { declared: usize, actual: usize },
 }
 
 
@@ -29,44 +126,200 @@ impl ROM {
 
     #[allow(unused)]
     /**
-        This is a dangerous function! If you don't make sure to initialize the values, 
+        This is a dangerous function! If you don't make sure to initialize the values,
         you'll be running the rest of the program with a fake rom!
      */
     pub fn new() -> Self {
         ROM {
             name: String::from("UNKNOWN"),
             size: 0,
+            ram_size: 0,
             data: vec![0; 0xFFFF],
-            cartridge_type: 0,
-            region: "NOWHERE",
+            cartridge_type: CartridgeType::RomOnly,
+            region: Destination::Unknown(0),
             version: 0,
-            header_checksum: 0
+            header_checksum: 0,
+            global_checksum: 0,
+            crc32: 0,
+            manufacturer_code: None,
+            cgb_flag: CgbFlag::DmgOnly,
+            licensee: Licensee::Old(0),
+            sgb_flag: SgbFlag::Unsupported,
+            warnings: Vec::new(),
         }
     }
 
-    pub fn read_rom(path: &str) -> Self {
-        let mut rom_file: File = File::open(path).expect("Couldn't open ROM");
-        let mut buffer = vec![0; 0xFFFF];
-        let buffer_len = buffer.len();
+    /**
+        Reads a ROM from a `.gb`/`.gbc` file, or from a `.zip`/`.gz` archive holding one.
+     */
+    pub fn read_rom(path: &str) -> Result<Self, RomError> {
+        ROM::from_file_bytes(ROM::read_rom_bytes(path)?)
+    }
+
+    /**
+        Same as `read_rom`, but applies an IPS/UPS/BPS patch first: `patch_path` if given,
+        otherwise a same-named patch next to the ROM if there's one.
+        With `fix_checksums`, the header and global checksums are recomputed after patching.
+     */
+    pub fn read_rom_with_patch(path: &str, patch_path: Option<&str>, fix_checksums: bool) -> Result<(Self, Option<PatchReport>), RomError> {
+        let mut buffer = ROM::read_rom_bytes(path)?;
+
+        let patch_path = patch_path.map(String::from).or_else(|| patch::find_patch_for(path));
+        let report = match patch_path {
+            Some(patch_path) => {
+                let (patched, report) = patch::apply_patch_file(&buffer, &patch_path).map_err(RomError::Patch)?;
+                buffer = patched;
+                Some(report)
+            },
+            None => None,
+        };
 
-        if buffer_len <= 0 {
-            panic!("Buffer length is zero!");
+        if fix_checksums {
+            ROM::fix_checksums(&mut buffer);
         }
-    
-        let _bytes_count: usize = rom_file.read(&mut buffer).expect("Couldn't read ROM");
-    
+
+        Ok((ROM::from_file_bytes(buffer)?, report))
+    }
+
+    fn from_file_bytes(buffer: Vec<u8>) -> Result<Self, RomError> {
+        if buffer.len() < ROM_HEADER_END {
+            return Err(RomError::TooSmall(buffer.len()));
+        }
+
+        let mut rom = ROM::from_bytes(buffer);
+        let declared = rom.size * 1024;
+        let actual = rom.data.len();
+
+        // an unknown size byte already gave us a warning
+        if declared != 0 {
+            if actual < declared {
+                return Err(RomError::Truncated { declared, actual });
+            }
+
+            if actual > declared {
+                rom.warnings.push(HeaderWarning::OversizedFile { declared, actual });
+            }
+        }
+
+        Ok(rom)
+    }
+
+    /**
+        Gets the raw ROM bytes out of the file, unpacking it first if it's an archive.
+        Archives are recognized by their magic bytes, not their extension.
+     */
+    pub fn read_rom_bytes(path: &str) -> Result<Vec<u8>, RomError> {
+        let file_bytes = fs::read(path)?;
+
+        if file_bytes.starts_with(&ZIP_MAGIC) {
+            ROM::unzip_rom(file_bytes)
+        } else if file_bytes.starts_with(&GZIP_MAGIC) {
+            let mut buffer = Vec::new();
+            GzDecoder::new(file_bytes.as_slice()).read_to_end(&mut buffer)?;
+            Ok(buffer)
+        } else {
+            Ok(file_bytes)
+        }
+    }
+
+    fn unzip_rom(zip_bytes: Vec<u8>) -> Result<Vec<u8>, RomError> {
+        let mut archive = ZipArchive::new(Cursor::new(zip_bytes)).map_err(|error| RomError::Archive(error.to_string()))?;
+
+        let rom_names: Vec<String> = archive.file_names()
+            .filter(|name| {
+                let name = name.to_lowercase();
+                ROM_EXTENSIONS.iter().any(|extension| name.ends_with(extension))
+            })
+            .map(String::from)
+            .collect();
+
+        match rom_names.len() {
+            0 => Err(RomError::NoRomInArchive),
+            1 => {
+                let mut rom_file = archive.by_name(&rom_names[0]).map_err(|error| RomError::Archive(error.to_string()))?;
+                let mut buffer = Vec::new();
+                rom_file.read_to_end(&mut buffer)?;
+                Ok(buffer)
+            },
+            _ => Err(RomError::MultipleRomsInArchive(rom_names)),
+        }
+    }
+
+    /**
+        Decodes the cartridge header. The data must be at least 0x150 bytes long.
+     */
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        let mut warnings: Vec<HeaderWarning> = Vec::new();
+
+        let cgb_flag = CgbFlag::from_byte(data[ROM_CGB_FLAG_BYTE_POS]);
+        let manufacturer_code = ROM::get_manufacturer_code(&data, cgb_flag);
+
+        let cartridge_type = CartridgeType::from_byte(data[ROM_TYPE_BYTE_POS]);
+        if let CartridgeType::Unknown(type_byte) = cartridge_type {
+            warnings.push(HeaderWarning::UnknownCartridgeType(type_byte));
+        }
+
+        let size = ROM::get_rom_size(data[ROM_SIZE_BYTE_POS]).unwrap_or_else(|| {
+            warnings.push(HeaderWarning::UnknownRomSize(data[ROM_SIZE_BYTE_POS]));
+            0
+        });
+
+        let ram_size = ROM::get_ram_size(data[ROM_RAM_SIZE_BYTE_POS]).unwrap_or_else(|| {
+            warnings.push(HeaderWarning::UnknownRamSize(data[ROM_RAM_SIZE_BYTE_POS]));
+            0
+        });
+
+        let region = Destination::from_byte(data[ROM_REGION_BYTE_POS]);
+        if let Destination::Unknown(region_byte) = region {
+            warnings.push(HeaderWarning::UnknownDestination(region_byte));
+        }
+
+        let licensee = Licensee::from_header(&data);
+        if licensee.name().is_none() {
+            warnings.push(HeaderWarning::UnknownLicensee(licensee.clone()));
+        }
+
+        if data[ROM_LOGO_RANGE] != NIN {
+            warnings.push(HeaderWarning::LogoMismatch);
+        }
+
+        let header_checksum = ROM::get_header_checksum(&data);
+        if header_checksum != data[ROM_HEADER_CHECKSUM_BYTE_POS] {
+            warnings.push(HeaderWarning::HeaderChecksumMismatch {
+                expected: data[ROM_HEADER_CHECKSUM_BYTE_POS],
+                calculated: header_checksum,
+            });
+        }
+
+        let global_checksum = ROM::get_global_checksum(&data);
+        let expected_global_checksum = u16::from_be_bytes([data[ROM_GLOBAL_CHECKSUM_BYTE_POS], data[ROM_GLOBAL_CHECKSUM_BYTE_POS + 1]]);
+        if global_checksum != expected_global_checksum {
+            warnings.push(HeaderWarning::GlobalChecksumMismatch {
+                expected: expected_global_checksum,
+                calculated: global_checksum,
+            });
+        }
+
         ROM {
-            name: ROM::get_rom_name(&buffer),
-            size: ROM::get_rom_size(buffer[ROM_SIZE_BYTE_POS]),
-            data: buffer.clone(),
-            cartridge_type: buffer[ROM_TYPE_BYTE_POS],
-            region: ROM::get_region(buffer[ROM_REGION_BYTE_POS]),
-            version: buffer[ROM_VERSION_BYTE_POS],
-            header_checksum: ROM::get_header_checksum(&buffer),
+            name: ROM::get_rom_name(&data, cgb_flag, manufacturer_code.is_some()),
+            size,
+            ram_size,
+            cartridge_type,
+            region,
+            version: data[ROM_VERSION_BYTE_POS],
+            header_checksum,
+            global_checksum,
+            crc32: crc32fast::hash(&data),
+            manufacturer_code,
+            cgb_flag,
+            licensee,
+            sgb_flag: SgbFlag::from_byte(data[ROM_SGB_FLAG_BYTE_POS]),
+            warnings,
+            data,
         }
     }
 
-    pub fn get_header_checksum(data: &Vec<u8>) -> u8 {
+    pub fn get_header_checksum(data: &[u8]) -> u8 {
         let mut checksum: u8 = 0;
 
         for address in ROM_HEADER_CHECKSUM_RANGE {
@@ -76,62 +329,80 @@ impl ROM {
         checksum
     }
 
-    pub fn get_region(region_byte: u8) -> &'static str {
-        match region_byte {
-            0x00 => "JAPAN",
-            0x01 => "WORLD",
-            _ => "UNKNOWN",
+    // patched ROMs usually don't bother updating those
+    pub fn fix_checksums(data: &mut [u8]) {
+        if data.len() < ROM_HEADER_END {
+            return;
         }
-    }
 
-    pub fn get_rom_size(size_byte: u8) -> usize {
-        32 * (1 << size_byte)
+        data[ROM_HEADER_CHECKSUM_BYTE_POS] = ROM::get_header_checksum(data);
+
+        let global_checksum = ROM::get_global_checksum(data).to_be_bytes();
+        data[ROM_GLOBAL_CHECKSUM_BYTE_POS] = global_checksum[0];
+        data[ROM_GLOBAL_CHECKSUM_BYTE_POS + 1] = global_checksum[1];
     }
 
-    pub fn get_cartridge_type_name(type_byte: u8) -> &'static str {
-        match type_byte {
-            0x00 => "ROM ONLY",
-            0x01 => "MBC1",
-            0x02 => "MBC1 + RAM",
-            0x03 => "MBC1 + RAM + BATTERY",
-            0x05 => "MBC2",
-            0x06 => "MBC2 + BATTERY",
-            0x08 => "ROM + RAM",
-            0x09 => "ROM + RAM + BATTERY",
-            0x0B => "MMM01",
-            0x0C => "MMM01 + RAM",
-            0x0D => "MMM01 + RAM + BATTERY",
-            0x0F => "MBC3 + TIMER + BATTERY",
-            0x10 => "MBC3 + TIMER + RAM + BATTERY 10",
-            0x11 => "MBC3",
-            0x12 => "MBC3 + RAM",
-            0x13 => "MBC3 + RAM + BATTERY",
-            0x19 => "MBC5",
-            0x1A => "MBC5 + RAM",
-            0x1B => "MBC5 + RAM + BATTERY",
-            0x1C => "MBC5 + RUMBLE",
-            0x1D => "MBC5 + RUMBLE + RAM",
-            0x1E => "MBC5 + RUMBLE + RAM + BATTERY",
-            0x20 => "MBC6",
-            0x22 => "MBC7 + SENSOR + RUMBLE + RAM + BATTERY",
-            0xFC => "POCKET CAMERA",
-            0xFD => "BANDAI TAMA5",
-            0xFE => "HuC3",
-            0xFF => "HuC1 + RAM + BATTERY",
-
-            _ => {
-                log(&format!("Unrecognized Cartridge Type: {type_byte}"));
-                "UnkownType"
+    // sum of every byte of the ROM, except the checksum itself
+    pub fn get_global_checksum(data: &[u8]) -> u16 {
+        let mut checksum: u16 = 0;
+
+        for (address, byte) in data.iter().enumerate() {
+            if address == ROM_GLOBAL_CHECKSUM_BYTE_POS || address == ROM_GLOBAL_CHECKSUM_BYTE_POS + 1 {
+                continue;
             }
+
+            checksum = checksum.wrapping_add(*byte as u16);
         }
+
+        checksum
     }
-    
-    pub fn get_rom_name(data: &Vec<u8>) -> String {
+
+    pub fn get_rom_size(size_byte: u8) -> Option<usize> {
+        match size_byte {
+            0x00..=0x08 => Some(32 * (1 << size_byte)),
+            _ => None,
+        }
+    }
+
+    pub fn get_ram_size(ram_size_byte: u8) -> Option<usize> {
+        match ram_size_byte {
+            0x00 => Some(0),
+            0x01 => Some(2 * 1024),     // unused, but some homebrew uses it
+            0x02 => Some(8 * 1024),
+            0x03 => Some(32 * 1024),
+            0x04 => Some(128 * 1024),
+            0x05 => Some(64 * 1024),
+            _ => None,
+        }
+    }
+
+    /**
+        Newer cartridges shortened the title to fit a 4 characters manufacturer code.
+        There's no flag for it, so we only trust it on CGB-aware cartridges and when it's 4 uppercase letters/digits.
+     */
+    pub fn get_manufacturer_code(data: &[u8], cgb_flag: CgbFlag) -> Option<String> {
+        let code_bytes = &data[ROM_MANUFACTURER_CODE_RANGE];
+
+        if cgb_flag != CgbFlag::DmgOnly && code_bytes.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit()) {
+            Some(code_bytes.iter().map(|byte| *byte as char).collect())
+        } else {
+            None
+        }
+    }
+
+    pub fn get_rom_name(data: &[u8], cgb_flag: CgbFlag, has_manufacturer_code: bool) -> String {
         let mut name_buffer: String = String::new();
-        // TODO: support CGB flag
 
-        for i in 0..16 {
-            let char_byte = data[i  +  0x0134];
+        let title_length = if has_manufacturer_code {
+            11
+        } else if cgb_flag != CgbFlag::DmgOnly {
+            15  // 0x0143 is the CGB flag
+        } else {
+            16
+        };
+
+        for i in 0..title_length {
+            let char_byte = data[i  +  ROM_TITLE_START];
 
             if char_byte == 0 {
                 break;
@@ -142,4 +413,413 @@ impl ROM {
 
         name_buffer
     }
-}
\ No newline at end of file
+}
+
+
+impl CartridgeType {
+    pub fn from_byte(type_byte: u8) -> Self {
+        match type_byte {
+            0x00 => CartridgeType::RomOnly,
+            0x01 => CartridgeType::Mbc1 { ram: false, battery: false },
+            0x02 => CartridgeType::Mbc1 { ram: true, battery: false },
+            0x03 => CartridgeType::Mbc1 { ram: true, battery: true },
+            0x05 => CartridgeType::Mbc2 { battery: false },
+            0x06 => CartridgeType::Mbc2 { battery: true },
+            0x08 => CartridgeType::RomRam { battery: false },
+            0x09 => CartridgeType::RomRam { battery: true },
+            0x0B => CartridgeType::Mmm01 { ram: false, battery: false },
+            0x0C => CartridgeType::Mmm01 { ram: true, battery: false },
+            0x0D => CartridgeType::Mmm01 { ram: true, battery: true },
+            0x0F => CartridgeType::Mbc3 { timer: true, ram: false, battery: true },
+            0x10 => CartridgeType::Mbc3 { timer: true, ram: true, battery: true },
+            0x11 => CartridgeType::Mbc3 { timer: false, ram: false, battery: false },
+            0x12 => CartridgeType::Mbc3 { timer: false, ram: true, battery: false },
+            0x13 => CartridgeType::Mbc3 { timer: false, ram: true, battery: true },
+            0x19 => CartridgeType::Mbc5 { rumble: false, ram: false, battery: false },
+            0x1A => CartridgeType::Mbc5 { rumble: false, ram: true, battery: false },
+            0x1B => CartridgeType::Mbc5 { rumble: false, ram: true, battery: true },
+            0x1C => CartridgeType::Mbc5 { rumble: true, ram: false, battery: false },
+            0x1D => CartridgeType::Mbc5 { rumble: true, ram: true, battery: false },
+            0x1E => CartridgeType::Mbc5 { rumble: true, ram: true, battery: true },
+            0x20 => CartridgeType::Mbc6,
+            0x22 => CartridgeType::Mbc7,
+            0xFC => CartridgeType::PocketCamera,
+            0xFD => CartridgeType::BandaiTama5,
+            0xFE => CartridgeType::HuC3,
+            0xFF => CartridgeType::HuC1,
+
+            _ => CartridgeType::Unknown(type_byte),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        let with_extras = |base: &str, extras: &[(bool, &str)]| -> String {
+            let mut name = String::from(base);
+
+            for (present, extra) in extras {
+                if *present {
+                    name.push_str(" + ");
+                    name.push_str(extra);
+                }
+            }
+
+            name
+        };
+
+        match *self {
+            CartridgeType::RomOnly => String::from("ROM ONLY"),
+            CartridgeType::Mbc1 { ram, battery } => with_extras("MBC1", &[(ram, "RAM"), (battery, "BATTERY")]),
+            CartridgeType::Mbc2 { battery } => with_extras("MBC2", &[(battery, "BATTERY")]),
+            CartridgeType::RomRam { battery } => with_extras("ROM + RAM", &[(battery, "BATTERY")]),
+            CartridgeType::Mmm01 { ram, battery } => with_extras("MMM01", &[(ram, "RAM"), (battery, "BATTERY")]),
+            CartridgeType::Mbc3 { timer, ram, battery } => with_extras("MBC3", &[(timer, "TIMER"), (ram, "RAM"), (battery, "BATTERY")]),
+            CartridgeType::Mbc5 { rumble, ram, battery } => with_extras("MBC5", &[(rumble, "RUMBLE"), (ram, "RAM"), (battery, "BATTERY")]),
+            CartridgeType::Mbc6 => String::from("MBC6"),
+            CartridgeType::Mbc7 => String::from("MBC7 + SENSOR + RUMBLE + RAM + BATTERY"),
+            CartridgeType::PocketCamera => String::from("POCKET CAMERA"),
+            CartridgeType::BandaiTama5 => String::from("BANDAI TAMA5"),
+            CartridgeType::HuC3 => String::from("HuC3"),
+            CartridgeType::HuC1 => String::from("HuC1 + RAM + BATTERY"),
+            CartridgeType::Unknown(type_byte) => format!("UnknownType (0x{type_byte:02X})"),
+        }
+    }
+}
+
+impl CgbFlag {
+    pub fn from_byte(cgb_byte: u8) -> Self {
+        match cgb_byte {
+            0x80 => CgbFlag::CgbEnhanced,
+            0xC0 => CgbFlag::CgbOnly,
+            _ => CgbFlag::DmgOnly,
+        }
+    }
+}
+
+impl SgbFlag {
+    pub fn from_byte(sgb_byte: u8) -> Self {
+        match sgb_byte {
+            0x03 => SgbFlag::Supported,
+            _ => SgbFlag::Unsupported,
+        }
+    }
+}
+
+impl Destination {
+    pub fn from_byte(region_byte: u8) -> Self {
+        match region_byte {
+            0x00 => Destination::Japan,
+            0x01 => Destination::Overseas,
+            _ => Destination::Unknown(region_byte),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Destination::Japan => "JAPAN",
+            Destination::Overseas => "WORLD",
+            Destination::Unknown(_) => "UNKNOWN",
+        }
+    }
+}
+
+impl Licensee {
+    pub fn from_header(data: &[u8]) -> Self {
+        let old_code = data[ROM_OLD_LICENSEE_BYTE_POS];
+
+        if old_code == USE_NEW_LICENSEE_CODE {
+            Licensee::New(data[ROM_NEW_LICENSEE_RANGE].iter().map(|byte| *byte as char).collect())
+        } else {
+            Licensee::Old(old_code)
+        }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Licensee::Old(code) => old_licensee_name(*code),
+            Licensee::New(code) => new_licensee_name(code),
+        }
+    }
+}
+
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(error) => write!(f, "couldn't read ROM: {error}"),
+            RomError::TooSmall(size) => write!(f, "ROM is {size} bytes long, too small to hold a header"),
+            RomError::Truncated { declared, actual } => write!(
+                f, "ROM is {actual} bytes long but its header says {declared} bytes, the file is probably truncated"
+            ),
+            RomError::Archive(error) => write!(f, "couldn't open archive: {error}"),
+            RomError::NoRomInArchive => write!(f, "archive doesn't contain any .gb/.gbc file"),
+            RomError::MultipleRomsInArchive(names) => write!(f, "archive contains more than one ROM: {}", names.join(", ")),
+            RomError::Patch(error) => write!(f, "couldn't apply patch: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(error: io::Error) -> Self {
+        RomError::Io(error)
+    }
+}
+
+impl fmt::Display for Licensee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name().unwrap_or("Unknown");
+
+        match self {
+            Licensee::Old(code) => write!(f, "{name} (0x{code:02X})"),
+            Licensee::New(code) => write!(f, "{name} (\"{code}\")"),
+        }
+    }
+}
+
+impl fmt::Display for HeaderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderWarning::LogoMismatch => write!(f, "Nintendo logo doesn't match, a real Game Boy would lock up"),
+            HeaderWarning::HeaderChecksumMismatch { expected, calculated } => write!(
+                f, "Header checksum mismatch (header says 0x{expected:02X}, calculated 0x{calculated:02X})"
+            ),
+            HeaderWarning::GlobalChecksumMismatch { expected, calculated } => write!(
+                f, "Global checksum mismatch (header says 0x{expected:04X}, calculated 0x{calculated:04X})"
+            ),
+            HeaderWarning::UnknownCartridgeType(type_byte) => write!(f, "Unrecognized Cartridge Type: 0x{type_byte:02X}"),
+            HeaderWarning::UnknownRomSize(size_byte) => write!(f, "Unrecognized ROM size: 0x{size_byte:02X}"),
+            HeaderWarning::UnknownRamSize(size_byte) => write!(f, "Unrecognized RAM size: 0x{size_byte:02X}"),
+            HeaderWarning::UnknownDestination(region_byte) => write!(f, "Unrecognized destination code: 0x{region_byte:02X}"),
+            HeaderWarning::UnknownLicensee(licensee) => write!(f, "Unrecognized licensee: {licensee}"),
+            HeaderWarning::OversizedFile { declared, actual } => write!(
+                f, "File is {actual} bytes long but the header says {declared} bytes, the extra bytes are ignored"
+            ),
+        }
+    }
+}
+
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "None",
+        0x01 => "Nintendo",
+        0x08 => "Capcom",
+        0x09 => "HOT-B",
+        0x0A => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C => "Elite Systems",
+        0x13 => "EA (Electronic Arts)",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F => "Virgin Games Ltd.",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kemco",
+        0x29 => "SETA Corporation",
+        0x30 => "Infogrames",
+        0x31 => "Nintendo",
+        0x32 => "Bandai",
+        0x34 => "Konami",
+        0x35 => "HectorSoft",
+        0x38 => "Capcom",
+        0x39 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 => "Atlus",
+        0x44 => "Malibu Interactive",
+        0x46 => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4A => "Virgin Games Ltd.",
+        0x4D => "Malibu Interactive",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA Corporation",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley Company",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x61 => "Virgin Games Ltd.",
+        0x67 => "Ocean Software",
+        0x69 => "EA (Electronic Arts)",
+        0x6E => "Elite Systems",
+        0x6F => "Electro Brain",
+        0x70 => "Infogrames",
+        0x71 => "Interplay Entertainment",
+        0x72 => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve Limited",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x7F => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai Corp.",
+        0x8E => "Ape Inc.",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kemco",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9D => "Banpresto",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA2 => "Bandai",
+        0xA4 => "Konami",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAA => "Broderbund",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB0 => "Acclaim Entertainment",
+        0xB1 => "ASCII Corporation or Nexsoft",
+        0xB2 => "Bandai",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy Corporation",
+        0xC0 => "Taito",
+        0xC2 => "Kemco",
+        0xC3 => "Square",
+        0xC4 => "Tokuma Shoten",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP, Inc.",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xCE => "Pony Canyon",
+        0xCF => "Angel",
+        0xD0 => "Taito",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD6 => "Naxat Soft",
+        0xD7 => "Copya System",
+        0xD9 => "Banpresto",
+        0xDA => "Tomy",
+        0xDB => "LJN",
+        0xDD => "Nippon Computer Systems",
+        0xDE => "Human Ent.",
+        0xDF => "Altron",
+        0xE0 => "Jaleco",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE3 => "Varie",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEB => "Atlus",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        0xFF => "LJN",
+
+        _ => return None,
+    })
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "00" => "None",
+        "01" => "Nintendo Research & Development 1",
+        "08" => "Capcom",
+        "13" => "EA (Electronic Arts)",
+        "18" => "Hudson Soft",
+        "19" => "B-AI",
+        "20" => "KSS",
+        "22" => "Planning Office WADA",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco",
+        "29" => "SETA Corporation",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean Software/Acclaim Entertainment",
+        "34" => "Konami",
+        "35" => "HectorSoft",
+        "37" => "Taito",
+        "38" => "Hudson Soft",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu Interactive",
+        "46" => "Angel",
+        "47" => "Bullet-Proof Software",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim Entertainment",
+        "52" => "Activision",
+        "53" => "Sammy USA Corporation",
+        "54" => "Konami",
+        "55" => "Hi Tech Expressions",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley Company",
+        "60" => "Titus Interactive",
+        "61" => "Virgin Games Ltd.",
+        "64" => "Lucasfilm Games",
+        "67" => "Ocean Software",
+        "69" => "EA (Electronic Arts)",
+        "70" => "Infogrames",
+        "71" => "Interplay Entertainment",
+        "72" => "Broderbund",
+        "73" => "Sculptured Software",
+        "75" => "The Sales Curve Limited",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa Entertainment",
+        "83" => "lozc",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft Co.",
+        "92" => "Video System",
+        "93" => "Ocean Software/Acclaim Entertainment",
+        "95" => "Varie",
+        "96" => "Yonezawa/s'pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        "9H" => "Bottom Up",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        "BL" => "MTO",
+        "DK" => "Kodansha",
+
+        _ => return None,
+    })
+}
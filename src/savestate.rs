@@ -0,0 +1,315 @@
+use std::{fmt, fs, io, path::PathBuf};
+
+use crate::{cpu::CPU, gpu::VRAM_SIZE, memory::{BOOT_ROM_SIZE, IE}, registers::{FlagsRegister, LCDControl}, rom::ROM};
+
+/*
+    Save states: a snapshot of the whole machine in a small versioned binary format.
+
+    Layout (little-endian):
+        magic "CBSS", version (u16)
+        ROM CRC32 (u32), ROM name length (u8) + name
+        CPU: PC, SP (u16), A F B C D E H L, LCDC (u8), halted (u8), IME (u8), IE (u8), cycles (u64)
+        memory up to IE (0xFFFF bytes), VRAM (0x1800 bytes)
+        boot ROM mapped (u8) [+ 0x100 bytes]
+
+    The timer, joypad and audio registers live in `memory`, so they come along with it.
+    Not emulated yet, so not saved beyond that:
+        - interrupts: IME has its byte, always 0 for now (IE is saved, nothing acts on it)
+        - the timers' internal counters (DIV/TIMA don't tick)
+        - the APU's channel state (it plays on from wherever it was)
+        - the memory bank controller's registers (there's no MBC)
+    Adding any of them means a new version. Older states still load: what they
+    don't have gets its power-on value (version 1 had no cycle count, 2 no IME/IE).
+    A change that can't be filled in that way raises OLDEST_SAVE_STATE_VERSION.
+*/
+
+const MAGIC: &[u8; 4] = b"CBSS";
+pub const SAVE_STATE_VERSION: u16 = 3;
+const OLDEST_SAVE_STATE_VERSION: u16 = 1;
+
+pub const SLOT_COUNT: u8 = 10;
+const STATES_DIR: &str = "data/states";
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    NotASaveState,
+    UnsupportedVersion(u16),
+    WrongRom { state_rom: String, loaded_rom: String },
+    Truncated,
+}
+
+
+pub fn save_state(cpu: &CPU, rom: &ROM) -> Vec<u8> {
+    let mut state: Vec<u8> = Vec::with_capacity(0x10000 + VRAM_SIZE + 0x200);
+
+    state.extend_from_slice(MAGIC);
+    state.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+
+    state.extend_from_slice(&rom.crc32.to_le_bytes());
+    let rom_name = &rom.name.as_bytes()[..rom.name.len().min(u8::MAX as usize)];
+    state.push(rom_name.len() as u8);
+    state.extend_from_slice(rom_name);
+
+    state.extend_from_slice(&cpu.pc.to_le_bytes());
+    state.extend_from_slice(&cpu.regs.sp.to_le_bytes());
+    state.extend_from_slice(&[
+        cpu.regs.a, cpu.regs.flags.into_u8(), cpu.regs.b, cpu.regs.c,
+        cpu.regs.d, cpu.regs.e, cpu.regs.h, cpu.regs.l,
+        cpu.regs.lcdc.into_u8(), cpu.is_halted as u8,
+        // IME, no interrupts yet
+        0, cpu.mem_bus.memory[IE as usize],
+    ]);
+    // keeps the position within the frame, movies depend on it
+    state.extend_from_slice(&cpu.cycles.to_le_bytes());
+
+    state.extend_from_slice(&cpu.mem_bus.memory[..IE as usize]);
+    state.extend_from_slice(&cpu.mem_bus.gpu.vram);
+
+    match &cpu.mem_bus.boot_rom {
+        Some(boot_rom) => {
+            state.push(1);
+            state.extend_from_slice(boot_rom);
+        },
+        None => state.push(0),
+    }
+
+    state
+}
+
+/**
+    Restores a state made by `save_state`. Nothing is touched if the state is invalid
+    or was made with another ROM.
+ */
+pub fn load_state(cpu: &mut CPU, rom: &ROM, state: &[u8]) -> Result<(), SaveStateError> {
+    let mut reader = StateReader { state, offset: 0 };
+
+    if reader.read_bytes(MAGIC.len())? != MAGIC {
+        return Err(SaveStateError::NotASaveState);
+    }
+
+    let version = reader.read_u16()?;
+    if !(OLDEST_SAVE_STATE_VERSION..=SAVE_STATE_VERSION).contains(&version) {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+
+    let rom_crc = reader.read_u32()?;
+    let name_length = reader.read_u8()? as usize;
+    let state_rom_name = String::from_utf8_lossy(reader.read_bytes(name_length)?).into_owned();
+
+    if rom_crc != rom.crc32 {
+        return Err(SaveStateError::WrongRom { state_rom: state_rom_name, loaded_rom: rom.name.clone() });
+    }
+
+    let pc = reader.read_u16()?;
+    let sp = reader.read_u16()?;
+    // version 3 added IME, ignored until there are interrupts, and IE
+    let regs = reader.read_bytes(if version >= 3 { 12 } else { 10 })?;
+    // version 2 added the cycle count
+    let cycles = if version >= 2 { reader.read_u64()? } else { 0 };
+    let memory = reader.read_bytes(IE as usize)?;
+    let vram = reader.read_bytes(VRAM_SIZE)?;
+    let boot_rom = match reader.read_u8()? {
+        0 => None,
+        _ => Some(reader.read_bytes(BOOT_ROM_SIZE)?.to_vec()),
+    };
+
+    // everything's been read, it's safe to overwrite the machine now
+    cpu.pc = pc;
+    cpu.regs.sp = sp;
+    cpu.regs.a = regs[0];
+    cpu.regs.flags = FlagsRegister::from_u8(regs[1]);
+    cpu.regs.b = regs[2];
+    cpu.regs.c = regs[3];
+    cpu.regs.d = regs[4];
+    cpu.regs.e = regs[5];
+    cpu.regs.h = regs[6];
+    cpu.regs.l = regs[7];
+    cpu.regs.lcdc = LCDControl::from_u8(regs[8]);
+    cpu.is_halted = regs[9] != 0;
+    // not saved, running into the same error again locks it again
+    cpu.is_locked = false;
+    cpu.cycles = cycles;
+
+    cpu.mem_bus.memory[..IE as usize].copy_from_slice(memory);
+    cpu.mem_bus.memory[IE as usize] = regs.get(11).copied().unwrap_or(0);
+    cpu.mem_bus.boot_rom = boot_rom;
+
+    // goes through the GPU so the tileset gets rebuilt too
+    for (index, byte) in vram.iter().enumerate() {
+        cpu.mem_bus.gpu.vram_write_byte(index, *byte);
+    }
+
+    Ok(())
+}
+
+// data/states/TETRIS_1A2B3C4D.ss1
+pub fn slot_path(rom: &ROM, slot: u8) -> PathBuf {
+    let rom_name: String = rom.name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    PathBuf::from(STATES_DIR).join(format!("{rom_name}_{:08X}.ss{slot}", rom.crc32))
+}
+
+pub fn save_to_slot(cpu: &CPU, rom: &ROM, slot: u8) -> Result<PathBuf, SaveStateError> {
+    let path = slot_path(rom, slot);
+
+    fs::create_dir_all(STATES_DIR)?;
+    fs::write(&path, save_state(cpu, rom))?;
+
+    Ok(path)
+}
+
+pub fn load_from_slot(cpu: &mut CPU, rom: &ROM, slot: u8) -> Result<PathBuf, SaveStateError> {
+    let path = slot_path(rom, slot);
+    let state = fs::read(&path)?;
+
+    load_state(cpu, rom, &state)?;
+
+    Ok(path)
+}
+
+
+struct StateReader<'a> {
+    state: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], SaveStateError> {
+        let bytes = self.state.get(self.offset..self.offset + count).ok_or(SaveStateError::Truncated)?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+}
+
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::Io(error) => write!(f, "{error}"),
+            SaveStateError::NotASaveState => write!(f, "not a Crusty-Boy save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f, "save state version {version} isn't supported (expected {OLDEST_SAVE_STATE_VERSION} to {SAVE_STATE_VERSION})"
+            ),
+            SaveStateError::WrongRom { state_rom, loaded_rom } => write!(
+                f, "save state was made with \"{state_rom}\", not the loaded ROM (\"{loaded_rom}\")"
+            ),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(error: io::Error) -> Self {
+        SaveStateError::Io(error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with(byte: u8) -> ROM {
+        let mut data = vec![0; 0x8000];
+        data[0x150] = byte;
+        ROM::from_bytes(data)
+    }
+
+    // a machine that's been somewhere, with something in every part a state holds
+    fn busy_cpu(rom: &ROM) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.mem_bus.load_rom(&rom.data);
+        cpu.pc = 0x0150;
+        cpu.regs.sp = 0xDFF0;
+        cpu.regs.a = 0x12;
+        cpu.regs.h = 0xC0;
+        cpu.is_halted = true;
+        cpu.cycles = 123_456;
+        cpu.mem_bus.write_byte(0xC000, 0x34);
+        cpu.mem_bus.write_byte(0x8010, 0x56);
+        cpu.mem_bus.write_byte(IE, 0x1F);
+        cpu.mem_bus.load_boot_rom(vec![0x31; 0x100]);
+        cpu
+    }
+
+    #[test]
+    fn round_trip_gives_the_same_state() {
+        let rom = rom_with(0x01);
+        let state = save_state(&busy_cpu(&rom), &rom);
+
+        let mut cpu = CPU::new();
+        load_state(&mut cpu, &rom, &state).unwrap();
+
+        assert_eq!(save_state(&cpu, &rom), state);
+        assert_eq!(cpu.mem_bus.read_byte(IE), 0x1F);
+        assert_eq!(cpu.mem_bus.gpu.vram_read_byte(0x10), 0x56);
+    }
+
+    #[test]
+    fn refuses_a_state_from_another_rom() {
+        let rom = rom_with(0x01);
+        let state = save_state(&busy_cpu(&rom), &rom);
+
+        let other_rom = rom_with(0x02);
+        let mut cpu = CPU::new();
+        let result = load_state(&mut cpu, &other_rom, &state);
+
+        assert!(matches!(result, Err(SaveStateError::WrongRom { .. })));
+        // untouched
+        assert_eq!(cpu.pc, 0);
+        assert_eq!(cpu.mem_bus.read_byte(0xC000), 0);
+    }
+
+    #[test]
+    fn loads_older_versions() {
+        let rom = rom_with(0x01);
+        let state = save_state(&busy_cpu(&rom), &rom);
+        // what version 3 added, IME and IE, comes after the header and 14 bytes of registers
+        let registers_end = MAGIC.len() + 2 + 4 + 1 + rom.name.len() + 14;
+
+        let mut version_2 = state.clone();
+        version_2[4..6].copy_from_slice(&2u16.to_le_bytes());
+        version_2.drain(registers_end..registers_end + 2);
+
+        let mut cpu = CPU::new();
+        load_state(&mut cpu, &rom, &version_2).unwrap();
+        assert_eq!((cpu.pc, cpu.regs.a, cpu.cycles), (0x0150, 0x12, 123_456));
+        assert_eq!(cpu.mem_bus.read_byte(0xC000), 0x34);
+        assert_eq!(cpu.mem_bus.read_byte(IE), 0);
+    }
+
+    #[test]
+    fn rejects_bad_states() {
+        let rom = rom_with(0x01);
+        let state = save_state(&busy_cpu(&rom), &rom);
+        let mut cpu = CPU::new();
+
+        let mut future = state.clone();
+        future[4..6].copy_from_slice(&(SAVE_STATE_VERSION + 1).to_le_bytes());
+        assert!(matches!(load_state(&mut cpu, &rom, &future), Err(SaveStateError::UnsupportedVersion(_))));
+
+        assert!(matches!(load_state(&mut cpu, &rom, &state[..state.len() - 1]), Err(SaveStateError::Truncated)));
+        assert!(matches!(load_state(&mut cpu, &rom, b"nope"), Err(SaveStateError::NotASaveState)));
+    }
+}
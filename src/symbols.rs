@@ -0,0 +1,148 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::{disassembler::{DisassembledInstruction, Operand}, utils::log};
+
+/*
+    RGBDS symbol files (`rgblink -n game.sym`), loaded when one sits next to the ROM:
+
+        ; File generated by rgblink
+        00:0150 Main
+        00:0158 Main.loop
+        01:4000 LoadGraphics
+        00:c000 wPlayerX
+
+    Addresses in $4000-$7FFF depend on the bank, everywhere else the bank is ignored.
+    Without an MBC, what's mapped there is always bank 1.
+*/
+
+// what's at $4000-$7FFF while running, there's no MBC to switch banks yet
+pub const MAPPED_ROM_BANK: u16 = 1;
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub bank: u16,
+    pub address: u16,
+    pub name: String,
+}
+
+#[derive(Default)]
+pub struct Symbols {
+    // sorted by address, then bank
+    list: Vec<Symbol>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    // malformed lines are skipped, a hand-edited file shouldn't keep the rest from loading
+    pub fn parse(text: &str) -> Self {
+        let mut list: Vec<Symbol> = text.lines()
+            .filter_map(|line| {
+                let line = line.split(';').next()?.trim();
+                let (location, name) = line.split_once(char::is_whitespace)?;
+                let (bank, address) = location.split_once(':')?;
+
+                Some(Symbol {
+                    bank: u16::from_str_radix(bank, 16).ok()?,
+                    address: u16::from_str_radix(address, 16).ok()?,
+                    name: name.trim().to_string(),
+                })
+            })
+            .collect();
+
+        list.sort_by_key(|symbol| (symbol.address, symbol.bank));
+
+        let by_name = list.iter().enumerate().map(|(index, symbol)| (symbol.name.clone(), index)).collect();
+        Symbols { list, by_name }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Symbols::parse(&fs::read_to_string(path)?))
+    }
+
+    // the .sym next to the ROM (same name), or no symbols
+    pub fn for_rom(rom_path: &str) -> Self {
+        let path = Path::new(rom_path).with_extension("sym");
+        if !path.exists() {
+            return Symbols::new();
+        }
+
+        match Symbols::load(&path) {
+            Ok(symbols) => {
+                log(&format!("[SYMBOLS] Loaded {} symbols from {}", symbols.list.len(), path.display()));
+                symbols
+            },
+            Err(error) => {
+                log(&format!("[SYMBOLS] Couldn't read {}: {error}", path.display()));
+                Symbols::new()
+            },
+        }
+    }
+
+    // the symbol right at `address`, `bank` being what's mapped at $4000-$7FFF
+    pub fn name_at(&self, address: u16, bank: u16) -> Option<&str> {
+        let start = self.list.partition_point(|symbol| symbol.address < address);
+
+        self.list[start..].iter()
+            .take_while(|symbol| symbol.address == address)
+            .find(|symbol| matches_bank(symbol, bank))
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    /**
+        "Main" at a symbol, "Main+$3" past one. Only looks back as far as the
+        start of the memory area, the symbol before a ROM bank isn't of any help.
+     */
+    pub fn describe(&self, address: u16, bank: u16) -> Option<String> {
+        let end = self.list.partition_point(|symbol| symbol.address <= address);
+
+        let symbol = self.list[..end].iter().rev()
+            .take_while(|symbol| area_start(symbol.address) == area_start(address))
+            .find(|symbol| matches_bank(symbol, bank))?;
+
+        Some(match address - symbol.address {
+            0 => symbol.name.clone(),
+            offset => format!("{}+${offset:X}", symbol.name),
+        })
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).map(|index| self.list[*index].address)
+    }
+
+    /**
+        The symbol an instruction's operand points to, to show instead of the
+        number: jump targets and memory addresses (not plain values).
+     */
+    pub fn operand_name(&self, instruction: &DisassembledInstruction, bank: u16) -> Option<&str> {
+        match instruction.operand? {
+            Operand::Target(address) | Operand::Address(address) => self.name_at(address, bank),
+            Operand::HighAddress(low) => self.name_at(0xFF00 | low as u16, bank),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.list.iter()
+    }
+}
+
+fn matches_bank(symbol: &Symbol, bank: u16) -> bool {
+    !(0x4000..0x8000).contains(&symbol.address) || symbol.bank == bank
+}
+
+// the start of the memory area `address` is in: ROM0, ROMX, VRAM, SRAM, WRAM, OAM/IO, HRAM
+fn area_start(address: u16) -> u16 {
+    match address {
+        0x0000..=0x3FFF => 0x0000,
+        0x4000..=0x7FFF => 0x4000,
+        0x8000..=0x9FFF => 0x8000,
+        0xA000..=0xBFFF => 0xA000,
+        0xC000..=0xFDFF => 0xC000,
+        0xFE00..=0xFF7F => 0xFE00,
+        _ => 0xFF80,
+    }
+}
@@ -0,0 +1,243 @@
+use std::{io::{self, BufWriter, Stdout, Write}, time::Duration};
+
+use crossterm::{
+    cursor, event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
+    queue, style::{self, Color}, terminal,
+};
+
+use crate::{boot, config::Config, cpu::CPU, gpu::{LCD_HEIGHT, LCD_WIDTH}, joypad, logger, pacing::FramePacer, rom::ROM, utils::log};
+
+/*
+    Terminal frontend, no display server needed (works over SSH):
+
+        crusty-boy terminal <rom> [--greyscale]
+
+    Every character cell shows two pixels stacked, with '▀': the top one is the
+    foreground color and the bottom one the background, so the frame takes 160x72 cells.
+
+    Most terminals only report key presses, not releases, so a pressed button is held
+    for KEY_HOLD_FRAMES frames (key repeat keeps it held). Terminals that do report
+    releases (kitty's keyboard protocol) get the exact timing.
+*/
+
+const HALF_BLOCK: char = '▀';
+const KEY_HOLD_FRAMES: u8 = 8;
+
+pub struct TerminalOptions {
+    pub rom_path: String,
+    // 24 shades of grey from the 256-color palette, for terminals without truecolor
+    pub greyscale: bool,
+}
+
+impl TerminalOptions {
+    pub fn from_args(args: &[String]) -> Self {
+        let mut options = TerminalOptions {
+            rom_path: String::new(),
+            // terminals with truecolor usually say so
+            greyscale: !std::env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit"),
+        };
+
+        for arg in args {
+            match arg.as_str() {
+                "--greyscale" => options.greyscale = true,
+                "--truecolor" => options.greyscale = false,
+                _ => options.rom_path = arg.clone(),
+            }
+        }
+
+        options
+    }
+}
+
+// puts the terminal back the way it was, even on a panic, and logging to it
+struct TerminalGuard {
+    keyboard_enhanced: bool,
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+
+        if self.keyboard_enhanced {
+            let _ = queue!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(stdout, style::ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = stdout.flush();
+        let _ = terminal::disable_raw_mode();
+        logger::set_console(true);
+    }
+}
+
+
+pub fn main(args: &[String]) -> i32 {
+    let options = TerminalOptions::from_args(args);
+
+    let rom = match ROM::read_rom(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            log(&format!("[TERMINAL] Couldn't load \"{}\": {error}", options.rom_path));
+            return 1;
+        },
+    };
+
+    match run(boot::power_on(&rom), &options, &Config::load()) {
+        Ok(()) => 0,
+        Err(error) => {
+            log(&format!("[TERMINAL] {error}"));
+            1
+        },
+    }
+}
+
+pub fn run(mut cpu: CPU, options: &TerminalOptions, config: &Config) -> io::Result<()> {
+    let (columns, rows) = terminal::size()?;
+    if (columns as usize) < LCD_WIDTH || (rows as usize) < LCD_HEIGHT / 2 {
+        log(&format!(
+            "[TERMINAL] The terminal is {columns}x{rows}, the screen needs {LCD_WIDTH}x{} to fit",
+            LCD_HEIGHT / 2
+        ));
+    }
+
+    terminal::enable_raw_mode()?;
+    let keyboard_enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+    let guard = TerminalGuard { keyboard_enhanced };
+    // a log line in the middle of a frame would garble it, they only go to the file until the guard drops
+    logger::set_console(false);
+
+    let mut stdout = BufWriter::with_capacity(1 << 16, io::stdout());
+    queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+    if keyboard_enhanced {
+        queue!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+    }
+
+    let mut pacer = FramePacer::new(config.turbo_speed, config.slow_motion_factor);
+    // frames left for each joypad button (same bits as joypad.rs)
+    let mut held_frames = [0u8; 8];
+    // what's on the terminal right now, to only redraw the rows that changed
+    let mut drawn_rows: Vec<Option<Vec<(u32, u32)>>> = vec![None; LCD_HEIGHT / 2];
+
+    'running: loop {
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) => {
+                    if is_quit_key(&key) {
+                        break 'running;
+                    }
+
+                    match key.code {
+                        KeyCode::Char('p') if key.kind == KeyEventKind::Press => pacer.toggle_pause(),
+                        KeyCode::Char('n') if key.kind != KeyEventKind::Release => pacer.advance_frame(),
+                        _ => {},
+                    }
+
+                    if let Some(button) = joypad_button(key.code) {
+                        let index = button.trailing_zeros() as usize;
+                        held_frames[index] = match key.kind {
+                            KeyEventKind::Release => 0,
+                            // with releases reported, it's held until then
+                            _ if keyboard_enhanced => u8::MAX,
+                            _ => KEY_HOLD_FRAMES,
+                        };
+                    }
+                },
+                // everything has moved, draw it all again
+                Event::Resize(_, _) => {
+                    queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
+                    drawn_rows.fill(None);
+                },
+                _ => {},
+            }
+        }
+
+        for _ in 0..pacer.frames_to_run(false) {
+            cpu.mem_bus.joypad.pressed = held_frames.iter().enumerate()
+                .filter(|(_, frames)| **frames > 0)
+                .fold(0, |pressed, (index, _)| pressed | (1 << index));
+
+            for frames in held_frames.iter_mut().filter(|frames| **frames != u8::MAX) {
+                *frames = frames.saturating_sub(1);
+            }
+
+            if let Err(error) = cpu.run_frame() {
+                // back from the alternate screen first, for what's logged to stay on the terminal
+                drop(guard);
+                cpu.log_history();
+                return Err(io::Error::other(error));
+            }
+        }
+
+        cpu.mem_bus.render_frame();
+        draw_frame(&mut stdout, &cpu, options.greyscale, &mut drawn_rows)?;
+        stdout.flush()?;
+
+        pacer.wait(false);
+    }
+
+    Ok(())
+}
+
+fn draw_frame(stdout: &mut BufWriter<Stdout>, cpu: &CPU, greyscale: bool, drawn_rows: &mut [Option<Vec<(u32, u32)>>]) -> io::Result<()> {
+    let screen = &cpu.mem_bus.gpu.screen;
+
+    for (row, drawn_row) in drawn_rows.iter_mut().enumerate() {
+        let cells: Vec<(u32, u32)> = (0..LCD_WIDTH)
+            .map(|x| (screen[row * 2 * LCD_WIDTH + x].to_rgb(), screen[(row * 2 + 1) * LCD_WIDTH + x].to_rgb()))
+            .collect();
+
+        if drawn_row.as_ref() == Some(&cells) {
+            continue;
+        }
+
+        queue!(stdout, cursor::MoveTo(0, row as u16))?;
+
+        // colors are only sent when they change, it's a lot less to send over SSH
+        let mut current: Option<(u32, u32)> = None;
+        for &(top, bottom) in &cells {
+            if current.map(|(current_top, _)| current_top) != Some(top) {
+                queue!(stdout, style::SetForegroundColor(terminal_color(top, greyscale)))?;
+            }
+            if current.map(|(_, current_bottom)| current_bottom) != Some(bottom) {
+                queue!(stdout, style::SetBackgroundColor(terminal_color(bottom, greyscale)))?;
+            }
+            current = Some((top, bottom));
+
+            queue!(stdout, style::Print(HALF_BLOCK))?;
+        }
+
+        queue!(stdout, style::ResetColor)?;
+        *drawn_row = Some(cells);
+    }
+
+    Ok(())
+}
+
+fn terminal_color(rgb: u32, greyscale: bool) -> Color {
+    let (r, g, b) = ((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8);
+
+    if greyscale {
+        // 232 is the darkest grey, 255 the lightest
+        let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+        Color::AnsiValue(232 + (luma * 23 / 255) as u8)
+    } else {
+        Color::Rgb { r, g, b }
+    }
+}
+
+fn is_quit_key(key: &KeyEvent) -> bool {
+    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(event::KeyModifiers::CONTROL);
+    key.kind == KeyEventKind::Press && (key.code == KeyCode::Esc || key.code == KeyCode::Char('q') || ctrl_c)
+}
+
+fn joypad_button(code: KeyCode) -> Option<u8> {
+    match code {
+        KeyCode::Right => Some(joypad::RIGHT),
+        KeyCode::Left => Some(joypad::LEFT),
+        KeyCode::Up => Some(joypad::UP),
+        KeyCode::Down => Some(joypad::DOWN),
+        KeyCode::Char('x') => Some(joypad::A),
+        KeyCode::Char('z') => Some(joypad::B),
+        KeyCode::Backspace => Some(joypad::SELECT),
+        KeyCode::Enter => Some(joypad::START),
+        _ => None,
+    }
+}
@@ -0,0 +1,267 @@
+use std::{collections::VecDeque, fs::File, io::{BufRead, BufReader}};
+
+use crate::{boot, cli::CliError, cpu::CPU, debugger, doctor, error::EmuError, rom::ROM, symbols::Symbols, utils::{self, log}};
+
+/*
+    Runs a ROM against a reference trace from an emulator that gets it right, and
+    stops at the first instruction where they disagree:
+
+        crusty-boy trace-diff <rom> <reference.log> [--context N]
+
+    One line per instruction, with the state before it runs. Lines are read as
+    NAME:VALUE fields, so Gameboy Doctor logs work, and so do BGB/SameBoy-style ones:
+
+        A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+        A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE PC:0100 (cy: 0)
+
+    Only the fields the reference has are compared, lines without a PC are skipped.
+    Like Gameboy Doctor expects, it starts from the post-boot state with LY stubbed.
+*/
+
+const DEFAULT_CONTEXT: usize = 10;
+const REGISTERS: [&str; 10] = ["A", "F", "B", "C", "D", "E", "H", "L", "SP", "PC"];
+// the register pairs some emulators log instead, and where their halves go in REGISTERS
+const REGISTER_PAIRS: [(&str, usize, usize); 4] = [("AF", 0, 1), ("BC", 2, 3), ("DE", 4, 5), ("HL", 6, 7)];
+const PC_INDEX: usize = 9;
+// BGB's F:ZNHC, a letter for each flag that's set and '-' otherwise
+const FLAG_LETTERS: [(char, u8); 4] = [('Z', 0x80), ('N', 0x40), ('H', 0x20), ('C', 0x10)];
+
+pub struct TraceDiffOptions {
+    pub rom_path: String,
+    pub reference_path: String,
+    // instructions shown before the difference
+    pub context: usize,
+}
+
+// the CPU's state before an instruction, as much of it as a trace line has
+#[derive(Default)]
+struct TraceState {
+    // in the order of REGISTERS
+    registers: [Option<u16>; 10],
+    pcmem: Option<Vec<u8>>,
+}
+
+// an instruction that matched, kept to show what led to the difference
+struct PastInstruction {
+    line: usize,
+    state: String,
+    disassembly: String,
+}
+
+pub enum DiffResult {
+    Matched { lines: usize },
+    Diverged { line: usize },
+    // the CPU stopped before the reference did
+    Halted { line: usize },
+    // on the instruction of that line
+    Locked { line: usize, error: EmuError },
+}
+
+impl TraceDiffOptions {
+    pub fn from_args(args: &[String]) -> Result<Self, CliError> {
+        let mut context = DEFAULT_CONTEXT;
+        let mut paths = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--context" => context = args.next()
+                    .and_then(|value| utils::parse_number(value))
+                    .ok_or(CliError::InvalidArgument(format!("{arg} needs a number")))? as usize,
+                _ if arg.starts_with("--") => return Err(CliError::InvalidArgument(format!("unknown option {arg}"))),
+                _ => paths.push(arg.clone()),
+            }
+        }
+
+        let [rom_path, reference_path] = <[String; 2]>::try_from(paths)
+            .map_err(|_| CliError::InvalidArgument(String::from("expected a ROM and a reference trace")))?;
+
+        Ok(TraceDiffOptions { rom_path, reference_path, context })
+    }
+}
+
+impl TraceState {
+    // None for lines that aren't instructions (no PC)
+    fn parse(line: &str) -> Option<Self> {
+        let mut state = TraceState::default();
+
+        for field in line.split_whitespace() {
+            let Some((name, value)) = field.split_once(':') else {
+                continue;
+            };
+            let name = name.to_ascii_uppercase();
+            let number = u16::from_str_radix(value.trim_end_matches(','), 16).ok();
+
+            if name == "PCMEM" {
+                state.pcmem = value.split(',').map(|byte| u8::from_str_radix(byte, 16).ok()).collect();
+            } else if name == "F" && value.len() == 4 && value.chars().all(|flag| "ZNHC-".contains(flag.to_ascii_uppercase())) {
+                state.registers[1] = Some(parse_flag_letters(value) as u16);
+            } else if let Some(index) = REGISTERS.iter().position(|register| *register == name) {
+                state.registers[index] = number;
+            } else if let Some((_, high, low)) = REGISTER_PAIRS.iter().find(|(pair, _, _)| *pair == name) {
+                state.registers[*high] = number.map(|value| value >> 8);
+                state.registers[*low] = number.map(|value| value & 0xFF);
+            }
+        }
+
+        state.registers[PC_INDEX].is_some().then_some(state)
+    }
+
+    fn of(cpu: &CPU) -> Self {
+        let regs = &cpu.regs;
+        let values = [regs.a, regs.flags.into_u8(), regs.b, regs.c, regs.d, regs.e, regs.h, regs.l].map(|value| value as u16);
+
+        let mut registers = [None; 10];
+        for (register, value) in registers.iter_mut().zip(values.into_iter().chain([regs.sp, cpu.pc])) {
+            *register = Some(value);
+        }
+
+        let pcmem = (0..4).map(|offset| cpu.mem_bus.peek_byte(cpu.pc.wrapping_add(offset))).collect();
+        TraceState { registers, pcmem: Some(pcmem) }
+    }
+
+    // "A: expected $01, got $02" for each field of `self` (the reference) that `ours` doesn't match
+    fn differences(&self, ours: &TraceState) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        for (index, name) in REGISTERS.iter().enumerate() {
+            if let (Some(expected), Some(got)) = (self.registers[index], ours.registers[index]) {
+                if expected != got {
+                    let width = if index >= 8 { 4 } else { 2 };
+                    differences.push(format!("{name}: expected ${expected:0width$X}, got ${got:0width$X}"));
+                }
+            }
+        }
+
+        if let (Some(expected), Some(got)) = (&self.pcmem, &ours.pcmem) {
+            let length = expected.len().min(got.len());
+            if expected[..length] != got[..length] {
+                differences.push(format!("PCMEM: expected {}, got {}", hex_bytes(&expected[..length]), hex_bytes(&got[..length])));
+            }
+        }
+
+        differences
+    }
+}
+
+fn parse_flag_letters(letters: &str) -> u8 {
+    FLAG_LETTERS.iter()
+        .filter(|(letter, _)| letters.to_ascii_uppercase().contains(*letter))
+        .map(|(_, mask)| mask)
+        .sum()
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect::<Vec<_>>().join(",")
+}
+
+
+pub fn main(args: &[String]) -> i32 {
+    match TraceDiffOptions::from_args(args).and_then(|options| run(&options)) {
+        Ok(DiffResult::Matched { lines }) => {
+            log(&format!("[TRACE-DIFF] All {lines} instructions of the reference matched"));
+            0
+        },
+        Ok(DiffResult::Diverged { line }) => {
+            log(&format!("[TRACE-DIFF] Diverged at line {line} of the reference"));
+            1
+        },
+        Ok(DiffResult::Halted { line }) => {
+            log(&format!("[TRACE-DIFF] The CPU stopped at line {line} of the reference, it goes on"));
+            1
+        },
+        Ok(DiffResult::Locked { line, error }) => {
+            log(&format!("[TRACE-DIFF] The CPU locked up at line {line} of the reference: {error}"));
+            1
+        },
+        Err(error) => {
+            log(&format!("[TRACE-DIFF] {error}"));
+            1
+        },
+    }
+}
+
+/**
+    Steps the CPU along the reference, one instruction per line, and prints
+    what led to the first difference.
+ */
+pub fn run(options: &TraceDiffOptions) -> Result<DiffResult, CliError> {
+    let rom = ROM::read_rom(&options.rom_path)?;
+    let mut cpu = boot::power_on(&rom);
+    cpu.symbols = Symbols::for_rom(&options.rom_path);
+    cpu.mem_bus.stubbed_ly = Some(doctor::STUBBED_LY);
+
+    let reference = BufReader::new(File::open(&options.reference_path)?);
+    let mut history: VecDeque<PastInstruction> = VecDeque::with_capacity(options.context + 1);
+    let mut compared = 0;
+
+    for (index, line) in reference.lines().enumerate() {
+        let line = line?;
+        let Some(expected) = TraceState::parse(&line) else {
+            continue;
+        };
+
+        if cpu.is_halted {
+            print_history(&history);
+            return Ok(DiffResult::Halted { line: index + 1 });
+        }
+
+        let ours = TraceState::of(&cpu);
+        let differences = expected.differences(&ours);
+
+        if !differences.is_empty() {
+            print_divergence(&cpu, &history, index + 1, &line, &differences);
+            return Ok(DiffResult::Diverged { line: index + 1 });
+        }
+
+        if options.context > 0 {
+            if history.len() == options.context {
+                history.pop_front();
+            }
+            history.push_back(PastInstruction {
+                line: index + 1,
+                state: doctor::state_line(&cpu),
+                disassembly: debugger::describe_instruction(&cpu, cpu.pc).0,
+            });
+        }
+
+        if let Err(error) = cpu.step() {
+            print_history(&history);
+            return Ok(DiffResult::Locked { line: index + 1, error });
+        }
+        compared += 1;
+    }
+
+    Ok(DiffResult::Matched { lines: compared })
+}
+
+fn print_history(history: &VecDeque<PastInstruction>) {
+    if history.is_empty() {
+        return;
+    }
+
+    println!("Last {} instructions:", history.len());
+    for instruction in history {
+        println!("  {:>8}  {}  |  {}", instruction.line, instruction.state, instruction.disassembly);
+    }
+}
+
+fn print_divergence(cpu: &CPU, history: &VecDeque<PastInstruction>, line: usize, reference: &str, differences: &[String]) {
+    print_history(history);
+
+    // the state is before the instruction, so it's the previous one that went wrong
+    if let Some(previous) = history.back() {
+        println!("\nLikely culprit, the instruction before: {}", previous.disassembly);
+    }
+
+    println!("\nLine {line}:");
+    println!("  expected  {}", reference.trim());
+    println!("  got       {}", doctor::state_line(cpu));
+    for difference in differences {
+        println!("  {difference}");
+    }
+
+    println!("\nAt PC: {}", debugger::describe_instruction(cpu, cpu.pc).0);
+}
+
+
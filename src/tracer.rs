@@ -0,0 +1,322 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use crate::{disassembler::{DisassembledInstruction, Operand}, symbols::Symbols};
+
+/*
+    Recursive-descent disassembly of a whole ROM: starts from the entry point and the
+    RST/interrupt vectors, and follows every JP, JR, CALL and RST from there. Whatever
+    is never reached is data. The result is written as an .asm file RGBDS assembles
+    back into the same ROM:
+
+        crusty-boy disasm <rom> --asm <out.asm>
+
+    Which bank $4000-$7FFF maps to can't be known without running the code, so
+    jumps there from bank 0 follow the last `LD A,n8` + `LD [$2000-$3FFF],A` seen on
+    the way (bank 1 when there wasn't any). Code in RAM isn't followed.
+
+    Names from the ROM's .sym file are used over the generated ones, and the RAM and
+    register ones are defined at the top (EQU) so memory accesses can use them too.
+*/
+
+const BANK_SIZE: usize = 0x4000;
+// the MBC's ROM bank register, written to switch banks
+const BANK_SELECT_START: u16 = 0x2000;
+const BANK_SELECT_END: u16 = 0x3FFF;
+const DATA_BYTES_PER_LINE: usize = 16;
+
+const ENTRY_POINTS: [(u16, &str); 14] = [
+    (0x0100, "Entry"),
+    (0x0000, "RST_00"), (0x0008, "RST_08"), (0x0010, "RST_10"), (0x0018, "RST_18"),
+    (0x0020, "RST_20"), (0x0028, "RST_28"), (0x0030, "RST_30"), (0x0038, "RST_38"),
+    (0x0040, "VBlankInterrupt"), (0x0048, "LCDInterrupt"), (0x0050, "TimerInterrupt"),
+    (0x0058, "SerialInterrupt"), (0x0060, "JoypadInterrupt"),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum ByteKind {
+    Data,
+    // the first byte of an instruction
+    Opcode,
+    Operand,
+}
+
+// the names callers give are kept over the ones jumps give
+#[derive(Clone, PartialEq, PartialOrd)]
+enum Label {
+    Named(String),
+    Call,
+    Jump,
+}
+
+// where to carry on tracing: a ROM offset, and which bank was last selected on the way there
+struct Branch {
+    offset: usize,
+    selected_bank: Option<usize>,
+}
+
+pub struct Trace<'a> {
+    data: &'a [u8],
+    symbols: &'a Symbols,
+    kinds: Vec<ByteKind>,
+    instructions: BTreeMap<usize, DisassembledInstruction>,
+    labels: BTreeMap<usize, Label>,
+    // where each jump/call/rst goes, for its label
+    targets: HashMap<usize, usize>,
+}
+
+impl<'a> Trace<'a> {
+    pub fn new(data: &'a [u8], symbols: &'a Symbols) -> Self {
+        let mut trace = Trace {
+            data,
+            symbols,
+            kinds: vec![ByteKind::Data; data.len()],
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            targets: HashMap::new(),
+        };
+
+        let mut pending: Vec<Branch> = Vec::new();
+        for (address, name) in ENTRY_POINTS {
+            if (address as usize) < data.len() {
+                trace.add_label(address as usize, Label::Named(String::from(name)));
+                pending.push(Branch { offset: address as usize, selected_bank: None });
+            }
+        }
+
+        let mut visited = HashSet::new();
+        while let Some(branch) = pending.pop() {
+            if visited.insert(branch.offset) {
+                trace.follow(branch, &mut pending);
+            }
+        }
+
+        trace
+    }
+
+    // disassembles straight ahead from `branch` until the code can't go on
+    fn follow(&mut self, branch: Branch, pending: &mut Vec<Branch>) {
+        let mut offset = branch.offset;
+        let mut selected_bank = branch.selected_bank;
+        // set by `LD A,n8`, for the bank switch that usually follows
+        let mut a_value: Option<u8> = None;
+
+        loop {
+            let bank = offset / BANK_SIZE;
+            let bank_end = ((bank + 1) * BANK_SIZE).min(self.data.len());
+            if offset >= bank_end {
+                return;
+            }
+
+            // already disassembled from another path
+            if self.kinds[offset] == ByteKind::Opcode {
+                return;
+            }
+
+            let instruction = DisassembledInstruction::decode(&self.data[offset..bank_end], cpu_address(offset));
+            let length = instruction.length() as usize;
+
+            // illegal, or overlapping instructions found from elsewhere
+            if instruction.is_data() || self.kinds[offset..offset + length].iter().any(|kind| *kind != ByteKind::Data) {
+                return;
+            }
+
+            self.kinds[offset] = ByteKind::Opcode;
+            self.kinds[offset + 1..offset + length].fill(ByteKind::Operand);
+
+            let template = instruction.template().to_string();
+            let mnemonic = template.split(' ').next().unwrap_or("");
+
+            match (instruction.operand, template.as_str()) {
+                (Some(Operand::Address(address)), "LD [a16],A") if (BANK_SELECT_START..=BANK_SELECT_END).contains(&address) => {
+                    // bank 0 can't be selected, the MBC gives bank 1 instead
+                    selected_bank = a_value.map(|bank| (bank as usize).max(1));
+                },
+                _ => {},
+            }
+            a_value = match (instruction.operand, template.as_str()) {
+                (Some(Operand::Immediate8(value)), "LD A,n8") => Some(value),
+                _ => None,
+            };
+
+            let target = match (instruction.operand, mnemonic) {
+                (Some(Operand::Target(address)), _) => Some(address),
+                (_, "RST") => u16::from_str_radix(template.trim_start_matches("RST $"), 16).ok(),
+                _ => None,
+            };
+
+            if let Some(target_offset) = target.and_then(|target| self.rom_offset(target, bank, selected_bank)) {
+                self.add_label(target_offset, if mnemonic == "JP" || mnemonic == "JR" { Label::Jump } else { Label::Call });
+                self.targets.insert(offset, target_offset);
+                pending.push(Branch { offset: target_offset, selected_bank });
+            }
+
+            self.instructions.insert(offset, instruction);
+
+            // nothing runs after these
+            let conditional = template.split([' ', ',']).nth(1).is_some_and(|word| ["NZ", "Z", "NC", "C"].contains(&word));
+            let ends_flow = match mnemonic {
+                "JP" | "JR" | "RET" => !conditional,
+                "RETI" => true,
+                _ => false,
+            };
+            if ends_flow {
+                return;
+            }
+
+            offset += length;
+        }
+    }
+
+    fn add_label(&mut self, offset: usize, label: Label) {
+        let current = self.labels.entry(offset).or_insert(label.clone());
+        if label < *current {
+            *current = label;
+        }
+    }
+
+    /**
+        Where a jump from `from_bank` to `address` lands in the ROM file, None
+        for RAM. See the top of the file for $4000-$7FFF from bank 0.
+     */
+    fn rom_offset(&self, address: u16, from_bank: usize, selected_bank: Option<usize>) -> Option<usize> {
+        let offset = match address {
+            0x0000..=0x3FFF => address as usize,
+            0x4000..=0x7FFF => {
+                let bank = if from_bank == 0 { selected_bank.unwrap_or(1) } else { from_bank };
+                bank * BANK_SIZE + (address as usize - BANK_SIZE)
+            },
+            _ => return None,
+        };
+
+        Some(offset).filter(|offset| *offset < self.data.len())
+    }
+
+    /**
+        Symbols can go anywhere but inside an instruction. Generated labels that
+        ended up inside another instruction or in data are dropped.
+     */
+    fn label_name(&self, offset: usize) -> Option<String> {
+        let (bank, address) = (offset / BANK_SIZE, cpu_address(offset));
+
+        if let Some(name) = self.symbols.name_at(address, bank as u16).filter(|_| self.kinds[offset] != ByteKind::Operand) {
+            return Some(name.to_string());
+        }
+
+        let label = self.labels.get(&offset).filter(|_| self.instructions.contains_key(&offset))?;
+
+        Some(match label {
+            Label::Named(name) => name.clone(),
+            Label::Call => format!("Call_{bank:03X}_{address:04X}"),
+            Label::Jump => format!("Jump_{bank:03X}_{address:04X}"),
+        })
+    }
+
+    pub fn code_bytes(&self) -> usize {
+        self.kinds.iter().filter(|kind| **kind != ByteKind::Data).count()
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels.keys().filter(|offset| self.instructions.contains_key(offset)).count()
+    }
+
+    /**
+        The whole ROM as RGBDS source, one SECTION per bank at its fixed address.
+        Labels only go on instructions, data is written as `db`s.
+     */
+    pub fn write_asm(&self, out: &mut impl Write, title: &str) -> io::Result<()> {
+        writeln!(out, "; {title}, disassembled by crusty-boy")?;
+        writeln!(out, "; {} bytes of code, {} of data", self.code_bytes(), self.data.len() - self.code_bytes())?;
+
+        let ram_symbols: Vec<_> = self.symbols.iter().filter(|symbol| is_ram_symbol(&symbol.name, symbol.address)).collect();
+        if !ram_symbols.is_empty() {
+            writeln!(out)?;
+        }
+        for symbol in ram_symbols {
+            writeln!(out, "DEF {} EQU ${:04X}", symbol.name, symbol.address)?;
+        }
+
+        for bank in 0..self.data.len().div_ceil(BANK_SIZE) {
+            writeln!(out)?;
+            if bank == 0 {
+                writeln!(out, "SECTION \"ROM Bank $000\", ROM0[$0000]")?;
+            } else {
+                writeln!(out, "SECTION \"ROM Bank ${bank:03X}\", ROMX[$4000], BANK[${bank:X}]")?;
+            }
+
+            let bank_end = ((bank + 1) * BANK_SIZE).min(self.data.len());
+            let mut offset = bank * BANK_SIZE;
+
+            while offset < bank_end {
+                if let Some(name) = self.label_name(offset) {
+                    writeln!(out, "\n{name}:")?;
+                }
+
+                if let Some(instruction) = self.instructions.get(&offset) {
+                    writeln!(out, "    {}", self.instruction_text(offset, instruction))?;
+                    offset += instruction.length() as usize;
+                    continue;
+                }
+
+                // data, up to the next instruction or symbol
+                let run_end = (offset + 1..bank_end)
+                    .find(|offset| self.kinds[*offset] != ByteKind::Data || self.label_name(*offset).is_some())
+                    .unwrap_or(bank_end)
+                    .min(offset + DATA_BYTES_PER_LINE);
+                let bytes: Vec<String> = self.data[offset..run_end].iter().map(|byte| format!("${byte:02X}")).collect();
+
+                writeln!(out, "    db {}", bytes.join(","))?;
+                offset = run_end;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn instruction_text(&self, offset: usize, instruction: &DisassembledInstruction) -> String {
+        // RGBDS always writes 0 after STOP, anything else has to stay as is
+        if instruction.template() == "STOP" && instruction.bytes[1] != 0x00 {
+            return format!("db $10,${:02X}", instruction.bytes[1]);
+        }
+
+        let label = self.targets.get(&offset).and_then(|target| self.label_name(*target));
+
+        let ram_address = match instruction.operand {
+            Some(Operand::Address(address)) => Some(address),
+            Some(Operand::HighAddress(low)) => Some(0xFF00 | low as u16),
+            _ => None,
+        };
+        let ram_symbol = ram_address
+            .and_then(|address| self.symbols.name_at(address, 0).filter(|name| is_ram_symbol(name, address)));
+
+        match (label, ram_symbol, instruction.operand) {
+            (Some(label), _, Some(Operand::Target(_))) => instruction.text_with_operand(&label),
+            (_, Some(name), _) => instruction.text_with_operand(name),
+            _ => instruction.to_string(),
+        }
+    }
+}
+
+// the symbols defined with EQU, local ones (Parent.child) can't be
+fn is_ram_symbol(name: &str, address: u16) -> bool {
+    address >= 0x8000 && !name.contains('.')
+}
+
+// where a ROM offset is mapped, bank 0 at $0000-$3FFF and the others at $4000-$7FFF
+fn cpu_address(offset: usize) -> u16 {
+    if offset < BANK_SIZE {
+        offset as u16
+    } else {
+        (BANK_SIZE + offset % BANK_SIZE) as u16
+    }
+}
+
+// traces `data` and writes the .asm to `path`
+pub fn write_asm_file<'a>(data: &'a [u8], symbols: &'a Symbols, path: &Path, title: &str) -> io::Result<Trace<'a>> {
+    let trace = Trace::new(data, symbols);
+
+    let mut out = BufWriter::new(File::create(path)?);
+    trace.write_asm(&mut out, title)?;
+    out.flush()?;
+
+    Ok(trace)
+}
@@ -1,50 +1,30 @@
-use std::{fs::{self, OpenOptions}, io::Write};
-
 use time::Duration;
 
-pub const PANIC_HANDLE: bool = true;
-#[allow(unused)]
-pub const NO_FLAGS_MESSAGE: &str = "Instruction doesn't support 'F' register (FLAGS)";
-pub const DEBUG_ENABLED: bool = true;
-
-const LOG_PATH: &str = "data/logs.txt";
+use crate::logger::{self, Category};
 
 #[allow(unused)]
-pub fn handle_error(message: &str) -> () {
-    if PANIC_HANDLE {
-        println!("{}", message);
-    } else {
-        panic!("{}", message);
-    }
-}
+pub const NO_FLAGS_MESSAGE: &str = "Instruction doesn't support 'F' register (FLAGS)";
 
 pub fn panic_log(message: &str) -> ! {
-    debug_logs(&message);
+    logger::error(Category::General, message);
     panic!("{}", message);
 }
 
-pub fn reset_logs() -> () {
-    let log_exist = fs::exists(LOG_PATH).expect("Couldn't check existence of LOGS");
-    if !log_exist {
-        println!("Logs doesn't exist!");
-        return;
-    }
-    fs::remove_file(LOG_PATH).expect("Couldn't Remove Log File");
+// the frontends' and tools' messages, see logger.rs for the emulation's own
+pub fn log(log: &str) -> () {
+    logger::info(Category::General, log);
 }
 
-pub fn debug_logs(log: &str) -> () {
-    let mut file = OpenOptions::new().append(true).create(true).open(LOG_PATH).expect("Couldn't do logs");
 
-    let _ = file.write_fmt(format_args!("{}\n", log));
-}
 
-pub fn log(log: &str) -> () {
-    debug_logs(log);
-    println!("{log}");
+// decimal, or hex with a 0x/$ prefix
+pub fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('$')) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
 }
 
-
-
 pub fn delay(ms: u32) -> () {
     std::thread::sleep(std::time::Duration::from_millis(ms.into()));
 }
\ No newline at end of file
@@ -0,0 +1,255 @@
+use std::{cell::RefCell, fmt};
+
+use crate::utils::parse_number;
+
+/*
+    Memory watchpoints, checked by MemoryBus on every read and write while any are set.
+    The bus is `None` when there are none, so normal traffic only pays for that check.
+
+    Hits are queued here, and the debugger picks them up between two instructions.
+*/
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // a write of this specific value
+    WriteValue(u8),
+    // a write that changes what's stored
+    Change,
+}
+
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+    // only logs the access instead of stopping
+    pub log_only: bool,
+}
+
+pub struct WatchHit {
+    pub address: u16,
+    pub kind: WatchKind,
+    pub old_value: u8,
+    pub new_value: u8,
+    pub log_only: bool,
+}
+
+pub struct Watchpoints {
+    pub list: Vec<Watchpoint>,
+    // read_byte only borrows the bus, hence the RefCell
+    hits: RefCell<Vec<WatchHit>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            list: Vec::new(),
+            hits: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn on_read(&self, address: u16, value: u8) {
+        for watchpoint in self.matching(address) {
+            if watchpoint.kind == WatchKind::Read {
+                self.hit(watchpoint, address, value, value);
+            }
+        }
+    }
+
+    pub fn on_write(&self, address: u16, old_value: u8, new_value: u8) {
+        for watchpoint in self.matching(address) {
+            let triggered = match watchpoint.kind {
+                WatchKind::Read => false,
+                WatchKind::Write => true,
+                WatchKind::WriteValue(value) => new_value == value,
+                WatchKind::Change => new_value != old_value,
+            };
+
+            if triggered {
+                self.hit(watchpoint, address, old_value, new_value);
+            }
+        }
+    }
+
+    pub fn take_hits(&self) -> Vec<WatchHit> {
+        std::mem::take(&mut *self.hits.borrow_mut())
+    }
+
+    fn matching(&self, address: u16) -> impl Iterator<Item = &Watchpoint> {
+        self.list.iter().filter(move |watchpoint| (watchpoint.start..=watchpoint.end).contains(&address))
+    }
+
+    fn hit(&self, watchpoint: &Watchpoint, address: u16, old_value: u8, new_value: u8) {
+        self.hits.borrow_mut().push(WatchHit {
+            address,
+            kind: watchpoint.kind,
+            old_value,
+            new_value,
+            log_only: watchpoint.log_only,
+        });
+    }
+}
+
+impl Watchpoint {
+    /**
+        "<address>[-<end>] [r|w|c|=<value>] [log]", writes by default:
+            $FF40 c
+            $C000-$C0FF =$00 log
+     */
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut words = text.split_whitespace();
+        let usage = || String::from("Usage: watch <address>[-<end>] [r|w|c|=<value>] [log]");
+
+        let range = words.next().ok_or_else(usage)?;
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_address(start).ok_or_else(usage)?, parse_address(end).ok_or_else(usage)?),
+            None => {
+                let address = parse_address(range).ok_or_else(usage)?;
+                (address, address)
+            },
+        };
+
+        let mut watchpoint = Watchpoint { start: start.min(end), end: start.max(end), kind: WatchKind::Write, log_only: false };
+
+        for word in words {
+            match word {
+                "r" => watchpoint.kind = WatchKind::Read,
+                "w" => watchpoint.kind = WatchKind::Write,
+                "c" => watchpoint.kind = WatchKind::Change,
+                "log" => watchpoint.log_only = true,
+                _ => match word.strip_prefix('=').and_then(parse_number) {
+                    Some(value) if value <= 0xFF => watchpoint.kind = WatchKind::WriteValue(value as u8),
+                    _ => return Err(usage()),
+                },
+            }
+        }
+
+        Ok(watchpoint)
+    }
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    parse_number(text).filter(|address| *address <= 0xFFFF).map(|address| address as u16)
+}
+
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "${:04X}", self.start)?;
+        } else {
+            write!(f, "${:04X}-${:04X}", self.start, self.end)?;
+        }
+
+        match self.kind {
+            WatchKind::Read => write!(f, " read")?,
+            WatchKind::Write => write!(f, " write")?,
+            WatchKind::WriteValue(value) => write!(f, " write of ${value:02X}")?,
+            WatchKind::Change => write!(f, " change")?,
+        }
+
+        if self.log_only {
+            write!(f, " (log)")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            WatchKind::Read => write!(f, "read ${:02X} at ${:04X}", self.new_value, self.address),
+            _ => write!(f, "wrote ${:02X} at ${:04X} (was ${:02X})", self.new_value, self.address, self.old_value),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watching(text: &str) -> Watchpoints {
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.list.push(Watchpoint::parse(text).unwrap());
+        watchpoints
+    }
+
+    #[test]
+    fn parses_addresses_and_ranges() {
+        let watchpoint = Watchpoint::parse("$FF40").unwrap();
+        assert_eq!((watchpoint.start, watchpoint.end), (0xFF40, 0xFF40));
+        assert_eq!(watchpoint.kind, WatchKind::Write);
+        assert!(!watchpoint.log_only);
+
+        // backwards ranges are turned around
+        let watchpoint = Watchpoint::parse("$C0FF-$C000 r").unwrap();
+        assert_eq!((watchpoint.start, watchpoint.end), (0xC000, 0xC0FF));
+        assert_eq!(watchpoint.kind, WatchKind::Read);
+
+        assert!(Watchpoint::parse("").is_err());
+        assert!(Watchpoint::parse("$10000").is_err());
+        assert!(Watchpoint::parse("$C000-").is_err());
+    }
+
+    #[test]
+    fn parses_kinds_and_log() {
+        assert_eq!(Watchpoint::parse("$FF40 c").unwrap().kind, WatchKind::Change);
+        assert_eq!(Watchpoint::parse("$FF40 w").unwrap().kind, WatchKind::Write);
+
+        let watchpoint = Watchpoint::parse("$C000-$C0FF =$00 log").unwrap();
+        assert_eq!(watchpoint.kind, WatchKind::WriteValue(0x00));
+        assert!(watchpoint.log_only);
+        assert_eq!(watchpoint.to_string(), "$C000-$C0FF write of $00 (log)");
+
+        assert!(Watchpoint::parse("$C000 =$100").is_err());
+        assert!(Watchpoint::parse("$C000 =").is_err());
+        assert!(Watchpoint::parse("$C000 x").is_err());
+    }
+
+    #[test]
+    fn change_only_triggers_on_a_different_value() {
+        let watchpoints = watching("$C000 c");
+
+        watchpoints.on_write(0xC000, 0x12, 0x12);
+        assert!(watchpoints.take_hits().is_empty());
+
+        watchpoints.on_write(0xC000, 0x12, 0x34);
+        let hits = watchpoints.take_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].old_value, hits[0].new_value), (0x12, 0x34));
+    }
+
+    #[test]
+    fn write_value_only_triggers_on_that_value() {
+        let watchpoints = watching("$C000-$C00F =$42");
+
+        watchpoints.on_write(0xC005, 0x42, 0x41);
+        watchpoints.on_write(0xC010, 0x00, 0x42);
+        watchpoints.on_read(0xC005, 0x42);
+        assert!(watchpoints.take_hits().is_empty());
+
+        // the same value again still counts, unlike a change
+        watchpoints.on_write(0xC005, 0x42, 0x42);
+        let hits = watchpoints.take_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].address, 0xC005);
+        assert_eq!(hits[0].kind, WatchKind::WriteValue(0x42));
+    }
+
+    #[test]
+    fn reads_only_trigger_read_watchpoints() {
+        let watchpoints = watching("$FF44 r log");
+
+        watchpoints.on_write(0xFF44, 0x00, 0x90);
+        assert!(watchpoints.take_hits().is_empty());
+
+        watchpoints.on_read(0xFF44, 0x90);
+        let hits = watchpoints.take_hits();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].log_only);
+        assert_eq!(hits[0].to_string(), "read $90 at $FF44");
+    }
+}